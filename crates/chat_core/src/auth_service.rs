@@ -2,27 +2,294 @@
 // The AS is responsible for managing the authentication process and issuing tokens.
 
 use serde::{Deserialize, Serialize};
-use std::time::SystemTime;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
 
-#[derive(Debug, Serialize, Deserialize)]
-enum CredentialType {
+#[cfg(test)]
+pub(crate) mod certificates;
+mod x509;
+
+pub use x509::*;
+
+/// How long a registered credential is trusted.
+pub const CREDENTIAL_VALIDITY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CredentialType {
     Basic,
     X509,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-enum SignatureScheme {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SignatureScheme {
     Ed25519,
     EcdsaSecp256r1,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Credential {
-    credential_type: CredentialType,
-    identity: String,
-    public_key: Vec<u8>,
-    signature_scheme: SignatureScheme,
-    valid_from: SystemTime,
-    valid_until: Option<SystemTime>,
-    revoked: bool,
+impl SignatureScheme {
+    /// Length of a public key of the scheme, uncompressed for ECDSA.
+    pub fn public_key_len(&self) -> usize {
+        match self {
+            SignatureScheme::Ed25519 => 32,
+            SignatureScheme::EcdsaSecp256r1 => 65,
+        }
+    }
+}
+
+/// The record the AS keeps for a registered identity: the signature key
+/// its leaves must carry, and the window in which it is trusted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credential {
+    pub credential_type: CredentialType,
+    pub identity: String,
+    pub public_key: Vec<u8>,
+    pub signature_scheme: SignatureScheme,
+    pub valid_from: SystemTime,
+    pub valid_until: Option<SystemTime>,
+    pub revoked: bool,
+    // DER encoded, leaf first, for X.509 credentials
+    #[serde(default)]
+    pub certificate_chain: Vec<Vec<u8>>,
+}
+
+impl Credential {
+    /// A basic credential, valid from now for `validity`.
+    pub fn basic(
+        identity: String,
+        public_key: Vec<u8>,
+        signature_scheme: SignatureScheme,
+        validity: Duration,
+    ) -> Self {
+        let valid_from = SystemTime::now();
+
+        Self {
+            credential_type: CredentialType::Basic,
+            identity,
+            public_key,
+            signature_scheme,
+            valid_from,
+            valid_until: Some(valid_from + validity),
+            revoked: false,
+            certificate_chain: Vec::new(),
+        }
+    }
+
+    /// An X.509 credential for the key certified by a chain ending at
+    /// `trust_anchor`, valid as long as its leaf certificate.
+    pub fn x509(chain: Vec<Vec<u8>>, trust_anchor: &TrustAnchor) -> Result<Self, CredentialError> {
+        let certified = trust_anchor.verify(&chain, SystemTime::now())?;
+
+        Ok(Self {
+            credential_type: CredentialType::X509,
+            identity: certified.identity,
+            public_key: certified.public_key,
+            signature_scheme: certified.signature_scheme,
+            valid_from: certified.not_before,
+            valid_until: Some(certified.not_after),
+            revoked: false,
+            certificate_chain: chain,
+        })
+    }
+
+    /// Whether the credential is trusted at `time`.
+    pub fn is_valid_at(&self, time: SystemTime) -> bool {
+        !self.revoked
+            && self.valid_from <= time
+            && self
+                .valid_until
+                .is_none_or(|valid_until| time < valid_until)
+    }
+
+    /// Whether a leaf claiming `identity` with `public_key` as signature key
+    /// is the one registered.
+    pub fn belongs_to(&self, identity: &[u8], public_key: &[u8]) -> bool {
+        self.identity.as_bytes() == identity && self.public_key == public_key
+    }
+
+    /// Checks a leaf against the credential registered for its identity. The
+    /// chain of an X.509 credential must still be valid, up to `trust_anchor`.
+    pub fn check(
+        &self,
+        public_key: &[u8],
+        time: SystemTime,
+        trust_anchor: Option<&TrustAnchor>,
+    ) -> Result<(), CredentialError> {
+        if self.public_key != public_key {
+            return Err(CredentialError::KeyMismatch(self.identity.clone()));
+        }
+        if self.revoked {
+            return Err(CredentialError::Revoked(self.identity.clone()));
+        }
+        if self.credential_type == CredentialType::X509 {
+            trust_anchor
+                .ok_or_else(|| CredentialError::InvalidChain("no trust anchor".to_string()))?
+                .verify(&self.certificate_chain, time)?;
+        }
+        if !self.is_valid_at(time) {
+            return Err(CredentialError::Expired(self.identity.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CredentialError {
+    #[error("`{0}` is not registered with the AS")]
+    Unknown(String),
+    #[error("`{0}` signs with a key not registered with the AS")]
+    KeyMismatch(String),
+    #[error("The credential of `{0}` is not valid at this time")]
+    Expired(String),
+    #[error("The credential of `{0}` is revoked")]
+    Revoked(String),
+    #[error("Invalid certificate chain: {0}")]
+    InvalidChain(String),
+}
+
+/// Decides whether the credential of a leaf joining or updating in a group
+/// is trusted, consulted by the group helpers of [`crate::ext_mls`].
+pub trait CredentialValidator: fmt::Debug + Send + Sync {
+    fn validate(&self, identity: &[u8], signature_key: &[u8]) -> Result<(), CredentialError>;
+
+    /// The CA the chains of X.509 leaves must end at, none are trusted
+    /// without one.
+    fn trust_anchor(&self) -> Option<&TrustAnchor> {
+        None
+    }
+}
+
+/// Trusts every basic credential, for groups without an AS.
+#[derive(Debug, Default)]
+pub struct AcceptAll;
+
+impl CredentialValidator for AcceptAll {
+    fn validate(&self, _identity: &[u8], _signature_key: &[u8]) -> Result<(), CredentialError> {
+        Ok(())
+    }
+}
+
+/// Told when the AS revokes the credentials of an identity, e.g. the
+/// delivery service so the leaves carrying them are removed from their groups.
+pub trait RevocationListener: fmt::Debug + Send + Sync {
+    fn revoked(&self, identity: &str);
+}
+
+/// In-process stand-in for the AS registry. An identity has a credential
+/// for each signature scheme, a device has a key per ciphersuite.
+#[derive(Debug, Default)]
+pub struct Registry {
+    credentials: RwLock<HashMap<String, Vec<Credential>>>,
+    listeners: RwLock<Vec<Arc<dyn RevocationListener>>>,
+    // The CA the chains of X.509 credentials must end at
+    trust_anchor: Option<TrustAnchor>,
+    // Only the devices of users authenticated by the AS register
+    sign_in_required: bool,
+}
+
+impl Registry {
+    pub fn with_trust_anchor(mut self, trust_anchor: TrustAnchor) -> Self {
+        self.trust_anchor = Some(trust_anchor);
+        self
+    }
+
+    /// Takes the credentials of signed in devices only, where an AS
+    /// authenticates the users. Without one any device picks its user.
+    pub fn signed_in_only(mut self) -> Self {
+        self.sign_in_required = true;
+        self
+    }
+
+    pub fn requires_sign_in(&self) -> bool {
+        self.sign_in_required
+    }
+
+    /// Registers a credential, replacing the one of the same identity and
+    /// signature scheme.
+    pub fn register(&self, credential: Credential) {
+        let mut credentials = self.credentials.write().unwrap();
+        let registered = credentials.entry(credential.identity.clone()).or_default();
+        registered.retain(|registered| registered.signature_scheme != credential.signature_scheme);
+        registered.push(credential);
+    }
+
+    pub fn get(&self, identity: &str, signature_scheme: SignatureScheme) -> Option<Credential> {
+        self.credentials
+            .read()
+            .unwrap()
+            .get(identity)?
+            .iter()
+            .find(|credential| credential.signature_scheme == signature_scheme)
+            .cloned()
+    }
+
+    /// Replaces the credential of a registered identity with one for a new
+    /// key of the same scheme, even past its expiry but not once revoked.
+    /// Returns the previous one.
+    pub fn rotate(&self, credential: Credential) -> Result<Credential, CredentialError> {
+        let mut credentials = self.credentials.write().unwrap();
+        let previous = credentials
+            .get_mut(&credential.identity)
+            .and_then(|registered| {
+                registered
+                    .iter_mut()
+                    .find(|registered| registered.signature_scheme == credential.signature_scheme)
+            })
+            .ok_or_else(|| CredentialError::Unknown(credential.identity.clone()))?;
+        if previous.revoked {
+            return Err(CredentialError::Revoked(credential.identity));
+        }
+
+        Ok(std::mem::replace(previous, credential))
+    }
+
+    pub fn subscribe(&self, listener: Arc<dyn RevocationListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Revokes the credentials of an identity and tells the listeners, a new
+    /// credential has to be registered for the identity to be trusted again.
+    pub fn revoke(&self, identity: &str) -> Result<Vec<Credential>, CredentialError> {
+        let revoked = {
+            let mut credentials = self.credentials.write().unwrap();
+            let registered = credentials
+                .get_mut(identity)
+                .ok_or_else(|| CredentialError::Unknown(identity.to_string()))?;
+            for credential in registered.iter_mut() {
+                credential.revoked = true;
+            }
+            registered.clone()
+        };
+        for listener in self.listeners.read().unwrap().iter() {
+            listener.revoked(identity);
+        }
+
+        Ok(revoked)
+    }
+}
+
+impl CredentialValidator for Registry {
+    fn validate(&self, identity: &[u8], signature_key: &[u8]) -> Result<(), CredentialError> {
+        let identity = String::from_utf8_lossy(identity);
+        let credentials = self.credentials.read().unwrap();
+        let registered = credentials
+            .get(identity.as_ref())
+            .ok_or_else(|| CredentialError::Unknown(identity.to_string()))?;
+        // The credential of the key's scheme, any one to report a mismatch
+        let credential = registered
+            .iter()
+            .find(|credential| credential.public_key == signature_key)
+            .or(registered.first())
+            .ok_or_else(|| CredentialError::Unknown(identity.to_string()))?;
+
+        credential.check(signature_key, SystemTime::now(), self.trust_anchor.as_ref())
+    }
+
+    fn trust_anchor(&self) -> Option<&TrustAnchor> {
+        self.trust_anchor.as_ref()
+    }
 }
@@ -0,0 +1,178 @@
+use std::time::Duration;
+
+use rcgen::{
+    date_time_ymd, BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair,
+    PublicKeyData, SignatureAlgorithm, PKCS_ECDSA_P256_SHA256, PKCS_ED25519,
+};
+
+use super::{
+    Credential, CredentialError, CredentialType, CredentialValidator, Registry, SignatureScheme,
+    TrustAnchor,
+};
+
+/// A certificate for `name` and its key, self-signed without `issuer`.
+fn certificate(
+    name: &str,
+    ca: bool,
+    key_pair: &KeyPair,
+    issuer: Option<(&Certificate, &KeyPair)>,
+) -> Certificate {
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params.distinguished_name.push(DnType::CommonName, name);
+    if ca {
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    }
+    match issuer {
+        Some((issuer, issuer_key)) => params.signed_by(key_pair, issuer, issuer_key).unwrap(),
+        None => params.self_signed(key_pair).unwrap(),
+    }
+}
+
+pub(crate) fn root(name: &str) -> (Certificate, KeyPair, TrustAnchor) {
+    let key_pair = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+    let root = certificate(name, true, &key_pair, None);
+    let anchor = TrustAnchor::from_der(root.der().to_vec()).unwrap();
+
+    (root, key_pair, anchor)
+}
+
+/// An Ed25519 public key, as a member's signer holds it.
+struct Ed25519Key<'a>(&'a [u8]);
+
+impl PublicKeyData for Ed25519Key<'_> {
+    fn der_bytes(&self) -> &[u8] {
+        self.0
+    }
+
+    fn algorithm(&self) -> &SignatureAlgorithm {
+        &PKCS_ED25519
+    }
+}
+
+/// A DER leaf certificate of the Ed25519 `public_key` of `identity`, issued
+/// by `issuer`.
+pub(crate) fn issue(
+    identity: &str,
+    public_key: &[u8],
+    issuer: &Certificate,
+    issuer_key: &KeyPair,
+) -> Vec<u8> {
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params.distinguished_name.push(DnType::CommonName, identity);
+    params
+        .signed_by(&Ed25519Key(public_key), issuer, issuer_key)
+        .unwrap()
+        .der()
+        .to_vec()
+}
+
+#[test]
+fn chain_through_an_intermediate_is_valid() {
+    let (root, root_key, anchor) = root("Root CA");
+    let intermediate_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+    let intermediate = certificate(
+        "Intermediate CA",
+        true,
+        &intermediate_key,
+        Some((&root, &root_key)),
+    );
+    let leaf_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+    let leaf = certificate(
+        "alice:0",
+        false,
+        &leaf_key,
+        Some((&intermediate, &intermediate_key)),
+    );
+
+    let chain = vec![leaf.der().to_vec(), intermediate.der().to_vec()];
+    let credential = Credential::x509(chain.clone(), &anchor).unwrap();
+    assert_eq!(credential.credential_type, CredentialType::X509);
+    assert_eq!(credential.identity, "alice:0");
+    assert_eq!(credential.public_key, leaf_key.public_key_raw());
+    assert_eq!(credential.signature_scheme, SignatureScheme::EcdsaSecp256r1);
+
+    // Ending the chain with the anchor changes nothing.
+    let mut with_root = chain;
+    with_root.push(root.der().to_vec());
+    assert_eq!(
+        Credential::x509(with_root, &anchor).unwrap().identity,
+        "alice:0"
+    );
+}
+
+#[test]
+fn chain_of_another_ca_is_rejected() {
+    let (_, _, anchor) = root("Root CA");
+    let (other, other_key, _) = root("Other CA");
+    let leaf_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+    let leaf = certificate("mallory:0", false, &leaf_key, Some((&other, &other_key)));
+
+    assert!(matches!(
+        Credential::x509(vec![leaf.der().to_vec()], &anchor),
+        Err(CredentialError::InvalidChain(_))
+    ));
+    assert!(matches!(
+        Credential::x509(Vec::new(), &anchor),
+        Err(CredentialError::InvalidChain(_))
+    ));
+
+    // A leaf can not issue certificates.
+    let (root, root_key, anchor) = root("Root CA");
+    let leaf = certificate("alice:0", false, &leaf_key, Some((&root, &root_key)));
+    let forged_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+    let forged = certificate("bob:0", false, &forged_key, Some((&leaf, &leaf_key)));
+    assert!(matches!(
+        Credential::x509(vec![forged.der().to_vec(), leaf.der().to_vec()], &anchor),
+        Err(CredentialError::InvalidChain(_))
+    ));
+}
+
+#[test]
+fn expired_certificate_is_rejected() {
+    let (root, root_key, anchor) = root("Root CA");
+    let leaf_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+    let mut params = CertificateParams::new(Vec::new()).unwrap();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "alice:0");
+    params.not_before = date_time_ymd(2000, 1, 1);
+    params.not_after = date_time_ymd(2001, 1, 1);
+    let expired = params.signed_by(&leaf_key, &root, &root_key).unwrap();
+
+    assert_eq!(
+        Credential::x509(vec![expired.der().to_vec()], &anchor),
+        Err(CredentialError::Expired("alice:0".to_string()))
+    );
+
+    // A valid credential expires with its certificate.
+    let leaf = certificate("alice:0", false, &leaf_key, Some((&root, &root_key)));
+    let credential = Credential::x509(vec![leaf.der().to_vec()], &anchor).unwrap();
+    let later = credential.valid_until.unwrap() + Duration::from_secs(1);
+    assert_eq!(
+        credential.check(leaf_key.public_key_raw(), later, Some(&anchor)),
+        Err(CredentialError::Expired("alice:0".to_string()))
+    );
+}
+
+#[test]
+fn registry_validates_x509_credentials_against_its_anchor() {
+    let (root, root_key, anchor) = root("Root CA");
+    let leaf_key = KeyPair::generate_for(&PKCS_ED25519).unwrap();
+    let leaf = certificate("alice:0", false, &leaf_key, Some((&root, &root_key)));
+    let credential = Credential::x509(vec![leaf.der().to_vec()], &anchor).unwrap();
+
+    let registry = Registry::default().with_trust_anchor(anchor);
+    registry.register(credential.clone());
+    assert_eq!(
+        registry.validate(b"alice:0", leaf_key.public_key_raw()),
+        Ok(())
+    );
+
+    // Without an anchor, no chain is trusted.
+    let registry = Registry::default();
+    registry.register(credential);
+    assert!(matches!(
+        registry.validate(b"alice:0", leaf_key.public_key_raw()),
+        Err(CredentialError::InvalidChain(_))
+    ));
+}
@@ -0,0 +1,163 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use x509_parser::{
+    certificate::X509Certificate,
+    oid_registry::{OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_SIG_ED25519},
+    prelude::FromDer,
+    time::ASN1Time,
+};
+
+use super::{CredentialError, SignatureScheme};
+
+/// The CA certificate the chains of X.509 credentials must end at, e.g. the
+/// local CA of the AS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrustAnchor {
+    certificate: Vec<u8>,
+}
+
+/// What the leaf certificate of a valid chain certifies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertifiedKey {
+    pub identity: String,
+    pub public_key: Vec<u8>,
+    pub signature_scheme: SignatureScheme,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+impl TrustAnchor {
+    /// A trust anchor from the DER encoding of a CA certificate.
+    pub fn from_der(certificate: Vec<u8>) -> Result<Self, CredentialError> {
+        if !parse(&certificate)?.is_ca() {
+            return Err(invalid_chain("the trust anchor is not a CA certificate"));
+        }
+
+        Ok(Self { certificate })
+    }
+
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// Checks a DER encoded chain, leaf first: every certificate is valid at
+    /// `time` and signed by the next one, the last one by the anchor. The
+    /// chain may end with the anchor itself.
+    pub fn verify(
+        &self,
+        chain: &[Vec<u8>],
+        time: SystemTime,
+    ) -> Result<CertifiedKey, CredentialError> {
+        let chain = match chain.split_last() {
+            Some((last, rest)) if *last == self.certificate => rest,
+            _ => chain,
+        };
+        let anchor = parse(&self.certificate)?;
+        let certificates = chain
+            .iter()
+            .map(|der| parse(der))
+            .collect::<Result<Vec<_>, _>>()?;
+        let leaf = certificates
+            .first()
+            .ok_or_else(|| invalid_chain("the chain is empty"))?;
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| invalid_chain("the time is before the Unix epoch"))?
+            .as_secs();
+        let time = ASN1Time::from_timestamp(seconds as i64)
+            .map_err(|err| invalid_chain(err.to_string()))?;
+
+        for (index, certificate) in certificates.iter().enumerate() {
+            let issuer = certificates.get(index + 1).unwrap_or(&anchor);
+            if !certificate.validity().is_valid_at(time) {
+                return Err(CredentialError::Expired(common_name(certificate)));
+            }
+            if index > 0 && !certificate.is_ca() {
+                return Err(invalid_chain(format!(
+                    "`{}` is not a CA",
+                    common_name(certificate)
+                )));
+            }
+            if certificate.issuer() != issuer.subject() {
+                return Err(invalid_chain(format!(
+                    "`{}` is not issued by `{}`",
+                    common_name(certificate),
+                    common_name(issuer)
+                )));
+            }
+            certificate
+                .verify_signature(Some(issuer.public_key()))
+                .map_err(|_| {
+                    invalid_chain(format!(
+                        "`{}` is not signed by `{}`",
+                        common_name(certificate),
+                        common_name(issuer)
+                    ))
+                })?;
+        }
+        if !anchor.validity().is_valid_at(time) {
+            return Err(CredentialError::Expired(common_name(&anchor)));
+        }
+
+        certified(leaf)
+    }
+}
+
+impl CertifiedKey {
+    /// What a DER encoded leaf certificate certifies, its chain unchecked,
+    /// see [`TrustAnchor::verify`].
+    pub fn read(leaf: &[u8]) -> Result<Self, CredentialError> {
+        certified(&parse(leaf)?)
+    }
+}
+
+fn certified(leaf: &X509Certificate) -> Result<CertifiedKey, CredentialError> {
+    let public_key = leaf.public_key();
+    let algorithm = &public_key.algorithm;
+    let curve = algorithm
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.as_oid().ok());
+    let signature_scheme = if algorithm.algorithm == OID_SIG_ED25519 {
+        SignatureScheme::Ed25519
+    } else if algorithm.algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY
+        && curve.as_ref() == Some(&OID_EC_P256)
+    {
+        SignatureScheme::EcdsaSecp256r1
+    } else {
+        return Err(invalid_chain("the leaf certifies an unsupported key"));
+    };
+
+    Ok(CertifiedKey {
+        identity: common_name(leaf),
+        public_key: public_key.subject_public_key.data.to_vec(),
+        signature_scheme,
+        not_before: system_time(leaf.validity().not_before),
+        not_after: system_time(leaf.validity().not_after),
+    })
+}
+
+fn parse(der: &[u8]) -> Result<X509Certificate<'_>, CredentialError> {
+    X509Certificate::from_der(der)
+        .map(|(_, certificate)| certificate)
+        .map_err(|err| invalid_chain(err.to_string()))
+}
+
+/// The identity a certificate is issued to.
+fn common_name(certificate: &X509Certificate) -> String {
+    certificate
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|name| name.as_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn system_time(time: ASN1Time) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(time.timestamp().max(0) as u64)
+}
+
+fn invalid_chain(reason: impl Into<String>) -> CredentialError {
+    CredentialError::InvalidChain(reason.into())
+}
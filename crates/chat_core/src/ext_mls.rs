@@ -1,6 +1,9 @@
-// mod group_chat;
+mod errors;
+#[cfg(test)]
+mod group_chat;
 mod helpers;
 mod memory_provider;
 
+pub use errors::*;
 pub use helpers::*;
 pub use memory_provider::*;
@@ -0,0 +1,70 @@
+use openmls::prelude::{
+    tls_codec, AddMembersError, Ciphersuite, CommitToPendingProposalsError, CreateMessageError,
+    ExportGroupInfoError, ExternalCommitError, KeyPackageNewError, KeyPackageVerifyError,
+    LeaveGroupError, MergeCommitError, MergePendingCommitError, NewGroupError, ProcessMessageError,
+    ProposeAddMemberError, ProposeRemoveMemberError, ProposeSelfUpdateError, RemoveMembersError,
+    SelfUpdateError, WelcomeError,
+};
+use openmls_rust_crypto::MemoryStorageError;
+
+use crate::auth_service::CredentialError;
+
+/// Errors returned by the group helpers.
+///
+/// Most variants wrap the error of the underlying OpenMLS operation, the
+/// storage backend is always the in-memory one used by [`MemoryProvider`].
+///
+/// [`MemoryProvider`]: super::MemoryProvider
+#[derive(Debug, thiserror::Error)]
+pub enum ChatError {
+    #[error("No member with identity `{0}` in the group")]
+    UnknownIdentity(String),
+    #[error("Expected a {0} message")]
+    UnexpectedMessage(&'static str),
+    #[error("Proposals are queued, a commit would carry them along")]
+    PendingProposals,
+    #[error("Unsupported ciphersuite {0:?}")]
+    UnsupportedCiphersuite(Ciphersuite),
+    #[error(transparent)]
+    InvalidCredential(#[from] CredentialError),
+    #[error(transparent)]
+    Codec(#[from] tls_codec::Error),
+    #[error(transparent)]
+    NewGroup(#[from] NewGroupError<MemoryStorageError>),
+    #[error(transparent)]
+    NewKeyPackage(#[from] KeyPackageNewError),
+    #[error(transparent)]
+    VerifyKeyPackage(#[from] KeyPackageVerifyError),
+    #[error(transparent)]
+    Welcome(#[from] WelcomeError<MemoryStorageError>),
+    #[error(transparent)]
+    ExportGroupInfo(#[from] ExportGroupInfoError),
+    #[error(transparent)]
+    ExternalCommit(#[from] ExternalCommitError<MemoryStorageError>),
+    #[error(transparent)]
+    AddMembers(#[from] AddMembersError<MemoryStorageError>),
+    #[error(transparent)]
+    RemoveMembers(#[from] RemoveMembersError<MemoryStorageError>),
+    #[error(transparent)]
+    LeaveGroup(#[from] LeaveGroupError<MemoryStorageError>),
+    #[error(transparent)]
+    SelfUpdate(#[from] SelfUpdateError<MemoryStorageError>),
+    #[error(transparent)]
+    ProposeAddMember(#[from] ProposeAddMemberError<MemoryStorageError>),
+    #[error(transparent)]
+    ProposeRemoveMember(#[from] ProposeRemoveMemberError<MemoryStorageError>),
+    #[error(transparent)]
+    ProposeSelfUpdate(#[from] ProposeSelfUpdateError<MemoryStorageError>),
+    #[error(transparent)]
+    CommitToPendingProposals(#[from] CommitToPendingProposalsError<MemoryStorageError>),
+    #[error(transparent)]
+    MergePendingCommit(#[from] MergePendingCommitError<MemoryStorageError>),
+    #[error(transparent)]
+    MergeCommit(#[from] MergeCommitError<MemoryStorageError>),
+    #[error(transparent)]
+    CreateMessage(#[from] CreateMessageError),
+    #[error(transparent)]
+    ProcessMessage(#[from] ProcessMessageError),
+    #[error(transparent)]
+    Storage(#[from] MemoryStorageError),
+}
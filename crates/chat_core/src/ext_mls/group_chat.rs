@@ -1,6 +1,36 @@
-use openmls::group::GroupId;
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{
+        tls_codec::Deserialize as _, tls_codec::Serialize as _, CredentialType, LeafNodeIndex,
+        MlsMessageIn, MlsMessageOut, ProcessedMessageContent,
+    },
+};
+use openmls_traits::OpenMlsProvider;
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
-use crate::helpers::setup_group;
+use super::{
+    errors::ChatError,
+    helpers::{
+        add_members, certificate_chain, commit_pending_proposals, export_group_info, find_member,
+        group_info_ciphersuite, identity_of, join_by_external_commit, join_group, leave_group,
+        merge_commit, propose_add, propose_remove, propose_update, receive_message, remove_leaves,
+        remove_members, self_update, send_message, setup_group, setup_group_with_ciphersuite,
+        user_of, welcome_ciphersuite, Member, Received, User, SUPPORTED_CIPHERSUITES,
+    },
+};
+use crate::auth_service::{
+    certificates, AcceptAll, Credential, CredentialError, CredentialValidator, Registry,
+    RevocationListener, CREDENTIAL_VALIDITY,
+};
+
+/// Round-trips a message through its wire encoding, as the Delivery Service does.
+fn deliver(message: &MlsMessageOut) -> MlsMessageIn {
+    let bytes = message.tls_serialize_detached().unwrap();
+    MlsMessageIn::tls_deserialize_exact(bytes).unwrap()
+}
 
 #[test]
 fn welcome_based_flow() {
@@ -26,3 +56,675 @@ fn welcome_based_flow() {
         );
     }
 }
+
+#[test]
+fn remove_members_flow() {
+    let mut members = setup_group("remove_flow", 3);
+    let removed_identity = members[2].1.identity().to_vec();
+
+    let (creator, others) = members.split_first_mut().unwrap();
+    let commit = remove_members(&mut creator.0, &creator.1, &[&removed_identity]).unwrap();
+    merge_commit(&mut creator.0, &creator.1).unwrap();
+    assert_eq!(creator.0.members().count(), 2);
+
+    let (remaining, removed) = others.split_at_mut(1);
+    let (remaining_group, remaining_member) = &mut remaining[0];
+    let (removed_group, removed_member) = &mut removed[0];
+
+    let received = receive_message(remaining_group, remaining_member, deliver(&commit)).unwrap();
+    assert_eq!(received, Received::Commit);
+    assert_eq!(remaining_group.epoch(), creator.0.epoch());
+
+    let message = send_message(&mut creator.0, &creator.1, b"after removal").unwrap();
+    let received = receive_message(remaining_group, remaining_member, deliver(&message)).unwrap();
+    assert_eq!(
+        received,
+        Received::Application {
+            sender: creator.1.identity().to_vec(),
+            content: b"after removal".to_vec(),
+        }
+    );
+
+    // Still on the previous epoch, the removed member has no key for the new one.
+    assert!(
+        receive_message(removed_group, removed_member, deliver(&message)).is_err(),
+        "Removed member decrypted a message from the next epoch"
+    );
+
+    receive_message(removed_group, removed_member, deliver(&commit)).unwrap();
+    assert!(!removed_group.is_active());
+    assert!(receive_message(removed_group, removed_member, deliver(&message)).is_err());
+}
+
+#[test]
+fn leave_group_flow() {
+    let mut members = setup_group("leave_flow", 3);
+
+    let (leaver, others) = members.split_last_mut().unwrap();
+    let proposal = leave_group(&mut leaver.0, &leaver.1).unwrap();
+
+    for (group, member) in others.iter_mut() {
+        let received = receive_message(group, member, deliver(&proposal)).unwrap();
+        assert_eq!(
+            received,
+            Received::Proposal {
+                sender: leaver.1.identity().to_vec(),
+            }
+        );
+    }
+
+    // Any other member can commit the leave proposal, here it is not the creator.
+    let (creator, committer) = others.split_first_mut().unwrap();
+    let (committer_group, committer_member) = &mut committer[0];
+    let (commit, welcome) = commit_pending_proposals(committer_group, committer_member).unwrap();
+    assert!(welcome.is_none());
+    merge_commit(committer_group, committer_member).unwrap();
+    assert_eq!(committer_group.members().count(), 2);
+
+    receive_message(&mut creator.0, &creator.1, deliver(&commit)).unwrap();
+    receive_message(&mut leaver.0, &leaver.1, deliver(&commit)).unwrap();
+    assert!(!leaver.0.is_active());
+
+    let message = send_message(&mut creator.0, &creator.1, b"after leave").unwrap();
+    assert!(receive_message(committer_group, committer_member, deliver(&message)).is_ok());
+    assert!(receive_message(&mut leaver.0, &leaver.1, deliver(&message)).is_err());
+}
+
+#[test]
+fn self_update_heals_compromise() {
+    let mut members = setup_group("update_flow", 3);
+
+    // The attacker leaks the whole state of member 1, epoch secrets included.
+    let leaked_provider = members[1].1.provider.snapshot();
+    let mut leaked_group = MlsGroup::load(leaked_provider.storage(), members[1].0.group_id())
+        .unwrap()
+        .expect("Group state is in the leaked storage");
+
+    let (creator, others) = members.split_first_mut().unwrap();
+    let message = send_message(&mut creator.0, &creator.1, b"before update").unwrap();
+    let processed = leaked_group
+        .process_message(
+            &leaked_provider,
+            deliver(&message).try_into_protocol_message().unwrap(),
+        )
+        .expect("The leaked epoch secret decrypts the current epoch");
+    assert!(matches!(
+        processed.into_content(),
+        ProcessedMessageContent::ApplicationMessage(_)
+    ));
+
+    let (updated, rest) = others.split_first_mut().unwrap();
+    let commit = self_update(&mut updated.0, &updated.1).unwrap();
+    merge_commit(&mut updated.0, &updated.1).unwrap();
+    receive_message(&mut creator.0, &creator.1, deliver(&commit)).unwrap();
+    for (group, member) in rest.iter_mut() {
+        receive_message(group, member, deliver(&commit)).unwrap();
+    }
+    assert_eq!(updated.0.epoch(), creator.0.epoch());
+
+    let message = send_message(&mut creator.0, &creator.1, b"after update").unwrap();
+    assert!(receive_message(&mut updated.0, &updated.1, deliver(&message)).is_ok());
+
+    // Even seeing the whole traffic, the leaked state can not follow the update.
+    assert!(leaked_group
+        .process_message(
+            &leaked_provider,
+            deliver(&commit).try_into_protocol_message().unwrap()
+        )
+        .is_err());
+    assert!(leaked_group
+        .process_message(
+            &leaked_provider,
+            deliver(&message).try_into_protocol_message().unwrap()
+        )
+        .is_err());
+}
+
+#[test]
+fn external_rejoin_after_losing_state() {
+    let mut members = setup_group("rejoin_flow", 3);
+    let group_info = export_group_info(&members[0].0, &members[0].1)
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+
+    // Member_2 lost its state: same identity, new keys, no group.
+    let (_, lost_member) = members.pop().unwrap();
+    let stale_index = members[0].0.members().last().unwrap().index;
+    let rejoined = Member::new(
+        &lost_member.user_id,
+        lost_member.device_id.clone(),
+        GroupId::from_slice(&[]),
+    );
+    assert_eq!(rejoined.identity(), lost_member.identity());
+
+    let (mut rejoined_group, commit) = join_by_external_commit(&rejoined, &group_info).unwrap();
+    merge_commit(&mut rejoined_group, &rejoined).unwrap();
+
+    for (group, member) in members.iter_mut() {
+        let received = receive_message(group, member, deliver(&commit)).unwrap();
+        assert_eq!(
+            received,
+            Received::ExternalJoin {
+                sender: rejoined.identity().to_vec(),
+                stale: vec![stale_index],
+            }
+        );
+    }
+    assert_eq!(rejoined_group.epoch(), members[0].0.epoch());
+
+    let (creator, others) = members.split_first_mut().unwrap();
+    let commit = remove_leaves(&mut creator.0, &creator.1, &[stale_index]).unwrap();
+    merge_commit(&mut creator.0, &creator.1).unwrap();
+    receive_message(&mut others[0].0, &others[0].1, deliver(&commit)).unwrap();
+    receive_message(&mut rejoined_group, &rejoined, deliver(&commit)).unwrap();
+    assert_eq!(rejoined_group.members().count(), 3);
+
+    let message = send_message(&mut creator.0, &creator.1, b"welcome back").unwrap();
+    let received = receive_message(&mut rejoined_group, &rejoined, deliver(&message)).unwrap();
+    assert_eq!(
+        received,
+        Received::Application {
+            sender: creator.1.identity().to_vec(),
+            content: b"welcome back".to_vec(),
+        }
+    );
+}
+
+#[test]
+fn proposal_queue_flow() {
+    let mut members = setup_group("proposal_flow", 3);
+    let newcomer = Member::new("Newcomer", "0".to_string(), GroupId::from_slice(&[]));
+    let removed_identity = members[2].1.identity().to_vec();
+
+    // Proposals from two members, nobody commits yet. An update of the
+    // removed member would be dropped by the commit.
+    let key_package = newcomer.key_package().unwrap();
+    let (creator_group, creator) = &mut members[0];
+    let add = propose_add(creator_group, creator, &key_package).unwrap();
+    let (proposer_group, proposer) = &mut members[1];
+    let remove = propose_remove(proposer_group, proposer, &removed_identity)
+        .unwrap()
+        .remove(0);
+    let update = propose_update(proposer_group, proposer).unwrap();
+
+    for (proposer, proposal) in [(0, &add), (1, &remove), (1, &update)] {
+        for (index, (group, member)) in members.iter_mut().enumerate() {
+            if index == proposer {
+                continue;
+            }
+            let received = receive_message(group, member, deliver(proposal)).unwrap();
+            assert!(matches!(received, Received::Proposal { .. }));
+        }
+    }
+    assert!(members
+        .iter()
+        .all(|(group, _)| group.pending_proposals().count() == 3));
+
+    // Any member commits the whole queue, here the proposer of the add.
+    let (removed, others) = members.split_last_mut().unwrap();
+    let (creator, rest) = others.split_first_mut().unwrap();
+    let (commit, welcome) = commit_pending_proposals(&mut creator.0, &creator.1).unwrap();
+    merge_commit(&mut creator.0, &creator.1).unwrap();
+    let welcome = welcome.expect("The queue adds a member");
+    assert_eq!(creator.0.pending_proposals().count(), 0);
+    assert_eq!(creator.0.members().count(), 3);
+
+    receive_message(&mut rest[0].0, &rest[0].1, deliver(&commit)).unwrap();
+    receive_message(&mut removed.0, &removed.1, deliver(&commit)).unwrap();
+    assert!(!removed.0.is_active());
+
+    let mut newcomer_group =
+        join_group(&newcomer, &welcome.tls_serialize_detached().unwrap()).unwrap();
+    let message = send_message(&mut rest[0].0, &rest[0].1, b"queue committed").unwrap();
+    for (group, member) in [
+        (&mut creator.0, &creator.1),
+        (&mut newcomer_group, &newcomer),
+    ] {
+        let received = receive_message(group, member, deliver(&message)).unwrap();
+        assert_eq!(
+            received,
+            Received::Application {
+                sender: rest[0].1.identity().to_vec(),
+                content: b"queue committed".to_vec(),
+            }
+        );
+    }
+}
+
+#[test]
+fn queued_proposals_are_committed_before_other_commits() {
+    let mut members = setup_group("queued_first", 2);
+    let (proposer_group, proposer) = &mut members[1];
+    let update = propose_update(proposer_group, proposer).unwrap();
+    let (group, member) = &mut members[0];
+    receive_message(group, member, deliver(&update)).unwrap();
+
+    // OpenMLS would commit the queue along, without telling the caller.
+    let updated = self_update(group, member);
+    let removed = remove_leaves(group, member, &[LeafNodeIndex::new(1)]);
+
+    assert!(matches!(updated, Err(ChatError::PendingProposals)));
+    assert!(matches!(removed, Err(ChatError::PendingProposals)));
+    assert!(group.pending_commit().is_none());
+    commit_pending_proposals(group, member).unwrap();
+    merge_commit(group, member).unwrap();
+    assert!(self_update(group, member).is_ok());
+}
+
+#[test]
+fn multi_device_user_flow() {
+    let mut members = setup_group("devices_flow", 2);
+    let bob = User::new("bob", 2);
+    assert_ne!(bob.devices[0].identity(), bob.devices[1].identity());
+
+    // Adding the user adds a leaf per device.
+    let (creator, others) = members.split_first_mut().unwrap();
+    let (commit, welcome) =
+        add_members(&mut creator.0, &creator.1, &bob.key_packages().unwrap()).unwrap();
+    merge_commit(&mut creator.0, &creator.1).unwrap();
+    receive_message(&mut others[0].0, &others[0].1, deliver(&commit)).unwrap();
+    assert_eq!(creator.0.members().count(), 4);
+
+    let welcome = welcome.tls_serialize_detached().unwrap();
+    let mut devices: Vec<_> = bob
+        .devices
+        .iter()
+        .map(|device| (join_group(device, &welcome).unwrap(), device))
+        .collect();
+
+    // Whichever device sends, the other members and devices see the user.
+    let (phone_group, phone) = &mut devices[0];
+    let message = send_message(phone_group, phone, b"from my phone").unwrap();
+    let (laptop_group, laptop) = &mut devices[1];
+    for (group, member) in [(&mut creator.0, &creator.1), (laptop_group, *laptop)] {
+        let Received::Application { sender, .. } =
+            receive_message(group, member, deliver(&message)).unwrap()
+        else {
+            panic!("Expected an application message");
+        };
+        assert_eq!(user_of(&sender), b"bob");
+    }
+
+    // Removing the user removes all the devices.
+    let commit = remove_members(&mut creator.0, &creator.1, &[b"bob"]).unwrap();
+    merge_commit(&mut creator.0, &creator.1).unwrap();
+    assert_eq!(creator.0.members().count(), 2);
+    for (group, member) in devices.iter_mut() {
+        receive_message(group, member, deliver(&commit)).unwrap();
+        assert!(!group.is_active());
+    }
+}
+
+/// The credential a member would register with the AS.
+fn credential_of(member: &Member) -> Credential {
+    Credential::basic(
+        String::from_utf8(member.identity().to_vec()).unwrap(),
+        member.credential_with_key.signature_key.as_slice().to_vec(),
+        member.signature_scheme().unwrap(),
+        CREDENTIAL_VALIDITY,
+    )
+}
+
+#[test]
+fn credential_validation_flow() {
+    let mut members = setup_group("validation_flow", 2);
+    let registry = Arc::new(Registry::default());
+    for (_, member) in members.iter_mut() {
+        registry.register(credential_of(member));
+        member.validator = registry.clone();
+    }
+    let epoch = members[0].0.epoch();
+
+    // Unknown, revoked and expired credentials are rejected when adding.
+    let mut eve = Member::new("eve", "0".to_string(), GroupId::from_slice(&[]));
+    let key_package = eve.key_package().unwrap();
+    let (group, creator) = &mut members[0];
+    let rejected = |group: &mut MlsGroup, creator: &Member| match add_members(
+        group,
+        creator,
+        std::slice::from_ref(&key_package),
+    ) {
+        Err(ChatError::InvalidCredential(error)) => error,
+        other => panic!("Expected an invalid credential, got {other:?}"),
+    };
+    assert!(matches!(
+        rejected(group, creator),
+        CredentialError::Unknown(_)
+    ));
+
+    let mut credential = credential_of(&eve);
+    credential.revoked = true;
+    registry.register(credential);
+    assert!(matches!(
+        rejected(group, creator),
+        CredentialError::Revoked(_)
+    ));
+
+    let mut credential = credential_of(&eve);
+    credential.valid_from = SystemTime::now() - 2 * CREDENTIAL_VALIDITY;
+    credential.valid_until = Some(SystemTime::now() - Duration::from_secs(1));
+    registry.register(credential);
+    assert!(matches!(
+        rejected(group, creator),
+        CredentialError::Expired(_)
+    ));
+    assert_eq!(group.epoch(), epoch);
+
+    // A commit adding the unregistered leaf is not merged by the receivers.
+    let (committer_group, committer) = &mut members[1];
+    committer.validator = Arc::new(AcceptAll);
+    let (commit, _) = add_members(
+        committer_group,
+        committer,
+        std::slice::from_ref(&key_package),
+    )
+    .unwrap();
+    let (group, creator) = &mut members[0];
+    assert!(matches!(
+        receive_message(group, creator, deliver(&commit)),
+        Err(ChatError::InvalidCredential(CredentialError::Expired(_)))
+    ));
+    assert_eq!(group.epoch(), epoch);
+
+    // Once registered, the same leaf is accepted.
+    registry.register(credential_of(&eve));
+    let (_, welcome) = add_members(group, creator, &[key_package]).unwrap();
+    merge_commit(group, creator).unwrap();
+    let welcome = welcome.tls_serialize_detached().unwrap();
+    eve.validator = registry.clone();
+    let eve_group = join_group(&eve, &welcome).unwrap();
+    assert_eq!(eve_group.members().count(), 3);
+}
+
+#[test]
+fn x509_credential_flow() {
+    let (root, root_key, anchor) = certificates::root("Root CA");
+    let mut members = setup_group("x509_flow", 2);
+    let registry = Arc::new(Registry::default().with_trust_anchor(anchor.clone()));
+    for (_, member) in members.iter_mut() {
+        registry.register(credential_of(member));
+        member.validator = registry.clone();
+    }
+
+    // The leaves of carol carry the chain the AS issued for her key.
+    let carol = Member::new("carol", "0".to_string(), GroupId::from_slice(&[]));
+    let chain = vec![certificates::issue(
+        "carol:0",
+        carol.signer.public(),
+        &root,
+        &root_key,
+    )];
+    let mut carol = carol.with_certificate_chain(&chain).unwrap();
+    let credential = &carol.credential_with_key.credential;
+    assert_eq!(credential.credential_type(), CredentialType::X509);
+    assert_eq!(certificate_chain(credential).unwrap(), chain);
+    assert_eq!(identity_of(credential), b"carol:0");
+    registry.register(Credential::x509(chain, &anchor).unwrap());
+    carol.validator = registry.clone();
+
+    let (group, creator) = &mut members[0];
+    let (commit, welcome) = add_members(group, creator, &[carol.key_package().unwrap()]).unwrap();
+    merge_commit(group, creator).unwrap();
+    let (other_group, other) = &mut members[1];
+    receive_message(other_group, other, deliver(&commit)).unwrap();
+    let mut carol_group = join_group(&carol, &welcome.tls_serialize_detached().unwrap()).unwrap();
+    assert!(find_member(other_group, b"carol:0").is_some());
+
+    let message = send_message(&mut carol_group, &carol, b"certified").unwrap();
+    let (group, creator) = &mut members[0];
+    match receive_message(group, creator, deliver(&message)).unwrap() {
+        Received::Application { sender, content } => {
+            assert_eq!(sender, b"carol:0");
+            assert_eq!(content, b"certified");
+        }
+        other => panic!("Expected an application message, got {other:?}"),
+    }
+}
+
+#[test]
+fn x509_chain_is_checked_against_the_trust_anchor() {
+    let (_, _, anchor) = certificates::root("Root CA");
+    let (other, other_key, _) = certificates::root("Other CA");
+    let mut members = setup_group("x509_anchor", 1);
+    let registry = Arc::new(Registry::default().with_trust_anchor(anchor));
+    let (group, creator) = &mut members[0];
+    registry.register(credential_of(creator));
+    creator.validator = registry.clone();
+
+    // A chain of another CA, the key registered or not.
+    let mallory = Member::new("mallory", "0".to_string(), GroupId::from_slice(&[]));
+    let chain = vec![certificates::issue(
+        "mallory:0",
+        mallory.signer.public(),
+        &other,
+        &other_key,
+    )];
+    let mallory = mallory.with_certificate_chain(&chain).unwrap();
+    registry.register(credential_of(&mallory));
+    let key_package = mallory.key_package().unwrap();
+    assert!(matches!(
+        add_members(group, creator, std::slice::from_ref(&key_package)),
+        Err(ChatError::InvalidCredential(CredentialError::InvalidChain(
+            _
+        )))
+    ));
+
+    // No chain is trusted without an anchor.
+    creator.validator = Arc::new(AcceptAll);
+    assert!(matches!(
+        add_members(group, creator, &[key_package]),
+        Err(ChatError::InvalidCredential(CredentialError::InvalidChain(
+            _
+        )))
+    ));
+    assert_eq!(group.members().count(), 1);
+
+    // A member only carries a chain of its own identity and key.
+    let eve = Member::new("eve", "0".to_string(), GroupId::from_slice(&[]));
+    assert!(matches!(
+        eve.with_certificate_chain(&chain),
+        Err(ChatError::InvalidCredential(CredentialError::InvalidChain(
+            _
+        )))
+    ));
+    let mallory = Member::new("mallory", "0".to_string(), GroupId::from_slice(&[]));
+    assert!(matches!(
+        mallory.with_certificate_chain(&chain),
+        Err(ChatError::InvalidCredential(CredentialError::KeyMismatch(
+            _
+        )))
+    ));
+}
+
+/// Records the revoked identities, as the delivery service would be told.
+#[derive(Debug, Default)]
+struct Revocations(Mutex<Vec<String>>);
+
+impl RevocationListener for Revocations {
+    fn revoked(&self, identity: &str) {
+        self.0.lock().unwrap().push(identity.to_string());
+    }
+}
+
+#[test]
+fn revoked_credential_flow() {
+    let mut members = setup_group("revocation_flow", 3);
+    let registry = Arc::new(Registry::default());
+    let revocations = Arc::new(Revocations::default());
+    registry.subscribe(revocations.clone());
+    for (_, member) in members.iter_mut() {
+        registry.register(credential_of(member));
+        member.validator = registry.clone();
+    }
+
+    let revoked = String::from_utf8(members[2].1.identity().to_vec()).unwrap();
+    assert!(matches!(
+        registry.revoke("nobody:0"),
+        Err(CredentialError::Unknown(_))
+    ));
+    assert!(registry
+        .revoke(&revoked)
+        .unwrap()
+        .iter()
+        .all(|credential| credential.revoked));
+    assert_eq!(*revocations.0.lock().unwrap(), vec![revoked.clone()]);
+
+    // The revoked member can no longer commit its leaf.
+    let (group, member) = &mut members[2];
+    let commit = self_update(group, member).unwrap();
+    let epoch = members[0].0.epoch();
+    for (group, member) in members.iter_mut().take(2) {
+        assert!(matches!(
+            receive_message(group, member, deliver(&commit)),
+            Err(ChatError::InvalidCredential(CredentialError::Revoked(_)))
+        ));
+        assert_eq!(group.epoch(), epoch);
+    }
+
+    // A member removes its leaf.
+    let (creator, others) = members.split_first_mut().unwrap();
+    let leaf = others[1].0.own_leaf_index();
+    let commit = remove_leaves(&mut creator.0, &creator.1, &[leaf]).unwrap();
+    merge_commit(&mut creator.0, &creator.1).unwrap();
+    receive_message(&mut others[0].0, &others[0].1, deliver(&commit)).unwrap();
+    assert_eq!(creator.0.members().count(), 2);
+    assert_eq!(others[0].0.members().count(), 2);
+}
+
+#[test]
+fn credential_rotation_flow() {
+    let mut members = setup_group("rotation_flow", 3);
+    let registry = Arc::new(Registry::default());
+    for (_, member) in members.iter_mut() {
+        registry.register(credential_of(member));
+        member.validator = registry.clone();
+    }
+
+    // The device re-registers a new key with the AS, then replaces its leaf
+    // by an external commit signed with it.
+    let (group, member) = &members[1];
+    let group_info = export_group_info(group, member)
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    let old_leaf = group.own_leaf_index();
+    let rotated = Member::new(
+        &member.user_id,
+        member.device_id.clone(),
+        GroupId::from_slice(&[]),
+    )
+    .with_validator(registry.clone());
+    let previous = registry.rotate(credential_of(&rotated)).unwrap();
+    assert_eq!(
+        previous.public_key,
+        member.credential_with_key.signature_key.as_slice()
+    );
+    let (mut rotated_group, commit) = join_by_external_commit(&rotated, &group_info).unwrap();
+    merge_commit(&mut rotated_group, &rotated).unwrap();
+
+    // The old key no longer validates, the other members drop its leaf.
+    assert!(matches!(
+        registry.validate(
+            member.identity(),
+            member.credential_with_key.signature_key.as_slice()
+        ),
+        Err(CredentialError::KeyMismatch(_))
+    ));
+    let (creator_group, creator) = &mut members[0];
+    let Received::ExternalJoin { stale, .. } =
+        receive_message(creator_group, creator, deliver(&commit)).unwrap()
+    else {
+        panic!("Expected an external join");
+    };
+    assert_eq!(stale, vec![old_leaf]);
+    let commit = remove_leaves(creator_group, creator, &stale).unwrap();
+    merge_commit(creator_group, creator).unwrap();
+    receive_message(&mut rotated_group, &rotated, deliver(&commit)).unwrap();
+    assert_eq!(creator_group.members().count(), 3);
+
+    let message = send_message(&mut rotated_group, &rotated, b"rotated").unwrap();
+    let Received::Application { content, .. } =
+        receive_message(creator_group, creator, deliver(&message)).unwrap()
+    else {
+        panic!("Expected an application message");
+    };
+    assert_eq!(content, b"rotated");
+
+    // A revoked credential can not be rotated.
+    registry
+        .revoke(&String::from_utf8_lossy(rotated.identity()))
+        .unwrap();
+    assert!(matches!(
+        registry.rotate(credential_of(&rotated)),
+        Err(CredentialError::Revoked(_))
+    ));
+}
+
+#[test]
+fn supported_ciphersuites_flow() {
+    for ciphersuite in SUPPORTED_CIPHERSUITES {
+        let mut members = setup_group_with_ciphersuite("ciphersuite_flow", 3, ciphersuite);
+        let registry = Arc::new(Registry::default());
+        for (group, member) in members.iter_mut() {
+            assert_eq!(group.ciphersuite(), ciphersuite);
+            registry.register(credential_of(member));
+            member.validator = registry.clone();
+        }
+        let scheme = members[0].1.signature_scheme().unwrap();
+        let identity = String::from_utf8(members[0].1.identity().to_vec()).unwrap();
+        assert_eq!(
+            registry.get(&identity, scheme).unwrap().signature_scheme,
+            scheme
+        );
+
+        // Messages and a validated self-update in the suite
+        let (creator, others) = members.split_first_mut().unwrap();
+        let message = send_message(&mut creator.0, &creator.1, b"hello").unwrap();
+        for (group, member) in others.iter_mut() {
+            let received = receive_message(group, member, deliver(&message)).unwrap();
+            assert!(
+                matches!(received, Received::Application { content, .. } if content == b"hello")
+            );
+        }
+        let commit = self_update(&mut others[0].0, &others[0].1).unwrap();
+        merge_commit(&mut others[0].0, &others[0].1).unwrap();
+        receive_message(&mut creator.0, &creator.1, deliver(&commit)).unwrap();
+        receive_message(&mut others[1].0, &others[1].1, deliver(&commit)).unwrap();
+
+        // A new device joins from a key package of the suite
+        let newcomer = Member::new_with_ciphersuite(
+            "newcomer",
+            "0".to_string(),
+            GroupId::from_slice(&[]),
+            ciphersuite,
+        )
+        .with_validator(registry.clone());
+        registry.register(credential_of(&newcomer));
+        let key_package = newcomer.key_package().unwrap();
+        let (_, welcome) = add_members(&mut creator.0, &creator.1, &[key_package]).unwrap();
+        merge_commit(&mut creator.0, &creator.1).unwrap();
+        let welcome = welcome.tls_serialize_detached().unwrap();
+        assert_eq!(welcome_ciphersuite(&welcome).unwrap(), ciphersuite);
+        let group = join_group(&newcomer, &welcome).unwrap();
+        assert_eq!(group.members().count(), 4);
+
+        let group_info = export_group_info(&creator.0, &creator.1).unwrap();
+        let group_info = group_info.tls_serialize_detached().unwrap();
+        assert_eq!(group_info_ciphersuite(&group_info).unwrap(), ciphersuite);
+    }
+}
+
+#[test]
+fn key_package_of_another_ciphersuite_is_rejected() {
+    let [default, other] = SUPPORTED_CIPHERSUITES;
+    let mut members = setup_group_with_ciphersuite("mixed_flow", 1, default);
+    let (group, creator) = &mut members[0];
+    let stranger =
+        Member::new_with_ciphersuite("stranger", "0".to_string(), GroupId::from_slice(&[]), other);
+
+    let key_package = stranger.key_package().unwrap();
+
+    assert!(add_members(group, creator, &[key_package]).is_err());
+    assert_eq!(group.members().count(), 1);
+}
@@ -1,40 +1,201 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
 use openmls::{
-    group::{GroupId, MlsGroup, MlsGroupCreateConfig, StagedWelcome},
+    group::{
+        GroupId, MlsGroup, MlsGroupCreateConfig, MlsGroupJoinConfig, StagedCommit, StagedWelcome,
+    },
     prelude::{
-        test_utils::new_credential, BasicCredential, Ciphersuite, CredentialWithKey, KeyPackage,
-        MlsMessageIn, ProcessedMessageContent,
+        test_utils::new_credential,
+        tls_codec::{Deserialize as _, Serialize as _, VLBytes},
+        BasicCredential, Capabilities, Ciphersuite, Credential, CredentialType, CredentialWithKey,
+        ExtensionType, KeyPackage, KeyPackageIn, LeafNode, LeafNodeIndex, LeafNodeParameters,
+        MlsMessageIn, MlsMessageOut, ProcessedMessageContent, Proposal, ProtocolVersion, Sender,
     },
 };
 use openmls_basic_credential::SignatureKeyPair;
 use openmls_traits::OpenMlsProvider;
 use uuid::Uuid;
 
-use super::memory_provider::MemoryProvider;
+use super::{errors::ChatError, memory_provider::MemoryProvider};
+use crate::auth_service::{
+    AcceptAll, CertifiedKey, CredentialError, CredentialValidator, SignatureScheme,
+};
 
+/// The ciphersuite of groups created without choosing one.
 pub const CIPHERSUITE: Ciphersuite =
     Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+/// The ciphersuites a device publishes key packages for, each with its own
+/// signature key. Both are provided by `openmls_rust_crypto`.
+pub const SUPPORTED_CIPHERSUITES: [Ciphersuite; 2] = [
+    CIPHERSUITE,
+    Ciphersuite::MLS_128_DHKEMP256_AES128GCM_SHA256_P256,
+];
 pub const MAX_PAST_EPOCHS: usize = 100;
 
+/// Separates the user from the device in the identity of a credential.
+pub const DEVICE_SEPARATOR: char = ':';
+
+/// Identity carried by the credential of a user's device.
+pub fn device_identity(user_id: &str, device_id: &str) -> String {
+    format!("{}{}{}", user_id, DEVICE_SEPARATOR, device_id)
+}
+
+/// A supported ciphersuite by the short name users pick it with.
+pub fn ciphersuite_by_name(name: &str) -> Option<Ciphersuite> {
+    match name {
+        "x25519" => Some(CIPHERSUITE),
+        "p256" => Some(Ciphersuite::MLS_128_DHKEMP256_AES128GCM_SHA256_P256),
+        _ => None,
+    }
+}
+
+/// The scheme of the signature keys used in groups of `ciphersuite`, as
+/// registered with the AS.
+pub fn signature_scheme(ciphersuite: Ciphersuite) -> Result<SignatureScheme, ChatError> {
+    match ciphersuite.signature_algorithm() {
+        openmls::prelude::SignatureScheme::ED25519 => Ok(SignatureScheme::Ed25519),
+        openmls::prelude::SignatureScheme::ECDSA_SECP256R1_SHA256 => {
+            Ok(SignatureScheme::EcdsaSecp256r1)
+        }
+        _ => Err(ChatError::UnsupportedCiphersuite(ciphersuite)),
+    }
+}
+
+/// User a device identity belongs to, see [`device_identity`]. An identity
+/// without device is a user.
+pub fn user_of(identity: &[u8]) -> &[u8] {
+    let separator = DEVICE_SEPARATOR as u8;
+    match identity.iter().rposition(|byte| *byte == separator) {
+        Some(position) => &identity[..position],
+        None => identity,
+    }
+}
+
+/// An X.509 credential carrying a DER encoded certificate chain, leaf first,
+/// e.g. the one the AS issued for a signature key.
+pub fn x509_credential(chain: &[Vec<u8>]) -> Result<Credential, ChatError> {
+    let chain: Vec<VLBytes> = chain.iter().map(|der| der.clone().into()).collect();
+
+    Ok(Credential::new(
+        CredentialType::X509,
+        chain.tls_serialize_detached()?,
+    ))
+}
+
+/// The certificate chain of an X.509 credential, see [`x509_credential`].
+pub fn certificate_chain(credential: &Credential) -> Result<Vec<Vec<u8>>, ChatError> {
+    if credential.credential_type() != CredentialType::X509 {
+        return Err(CredentialError::InvalidChain("not an X.509 credential".to_string()).into());
+    }
+    let chain: Vec<VLBytes> = credential.deserialized()?;
+
+    Ok(chain.into_iter().map(Vec::from).collect())
+}
+
+/// Identity carried by a credential: the identity of a basic credential, the
+/// common name of the leaf certificate of an X.509 one. Empty if the chain
+/// can not be read.
+pub fn identity_of(credential: &Credential) -> Vec<u8> {
+    match credential.credential_type() {
+        CredentialType::X509 => certificate_chain(credential)
+            .ok()
+            .and_then(|chain| CertifiedKey::read(chain.first()?).ok())
+            .map(|certified| certified.identity.into_bytes())
+            .unwrap_or_default(),
+        _ => credential.serialized_content().to_vec(),
+    }
+}
+
+/// Checks the credential of a leaf and its signature key with `validator`.
+/// The chain of an X.509 credential must end at the trust anchor of the
+/// validator, its leaf certificate certifying the signature key.
+pub fn validate_credential(
+    validator: &dyn CredentialValidator,
+    credential: &Credential,
+    signature_key: &[u8],
+) -> Result<(), ChatError> {
+    if credential.credential_type() == CredentialType::X509 {
+        let trust_anchor = validator
+            .trust_anchor()
+            .ok_or_else(|| CredentialError::InvalidChain("no trust anchor".to_string()))?;
+        let certified = trust_anchor.verify(&certificate_chain(credential)?, SystemTime::now())?;
+        if certified.public_key != signature_key {
+            return Err(CredentialError::KeyMismatch(certified.identity).into());
+        }
+    }
+
+    Ok(validator.validate(&identity_of(credential), signature_key)?)
+}
+
+/// The capabilities of the leaves of a device. Every member must support
+/// the credential type of each leaf, basic and X.509 ones are mixed.
+fn leaf_capabilities(extensions: Option<&[ExtensionType]>) -> Capabilities {
+    Capabilities::new(
+        None,
+        None,
+        extensions,
+        None,
+        Some(&[CredentialType::Basic, CredentialType::X509]),
+    )
+}
+
+/// A device of a user: each device has its own leaf, keys and credential,
+/// the credential identity ties it to the user, see [`device_identity`].
+///
+/// The signature key is of the scheme of the member's ciphersuite, a device
+/// taking part in groups of several ciphersuites is a member for each.
 #[derive(Debug)]
 pub struct Member {
     pub provider: MemoryProvider,
     pub credential_with_key: CredentialWithKey,
     pub signer: SignatureKeyPair,
+    pub ciphersuite: Ciphersuite,
     pub group_id: GroupId,
     pub user_id: String,
+    pub device_id: String,
+    /// Consulted on every leaf added or updated in the member's groups.
+    pub validator: Arc<dyn CredentialValidator>,
+    // Carried by the credential, see [`identity_of`]
+    identity: Vec<u8>,
 }
 
 impl Member {
-    fn client_id(&self) -> &str {
-        &self.user_id.as_str()
+    fn client_id(&self) -> String {
+        device_identity(&self.user_id, &self.device_id)
+    }
+
+    /// Identity carried by the member's credential.
+    pub fn identity(&self) -> &[u8] {
+        &self.identity
+    }
+
+    /// Identity of the user the device belongs to.
+    pub fn user(&self) -> &[u8] {
+        user_of(self.identity())
+    }
+
+    /// Scheme of the member's signature key, see [`signature_scheme`].
+    pub fn signature_scheme(&self) -> Result<SignatureScheme, ChatError> {
+        signature_scheme(self.ciphersuite)
     }
 }
 
 impl Member {
-    pub fn new(name: &str, user_id: String, group_id: GroupId) -> Member {
-        let identity = format!("{}_{}", name, user_id);
+    /// A member of groups of the default [`CIPHERSUITE`].
+    pub fn new(user_id: &str, device_id: String, group_id: GroupId) -> Member {
+        Self::new_with_ciphersuite(user_id, device_id, group_id, CIPHERSUITE)
+    }
+
+    pub fn new_with_ciphersuite(
+        user_id: &str,
+        device_id: String,
+        group_id: GroupId,
+        ciphersuite: Ciphersuite,
+    ) -> Member {
+        let identity = device_identity(user_id, &device_id);
         let creator_provider = MemoryProvider::default();
         // signature_key in credential_with_key is same as keypair.public
         // credential could be
@@ -43,22 +204,142 @@ impl Member {
         let (credential_with_key, creator_signer) = new_credential(
             &creator_provider,
             identity.as_bytes(),
-            CIPHERSUITE.signature_algorithm(),
+            ciphersuite.signature_algorithm(),
         );
 
         Self {
             provider: creator_provider,
             credential_with_key,
             signer: creator_signer,
+            ciphersuite,
             group_id,
-            user_id,
+            user_id: user_id.to_string(),
+            device_id,
+            validator: Arc::new(AcceptAll),
+            identity: identity.into_bytes(),
         }
     }
 
+    pub fn with_validator(mut self, validator: Arc<dyn CredentialValidator>) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Carries in the member's leaves the X.509 credential of `chain`, DER
+    /// encoded leaf first, instead of a basic one. The leaf certificate must
+    /// certify the member's identity and signature key.
+    pub fn with_certificate_chain(mut self, chain: &[Vec<u8>]) -> Result<Self, ChatError> {
+        let leaf = chain
+            .first()
+            .ok_or_else(|| CredentialError::InvalidChain("the chain is empty".to_string()))?;
+        let certified = CertifiedKey::read(leaf)?;
+        if certified.identity.as_bytes() != self.identity() {
+            return Err(CredentialError::InvalidChain(format!(
+                "the certificate is issued to `{}`",
+                certified.identity
+            ))
+            .into());
+        }
+        if certified.public_key != self.signer.public() {
+            return Err(CredentialError::KeyMismatch(certified.identity).into());
+        }
+        self.credential_with_key.credential = x509_credential(chain)?;
+
+        Ok(self)
+    }
+
+    /// Checks the credential of a leaf with the member's validator, see
+    /// [`validate_credential`].
+    pub fn validate_leaf(&self, leaf_node: &LeafNode) -> Result<(), ChatError> {
+        validate_credential(
+            self.validator.as_ref(),
+            leaf_node.credential(),
+            leaf_node.signature_key().as_slice(),
+        )
+    }
+
+    /// Checks the credentials of all the leaves of a group the member joins.
+    /// Leaves of its own identity are skipped: when rejoining with a new key
+    /// they carry the previous one, and are removed as stale.
+    fn validate_group(&self, group: &MlsGroup) -> Result<(), ChatError> {
+        group
+            .members()
+            .filter(|member| identity_of(&member.credential) != self.identity())
+            .try_for_each(|member| {
+                validate_credential(
+                    self.validator.as_ref(),
+                    &member.credential,
+                    &member.signature_key,
+                )
+            })
+    }
+
     pub fn basic_credential(&self) -> BasicCredential {
-        let client_id = &self.client_id().clone().as_bytes().to_vec();
+        let client_id = self.client_id().as_bytes().to_vec();
 
-        BasicCredential::new(client_id.to_owned())
+        BasicCredential::new(client_id)
+    }
+
+    /// Builds a key package signed with the member's own signer, so that the
+    /// member can later join a group of its ciphersuite from a Welcome for it.
+    pub fn key_package(&self) -> Result<KeyPackage, ChatError> {
+        let bundle = KeyPackage::builder()
+            .leaf_node_capabilities(leaf_capabilities(None))
+            .build(
+                self.ciphersuite,
+                &self.provider,
+                &self.signer,
+                self.credential_with_key.clone(),
+            )?;
+
+        Ok(bundle.key_package().clone())
+    }
+
+    /// Like [`Member::key_package`], for the key package the Delivery Service
+    /// hands out, without removing it, once the member's pool is empty.
+    pub fn last_resort_key_package(&self) -> Result<KeyPackage, ChatError> {
+        let bundle = KeyPackage::builder()
+            .leaf_node_capabilities(leaf_capabilities(Some(&[ExtensionType::LastResort])))
+            .mark_as_last_resort()
+            .build(
+                self.ciphersuite,
+                &self.provider,
+                &self.signer,
+                self.credential_with_key.clone(),
+            )?;
+
+        Ok(bundle.key_package().clone())
+    }
+}
+
+/// A user with several devices. Adding the user to a group adds all of them.
+#[derive(Debug)]
+pub struct User {
+    pub user_id: String,
+    pub devices: Vec<Member>,
+}
+
+impl User {
+    pub fn new(user_id: &str, devices: usize) -> User {
+        let devices = (0..devices)
+            .map(|_| {
+                Member::new(
+                    user_id,
+                    Uuid::new_v4().to_string(),
+                    GroupId::from_slice(&[]),
+                )
+            })
+            .collect();
+
+        Self {
+            user_id: user_id.to_string(),
+            devices,
+        }
+    }
+
+    /// A key package of each device, see [`Member::key_package`].
+    pub fn key_packages(&self) -> Result<Vec<KeyPackage>, ChatError> {
+        self.devices.iter().map(Member::key_package).collect()
     }
 }
 
@@ -86,8 +367,13 @@ pub fn create_group(
     group_config: &MlsGroupCreateConfig,
 ) -> (MlsGroup, Member) {
     let group_id = GroupId::from_slice(group_name.as_bytes());
-    let user_id = Uuid::new_v4().to_string();
-    let creator_member = Member::new(&creator_name, user_id, group_id);
+    let device_id = Uuid::new_v4().to_string();
+    let creator_member = Member::new_with_ciphersuite(
+        &creator_name,
+        device_id,
+        group_id,
+        group_config.ciphersuite(),
+    );
 
     let creator_group = MlsGroup::new_with_group_id(
         &creator_member.provider,
@@ -101,32 +387,6 @@ pub fn create_group(
     (creator_group, creator_member)
 }
 
-fn new_member(
-    name: &str,
-) -> (
-    MemoryProvider,
-    SignatureKeyPair,
-    CredentialWithKey,
-    openmls::prelude::KeyPackageBundle,
-) {
-    let identity = format!("{}_{}", name, Uuid::new_v4());
-    let member_provider = MemoryProvider::default();
-    let (credential_with_key, signer) = new_credential(
-        &member_provider,
-        identity.as_bytes(),
-        CIPHERSUITE.signature_algorithm(),
-    );
-    let key_package = KeyPackage::builder()
-        .build(
-            CIPHERSUITE,
-            &member_provider,
-            &signer,
-            credential_with_key.clone(),
-        )
-        .expect("An unexpected error occurred.");
-    (member_provider, signer, credential_with_key, key_package)
-}
-
 /// Convenience function that generates a Mls group based on
 /// num: number of expected members in a group which
 /// creator is always at the index 0 - creator = members[0]
@@ -135,8 +395,17 @@ fn new_member(
 ///
 /// Returns the [`MlsGroup`] and the [`Member`].
 pub fn setup_group(group_name: &str, num: usize) -> Vec<(MlsGroup, Member)> {
+    setup_group_with_ciphersuite(group_name, num, CIPHERSUITE)
+}
+
+/// Like [`setup_group`], for a group of `ciphersuite`.
+pub fn setup_group_with_ciphersuite(
+    group_name: &str,
+    num: usize,
+    ciphersuite: Ciphersuite,
+) -> Vec<(MlsGroup, Member)> {
     let mut members = Vec::new();
-    let mls_group_create_config = create_group_config();
+    let mls_group_create_config = create_group_config(ciphersuite);
     let creator_name = "Member_0"; // Creator is always at index 0
     let (creator_group, creator_member) = create_group(
         creator_name.to_string(),
@@ -146,8 +415,13 @@ pub fn setup_group(group_name: &str, num: usize) -> Vec<(MlsGroup, Member)> {
     members.push((creator_group, creator_member));
 
     for member_i in 1..num {
-        let (member_provider, signer, credential_with_key, key_package) =
-            new_member(&format!("Member_{member_i}"));
+        let mut member = Member::new_with_ciphersuite(
+            &format!("Member_{member_i}"),
+            Uuid::new_v4().to_string(),
+            GroupId::from_slice(&[]),
+            ciphersuite,
+        );
+        let key_package = member.key_package().unwrap();
 
         let creator = &mut members[0];
         let creator_group = &mut creator.0;
@@ -155,11 +429,7 @@ pub fn setup_group(group_name: &str, num: usize) -> Vec<(MlsGroup, Member)> {
         let creator_signer = &creator.1.signer;
 
         let (commit, welcome, _) = creator_group
-            .add_members(
-                creator_provider,
-                creator_signer,
-                &[key_package.key_package().clone()],
-            )
+            .add_members(creator_provider, creator_signer, &[key_package])
             .unwrap();
 
         creator_group
@@ -172,13 +442,13 @@ pub fn setup_group(group_name: &str, num: usize) -> Vec<(MlsGroup, Member)> {
             .expect("expected the message to be a welcome message");
 
         let member_i_group = StagedWelcome::new_from_welcome(
-            &member_provider,
+            &member.provider,
             mls_group_create_config.join_config(),
             welcome,
             Some(creator_group.export_ratchet_tree().into()),
         )
         .unwrap()
-        .into_group(&member_provider)
+        .into_group(&member.provider)
         .unwrap();
 
         // Merge commit on all other members
@@ -186,28 +456,28 @@ pub fn setup_group(group_name: &str, num: usize) -> Vec<(MlsGroup, Member)> {
             process_commit(group, &member.provider, commit.clone());
         }
 
-        let group_id = member_i_group.group_id().clone();
         // Add new member to list
-        members.push((
-            member_i_group,
-            Member {
-                // FIXME: each member is a user with one client
-                user_id: Uuid::new_v4().to_string(),
-                provider: member_provider,
-                credential_with_key,
-                signer,
-                group_id,
-            },
-        ));
+        member.group_id = member_i_group.group_id().clone();
+        members.push((member_i_group, member));
     }
 
     members
 }
 
-pub fn create_group_config() -> MlsGroupCreateConfig {
+pub fn create_group_config(ciphersuite: Ciphersuite) -> MlsGroupCreateConfig {
     MlsGroupCreateConfig::builder()
         .use_ratchet_tree_extension(true)
-        .ciphersuite(CIPHERSUITE)
+        .ciphersuite(ciphersuite)
+        .capabilities(leaf_capabilities(None))
+        .max_past_epochs(MAX_PAST_EPOCHS)
+        .build()
+}
+
+/// The configuration of groups joined from a Welcome or a GroupInfo, their
+/// ciphersuite is the one of the group.
+pub fn join_group_config() -> MlsGroupJoinConfig {
+    MlsGroupJoinConfig::builder()
+        .use_ratchet_tree_extension(true)
         .max_past_epochs(MAX_PAST_EPOCHS)
         .build()
 }
@@ -236,10 +506,379 @@ pub fn generate_key_package(
         .expect("An unexpected error occurred.")
 }
 
+/// Returns the leaf index of the member whose credential carries `identity`.
+pub fn find_member(group: &MlsGroup, identity: &[u8]) -> Option<LeafNodeIndex> {
+    group
+        .members()
+        .find(|member| identity_of(&member.credential) == identity)
+        .map(|member| member.index)
+}
+
+/// Returns the leaf indices of the devices `identity` designates: a device,
+/// or all the devices of a user, see [`user_of`].
+pub fn find_devices(group: &MlsGroup, identity: &[u8]) -> Vec<LeafNodeIndex> {
+    group
+        .members()
+        .filter(|member| {
+            let device = identity_of(&member.credential);
+            device == identity || user_of(&device) == identity
+        })
+        .map(|member| member.index)
+        .collect()
+}
+
+/// Deserializes and verifies a key package received from the Delivery Service.
+pub fn read_key_package(provider: &MemoryProvider, bytes: &[u8]) -> Result<KeyPackage, ChatError> {
+    let key_package = KeyPackageIn::tls_deserialize_exact(bytes)?;
+
+    Ok(key_package.validate(provider.crypto(), ProtocolVersion::Mls10)?)
+}
+
+/// The ciphersuite of the group a serialized Welcome is for, to pick the
+/// member whose key package it was sent to.
+pub fn welcome_ciphersuite(welcome: &[u8]) -> Result<Ciphersuite, ChatError> {
+    let welcome = MlsMessageIn::tls_deserialize_exact(welcome)?
+        .into_welcome()
+        .ok_or(ChatError::UnexpectedMessage("welcome"))?;
+    // The cipher suite is the first field of a Welcome (RFC 9420, 12.4.3.1)
+    let encoded = welcome.tls_serialize_detached()?;
+
+    Ok(Ciphersuite::tls_deserialize(&mut encoded.as_slice())?)
+}
+
+/// The ciphersuite of the group of a serialized GroupInfo, see
+/// [`welcome_ciphersuite`].
+pub fn group_info_ciphersuite(group_info: &[u8]) -> Result<Ciphersuite, ChatError> {
+    let group_info = MlsMessageIn::tls_deserialize_exact(group_info)?
+        .into_verifiable_group_info()
+        .ok_or(ChatError::UnexpectedMessage("group info"))?;
+
+    Ok(group_info.ciphersuite())
+}
+
+/// Joins a group from a serialized Welcome. The ratchet tree is taken from
+/// the Welcome's extension, see [`create_group_config`].
+pub fn join_group(member: &Member, welcome: &[u8]) -> Result<MlsGroup, ChatError> {
+    let welcome = MlsMessageIn::tls_deserialize_exact(welcome)?
+        .into_welcome()
+        .ok_or(ChatError::UnexpectedMessage("welcome"))?;
+    let group =
+        StagedWelcome::new_from_welcome(&member.provider, &join_group_config(), welcome, None)?
+            .into_group(&member.provider)?;
+    member.validate_group(&group)?;
+
+    Ok(group)
+}
+
+/// Exports the signed GroupInfo of the current epoch, ratchet tree included,
+/// so that a client can join the group by external commit.
+pub fn export_group_info(group: &MlsGroup, member: &Member) -> Result<MlsMessageOut, ChatError> {
+    Ok(group.export_group_info(&member.provider, &member.signer, true)?)
+}
+
+/// Joins a group from a serialized GroupInfo, see [`export_group_info`].
+///
+/// Returns the group and the external commit adding the member, which the
+/// members have to process. The commit is pending, see [`merge_commit`]. A
+/// leaf of the group with the member's signature key is removed by the
+/// commit, a leaf left behind with lost state is not, see
+/// [`Received::ExternalJoin`].
+pub fn join_by_external_commit(
+    member: &Member,
+    group_info: &[u8],
+) -> Result<(MlsGroup, MlsMessageOut), ChatError> {
+    let group_info = MlsMessageIn::tls_deserialize_exact(group_info)?
+        .into_verifiable_group_info()
+        .ok_or(ChatError::UnexpectedMessage("group info"))?;
+    let (group, commit, _) = MlsGroup::join_by_external_commit(
+        &member.provider,
+        &member.signer,
+        None,
+        group_info,
+        &join_group_config(),
+        Some(leaf_capabilities(None)),
+        None,
+        &[],
+        member.credential_with_key.clone(),
+    )?;
+    member.validate_group(&group)?;
+
+    Ok((group, commit))
+}
+
+/// Merges the member's own pending commit.
+///
+/// The commit helpers below leave their commit pending: only one commit per
+/// epoch is accepted by the Delivery Service, so it is merged once accepted.
+/// A commit of another member merged meanwhile discards it.
+pub fn merge_commit(group: &mut MlsGroup, member: &Member) -> Result<(), ChatError> {
+    Ok(group.merge_pending_commit(&member.provider)?)
+}
+
+/// Adds the owners of `key_packages` to the group.
+///
+/// Returns the commit the existing members have to process and the Welcome
+/// for the new members. The commit is pending, see [`merge_commit`].
+pub fn add_members(
+    group: &mut MlsGroup,
+    member: &Member,
+    key_packages: &[KeyPackage],
+) -> Result<(MlsMessageOut, MlsMessageOut), ChatError> {
+    for key_package in key_packages {
+        member.validate_leaf(key_package.leaf_node())?;
+    }
+    let (commit, welcome, _) = group.add_members(&member.provider, &member.signer, key_packages)?;
+
+    Ok((commit, welcome))
+}
+
+/// Removes the devices designated by `identities`, see [`find_devices`].
+///
+/// Returns the commit the remaining members have to process, it is pending,
+/// see [`merge_commit`]. Once merged, the removed members can not decrypt
+/// anything sent in the new epoch.
+pub fn remove_members(
+    group: &mut MlsGroup,
+    member: &Member,
+    identities: &[&[u8]],
+) -> Result<MlsMessageOut, ChatError> {
+    let mut leaf_indices = Vec::new();
+    for identity in identities {
+        let devices = find_devices(group, identity);
+        if devices.is_empty() {
+            return Err(ChatError::UnknownIdentity(
+                String::from_utf8_lossy(identity).into_owned(),
+            ));
+        }
+        leaf_indices.extend(devices);
+    }
+
+    remove_leaves(group, member, &leaf_indices)
+}
+
+/// Removes the given leaves, like [`remove_members`] for leaves that share
+/// their identity with another one.
+///
+/// Fails while proposals are queued: the commit would carry them along, and
+/// the members they add would get no Welcome. Commit them first, see
+/// [`commit_pending_proposals`].
+pub fn remove_leaves(
+    group: &mut MlsGroup,
+    member: &Member,
+    leaf_indices: &[LeafNodeIndex],
+) -> Result<MlsMessageOut, ChatError> {
+    if group.pending_proposals().next().is_some() {
+        return Err(ChatError::PendingProposals);
+    }
+    let (commit, _, _) = group.remove_members(&member.provider, &member.signer, leaf_indices)?;
+
+    Ok(commit)
+}
+
+/// Proposes to remove the member's own leaf from the group.
+///
+/// A member can not commit its own removal, another member has to commit
+/// the returned proposal, see [`commit_pending_proposals`].
+pub fn leave_group(group: &mut MlsGroup, member: &Member) -> Result<MlsMessageOut, ChatError> {
+    Ok(group.leave_group(&member.provider, &member.signer)?)
+}
+
+/// Proposes to add the owner of `key_package`, without committing.
+///
+/// The proposal is queued by every member, including the proposer, until a
+/// member commits the queue, see [`commit_pending_proposals`].
+pub fn propose_add(
+    group: &mut MlsGroup,
+    member: &Member,
+    key_package: &KeyPackage,
+) -> Result<MlsMessageOut, ChatError> {
+    member.validate_leaf(key_package.leaf_node())?;
+    let (proposal, _) = group.propose_add_member(&member.provider, &member.signer, key_package)?;
+
+    Ok(proposal)
+}
+
+/// Proposes to remove the devices designated by `identity`, one proposal
+/// per device, without committing, see [`propose_add`].
+pub fn propose_remove(
+    group: &mut MlsGroup,
+    member: &Member,
+    identity: &[u8],
+) -> Result<Vec<MlsMessageOut>, ChatError> {
+    let devices = find_devices(group, identity);
+    if devices.is_empty() {
+        return Err(ChatError::UnknownIdentity(
+            String::from_utf8_lossy(identity).into_owned(),
+        ));
+    }
+
+    devices
+        .into_iter()
+        .map(|leaf_index| {
+            let (proposal, _) =
+                group.propose_remove_member(&member.provider, &member.signer, leaf_index)?;
+            Ok(proposal)
+        })
+        .collect()
+}
+
+/// Proposes to rotate the member's leaf key, without committing, see
+/// [`propose_add`]. The member can not commit its own update proposal.
+pub fn propose_update(group: &mut MlsGroup, member: &Member) -> Result<MlsMessageOut, ChatError> {
+    let (proposal, _) = group.propose_self_update(
+        &member.provider,
+        &member.signer,
+        LeafNodeParameters::default(),
+    )?;
+
+    Ok(proposal)
+}
+
+/// Commits every proposal queued since the last epoch.
+///
+/// Returns the commit the other members have to process, and the Welcome
+/// for the members added by the queued proposals if any. The commit is
+/// pending, see [`merge_commit`].
+pub fn commit_pending_proposals(
+    group: &mut MlsGroup,
+    member: &Member,
+) -> Result<(MlsMessageOut, Option<MlsMessageOut>), ChatError> {
+    let (commit, welcome, _) =
+        group.commit_to_pending_proposals(&member.provider, &member.signer)?;
+
+    Ok((commit, welcome))
+}
+
+/// Rotates the member's leaf key with a commit.
+///
+/// Returns the commit the other members have to process, it is pending, see
+/// [`merge_commit`]. Once merged, a leaked secret of a previous epoch, or the
+/// previous leaf key, does not decrypt anything sent afterwards
+/// (post-compromise security).
+///
+/// Fails while proposals are queued, like [`remove_leaves`].
+pub fn self_update(group: &mut MlsGroup, member: &Member) -> Result<MlsMessageOut, ChatError> {
+    if group.pending_proposals().next().is_some() {
+        return Err(ChatError::PendingProposals);
+    }
+    let (commit, _, _) = group.self_update(
+        &member.provider,
+        &member.signer,
+        LeafNodeParameters::default(),
+    )?;
+
+    Ok(commit)
+}
+
+/// Encrypts `content` as an application message for the current epoch.
+pub fn send_message(
+    group: &mut MlsGroup,
+    member: &Member,
+    content: &[u8],
+) -> Result<MlsMessageOut, ChatError> {
+    Ok(group.create_message(&member.provider, &member.signer, content)?)
+}
+
+/// What a member got out of an incoming group message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// A decrypted application message and the identity of its sender.
+    Application { sender: Vec<u8>, content: Vec<u8> },
+    /// A proposal that was queued for the next commit.
+    Proposal { sender: Vec<u8> },
+    /// A commit that was merged, the group moved to a new epoch.
+    Commit,
+    /// An external commit that was merged, its sender joined the group.
+    ///
+    /// `stale` are the leaves the sender had before joining, left behind
+    /// when it lost its group state. Any member can remove them.
+    ExternalJoin {
+        sender: Vec<u8>,
+        stale: Vec<LeafNodeIndex>,
+    },
+}
+
+/// Processes a message received for `group`: application messages are
+/// decrypted, proposals are queued and commits are merged.
+///
+/// Once a commit removing the member is merged the group is no longer
+/// active, see [`MlsGroup::is_active`].
+pub fn receive_message(
+    group: &mut MlsGroup,
+    member: &Member,
+    message: MlsMessageIn,
+) -> Result<Received, ChatError> {
+    let message = message
+        .try_into_protocol_message()
+        .map_err(|_| ChatError::UnexpectedMessage("protocol"))?;
+    let processed_message = group.process_message(&member.provider, message)?;
+    let sender = identity_of(processed_message.credential());
+    let external = matches!(processed_message.sender(), Sender::NewMemberCommit);
+
+    match processed_message.into_content() {
+        ProcessedMessageContent::ApplicationMessage(application_message) => {
+            Ok(Received::Application {
+                sender,
+                content: application_message.into_bytes(),
+            })
+        }
+        ProcessedMessageContent::ProposalMessage(proposal)
+        | ProcessedMessageContent::ExternalJoinProposalMessage(proposal) => {
+            if let Some(leaf_node) = proposed_leaf(proposal.proposal()) {
+                member.validate_leaf(leaf_node)?;
+            }
+            group.store_pending_proposal(member.provider.storage(), *proposal)?;
+            Ok(Received::Proposal { sender })
+        }
+        ProcessedMessageContent::StagedCommitMessage(staged_commit) if external => {
+            validate_commit(member, &staged_commit)?;
+            let removed: Vec<LeafNodeIndex> = staged_commit
+                .remove_proposals()
+                .map(|queued| queued.remove_proposal().removed())
+                .collect();
+            let stale = group
+                .members()
+                .filter(|leaf| identity_of(&leaf.credential) == sender)
+                .map(|leaf| leaf.index)
+                .filter(|index| !removed.contains(index))
+                .collect();
+            group.merge_staged_commit(&member.provider, *staged_commit)?;
+            Ok(Received::ExternalJoin { sender, stale })
+        }
+        ProcessedMessageContent::StagedCommitMessage(staged_commit) => {
+            validate_commit(member, &staged_commit)?;
+            group.merge_staged_commit(&member.provider, *staged_commit)?;
+            Ok(Received::Commit)
+        }
+    }
+}
+
+/// The leaf node an Add or Update proposal brings into the group.
+fn proposed_leaf(proposal: &Proposal) -> Option<&LeafNode> {
+    match proposal {
+        Proposal::Add(add) => Some(add.key_package().leaf_node()),
+        Proposal::Update(update) => Some(update.leaf_node()),
+        _ => None,
+    }
+}
+
+/// Checks every leaf a commit adds or updates, before it is merged.
+///
+/// A rejected commit is not merged, the member stays in the previous epoch.
+fn validate_commit(member: &Member, staged_commit: &StagedCommit) -> Result<(), ChatError> {
+    staged_commit
+        .queued_proposals()
+        .filter_map(|queued| proposed_leaf(queued.proposal()))
+        .chain(staged_commit.update_path_leaf_node())
+        .try_for_each(|leaf_node| member.validate_leaf(leaf_node))
+}
+
+#[allow(dead_code)]
 struct ProviderPool {
     providers: Vec<Arc<Mutex<MemoryProvider>>>,
 }
 
+#[allow(dead_code)]
 impl ProviderPool {
     // Create a new pool with a specified number of providers
     fn new(size: usize) -> Self {
@@ -24,3 +24,19 @@ impl OpenMlsProvider for MemoryProvider {
         &self.crypto
     }
 }
+
+#[cfg(test)]
+impl MemoryProvider {
+    /// Copy of everything the member stored, group state, epoch secrets and
+    /// private keys included, as an attacker compromising the device gets it.
+    pub fn snapshot(&self) -> Self {
+        let values = self.key_store.values.read().unwrap().clone();
+
+        Self {
+            crypto: RustCrypto::default(),
+            key_store: MemoryStorage {
+                values: values.into(),
+            },
+        }
+    }
+}
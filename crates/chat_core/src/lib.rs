@@ -0,0 +1,2 @@
+pub mod auth_service;
+pub mod ext_mls;
@@ -1,36 +1,50 @@
 use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
-use crate::client::{spawn_client, ClientInfo};
+use chat_core::auth_service::{CredentialValidator, Registry};
+
+use crate::client::{spawn_client, ClientInfo, TokenVerifier, Transport};
 use crate::main_loop::{ServerHandle, ToDelivery};
 
 use tokio::net::TcpListener;
 
-pub async fn start_accept(bind: SocketAddr, mut handle: ServerHandle) {
-    let res = accept_loop(bind, handle.clone()).await;
-    match res {
-        Ok(()) => {}
-        Err(err) => {
-            handle.send(ToDelivery::FatalError(err)).await;
-        }
+pub async fn start_accept(
+    bind: SocketAddr,
+    mut handle: ServerHandle,
+    registry: Arc<Registry>,
+    validator: Option<Arc<dyn CredentialValidator>>,
+    verifier: Option<Arc<dyn TokenVerifier>>,
+) {
+    let res = accept_loop(bind, handle.clone(), registry, validator, verifier).await;
+    if let Err(err) = res {
+        handle.send(ToDelivery::FatalError(err)).await;
     }
 }
 
-pub async fn accept_loop(bind: SocketAddr, handle: ServerHandle) -> Result<(), io::Error> {
+pub async fn accept_loop(
+    bind: SocketAddr,
+    handle: ServerHandle,
+    registry: Arc<Registry>,
+    validator: Option<Arc<dyn CredentialValidator>>,
+    verifier: Option<Arc<dyn TokenVerifier>>,
+) -> Result<(), io::Error> {
     let listen = TcpListener::bind(bind).await?;
 
     loop {
         let (tcp, ip) = listen.accept().await?;
-        println!("[Client] tcp: {:?}", tcp);
-        println!("[Client] ip: {:?}", ip);
-
         let id = handle.next_id();
+        tracing::info!(client_id = %id, %ip, "Accepted a telnet client");
 
         let data = ClientInfo {
             ip,
             id,
-            tcp,
+            transport: Transport::Telnet(tcp),
             handle: handle.clone(),
+            registry: registry.clone(),
+            validator: validator.clone(),
+            user: None,
+            verifier: verifier.clone(),
         };
 
         spawn_client(data);
@@ -0,0 +1,226 @@
+// This module runs the admin console of the delivery service, a telnet
+// listener of its own for the operators. Whoever connects is an admin, it is
+// bound to a private address. The commands are answered by the main loop.
+
+use std::{io, net::SocketAddr};
+
+use futures::StreamExt;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+};
+use tokio_util::codec::FramedRead;
+use tracing::Instrument;
+
+use crate::{
+    main_loop::{ServerHandle, ToDelivery},
+    telnet::{Item, TelnetCodec},
+    ClientId,
+};
+
+const HELP: [&str; 6] = [
+    "a#ses: list the sessions, with their queued messages",
+    "a#grp: list the groups, with their epochs",
+    "a#kck <client id>: disconnect a client",
+    "a#bct <notice>: send a notice to every client",
+    "a#kps: count the key packages left of each identity",
+    "a#hlp: list the commands",
+];
+
+#[derive(Debug)]
+enum AdminCommand {
+    Sessions,
+    Groups,
+    Kick(ClientId),
+    Broadcast(String),
+    KeyPackages,
+    Help,
+}
+
+fn parse_admin_line(line: &[u8]) -> Option<AdminCommand> {
+    let line = String::from_utf8_lossy(line);
+    let (command, args) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+
+    match command {
+        // a#ses == admin: list the [ses]sions
+        "a#ses" => Some(AdminCommand::Sessions),
+        // a#grp == admin: list the [gr]ou[p]s
+        "a#grp" => Some(AdminCommand::Groups),
+        // a#kck == admin: [k]i[ck] a client by id
+        "a#kck" => args
+            .trim()
+            .parse()
+            .ok()
+            .map(ClientId)
+            .map(AdminCommand::Kick),
+        // a#bct == admin: [b]road[c]as[t] a notice
+        "a#bct" if !args.trim().is_empty() => {
+            Some(AdminCommand::Broadcast(args.trim().to_string()))
+        }
+        // a#kps == admin: [k]ey [p]ackage [s]tats
+        "a#kps" => Some(AdminCommand::KeyPackages),
+        // a#hlp == admin: [h]e[lp]
+        "a#hlp" => Some(AdminCommand::Help),
+        _ => None,
+    }
+}
+
+pub async fn start_admin_console(bind: SocketAddr, handle: ServerHandle) {
+    if let Err(err) = admin_accept_loop(bind, handle).await {
+        tracing::error!(error = %err, "The admin console failed");
+    }
+}
+
+async fn admin_accept_loop(bind: SocketAddr, handle: ServerHandle) -> Result<(), io::Error> {
+    let listen = TcpListener::bind(bind).await?;
+
+    loop {
+        let (tcp, ip) = listen.accept().await?;
+        tracing::info!(%ip, "Accepted an admin");
+
+        let span = tracing::info_span!("admin_session", %ip);
+        let handle = handle.clone();
+        tokio::spawn(
+            async move {
+                if let Err(err) = admin_session(tcp, handle).await {
+                    tracing::warn!(error = %err, "Admin session failed");
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
+
+async fn admin_session(mut tcp: TcpStream, mut handle: ServerHandle) -> Result<(), io::Error> {
+    let (read, mut write) = tcp.split();
+    let mut lines = FramedRead::new(read, TelnetCodec::new());
+    write
+        .write_all(b"Admin console of the delivery service, a#hlp lists the commands.\r\n")
+        .await?;
+
+    while let Some(item) = lines.next().await {
+        let command = match item? {
+            Item::Line(line) if line.is_empty() => continue,
+            Item::Line(line) => parse_admin_line(&line),
+            // Telnet negotiation is not needed here
+            Item::SE
+            | Item::DataMark
+            | Item::Break
+            | Item::InterruptProcess
+            | Item::AbortOutput
+            | Item::AreYouThere
+            | Item::GoAhead
+            | Item::SB
+            | Item::Will(_)
+            | Item::Wont(_)
+            | Item::Do(_)
+            | Item::Dont(_) => continue,
+            // The commands of the chat clients
+            _ => None,
+        };
+        let reply = match command {
+            Some(command) => {
+                tracing::info!(?command, "Admin command");
+                run(command, &mut handle).await
+            }
+            None => vec!["Unknown command, a#hlp lists the commands.".to_string()],
+        };
+        for line in reply {
+            write.write_all(line.as_bytes()).await?;
+            write.write_all(&[13, 10]).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks the main loop, returns the lines of the answer.
+async fn run(command: AdminCommand, handle: &mut ServerHandle) -> Vec<String> {
+    match command {
+        AdminCommand::Sessions => {
+            let (resp, sessions) = oneshot::channel();
+            handle.send(ToDelivery::ListSessions { resp }).await;
+            let mut sessions = sessions.await.unwrap_or_default();
+            sessions.sort_by_key(|session| session.id.0);
+            let mut lines: Vec<String> = sessions
+                .into_iter()
+                .map(|session| {
+                    format!(
+                        "{} {} {}, {} queued",
+                        session.id.0,
+                        session.ip,
+                        session.identity.as_deref().unwrap_or("-"),
+                        session.queued
+                    )
+                })
+                .collect();
+            lines.push(format!("{} sessions.", lines.len()));
+            lines
+        }
+        AdminCommand::Groups => {
+            let (resp, groups) = oneshot::channel();
+            handle.send(ToDelivery::ListGroups { resp }).await;
+            let mut groups = groups.await.unwrap_or_default();
+            groups.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+            let mut lines: Vec<String> = groups
+                .into_iter()
+                .map(|group| {
+                    format!(
+                        "{} epoch {}, {} members, {} pending proposals{}",
+                        group.group_id,
+                        group.epoch,
+                        group.members,
+                        group.pending_proposals,
+                        group
+                            .room
+                            .map(|room| format!(", room {}", room))
+                            .unwrap_or_default()
+                    )
+                })
+                .collect();
+            lines.push(format!("{} groups.", lines.len()));
+            lines
+        }
+        AdminCommand::Kick(id) => {
+            let (resp, kicked) = oneshot::channel();
+            handle.send(ToDelivery::Kick { id, resp }).await;
+            match kicked.await.unwrap_or_default() {
+                true => vec![format!("Kicked client {}.", id.0)],
+                false => vec![format!("No client {}.", id.0)],
+            }
+        }
+        AdminCommand::Broadcast(notice) => {
+            let (resp, sent) = oneshot::channel();
+            handle.send(ToDelivery::Broadcast { notice, resp }).await;
+            vec![format!(
+                "Sent the notice to {} clients.",
+                sent.await.unwrap_or_default()
+            )]
+        }
+        AdminCommand::KeyPackages => {
+            let (resp, stats) = oneshot::channel();
+            handle.send(ToDelivery::KeyPackageStats { resp }).await;
+            let mut stats = stats.await.unwrap_or_default();
+            stats.sort_by(|a, b| a.identity.cmp(&b.identity));
+            let total: usize = stats
+                .iter()
+                .flat_map(|stats| stats.counts.iter().map(|(_, count)| count))
+                .sum();
+            let mut lines: Vec<String> = stats
+                .into_iter()
+                .map(|stats| {
+                    let counts: Vec<String> = stats
+                        .counts
+                        .iter()
+                        .map(|(ciphersuite, count)| format!("{} {:?}", count, ciphersuite))
+                        .collect();
+                    format!("{}: {}", stats.identity, counts.join(", "))
+                })
+                .collect();
+            lines.push(format!("{} key packages.", total));
+            lines
+        }
+        AdminCommand::Help => HELP.iter().map(|line| line.to_string()).collect(),
+    }
+}
@@ -0,0 +1,63 @@
+use std::{collections::HashMap, ops::RangeInclusive};
+
+#[cfg(test)]
+mod history_range;
+
+/// An application message of a group, as the delivery service routed it. It
+/// stays encrypted, only the members holding the secrets of its epoch can
+/// read it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchivedMessage {
+    pub epoch: u64,
+    // Order of the message in its group, from 0
+    pub sequence: u64,
+    pub message: Vec<u8>,
+}
+
+/// The ciphertexts of the groups, kept by the delivery service for the
+/// members fetching their history. Append-only, by group, in the order they
+/// were routed.
+#[derive(Debug, Default)]
+pub struct Archive {
+    groups: HashMap<Vec<u8>, Vec<ArchivedMessage>>,
+}
+
+impl Archive {
+    /// Appends a message of `epoch` to the group, returns its sequence.
+    pub fn append(&mut self, group_id: &[u8], epoch: u64, message: Vec<u8>) -> u64 {
+        let messages = self.groups.entry(group_id.to_vec()).or_default();
+        let sequence = messages.len() as u64;
+        messages.push(ArchivedMessage {
+            epoch,
+            sequence,
+            message,
+        });
+
+        sequence
+    }
+
+    /// The last `last` messages of the group sent in `epochs`, before the
+    /// sequence `before` if any, oldest first.
+    pub fn range(
+        &self,
+        group_id: &[u8],
+        epochs: RangeInclusive<u64>,
+        before: Option<u64>,
+        last: usize,
+    ) -> Vec<ArchivedMessage> {
+        let Some(messages) = self.groups.get(group_id) else {
+            return Vec::new();
+        };
+        let end = before.map_or(messages.len(), |before| messages.len().min(before as usize));
+        let mut range: Vec<ArchivedMessage> = messages[..end]
+            .iter()
+            .rev()
+            .filter(|archived| epochs.contains(&archived.epoch))
+            .take(last)
+            .cloned()
+            .collect();
+        range.reverse();
+
+        range
+    }
+}
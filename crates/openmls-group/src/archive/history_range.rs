@@ -0,0 +1,72 @@
+use super::Archive;
+
+/// Two messages in each of the epochs 1 to 3, with sequences 0 to 5.
+fn archive() -> Archive {
+    let mut archive = Archive::default();
+    for epoch in 1..=3 {
+        for _ in 0..2 {
+            archive.append(b"group", epoch, vec![epoch as u8]);
+        }
+    }
+
+    archive
+}
+
+fn sequences(
+    archive: &Archive,
+    epochs: std::ops::RangeInclusive<u64>,
+    before: Option<u64>,
+    last: usize,
+) -> Vec<u64> {
+    archive
+        .range(b"group", epochs, before, last)
+        .into_iter()
+        .map(|archived| archived.sequence)
+        .collect()
+}
+
+#[test]
+fn messages_are_numbered_by_group() {
+    let mut archive = archive();
+
+    assert_eq!(archive.append(b"group", 3, Vec::new()), 6);
+    assert_eq!(archive.append(b"other", 1, Vec::new()), 0);
+}
+
+#[test]
+fn the_last_messages_come_oldest_first() {
+    let archive = archive();
+
+    assert_eq!(sequences(&archive, 0..=u64::MAX, None, 3), [3, 4, 5]);
+    assert_eq!(
+        sequences(&archive, 0..=u64::MAX, None, 10),
+        [0, 1, 2, 3, 4, 5]
+    );
+}
+
+#[test]
+fn only_the_messages_of_the_epochs_are_returned() {
+    let archive = archive();
+
+    assert_eq!(sequences(&archive, 1..=2, None, 10), [0, 1, 2, 3]);
+    assert_eq!(sequences(&archive, 2..=2, None, 1), [3]);
+    assert!(sequences(&archive, 4..=5, None, 10).is_empty());
+}
+
+#[test]
+fn pages_end_before_a_sequence() {
+    let archive = archive();
+
+    assert_eq!(sequences(&archive, 0..=u64::MAX, Some(3), 2), [1, 2]);
+    assert_eq!(sequences(&archive, 2..=3, Some(3), 10), [2]);
+    assert!(sequences(&archive, 0..=u64::MAX, Some(0), 10).is_empty());
+    // Past the end, as if there were none
+    assert_eq!(sequences(&archive, 0..=u64::MAX, Some(100), 1), [5]);
+}
+
+#[test]
+fn an_unknown_group_has_no_history() {
+    let archive = archive();
+
+    assert!(archive.range(b"other", 0..=u64::MAX, None, 10).is_empty());
+}
@@ -1,6 +1,17 @@
-use std::{io, net::SocketAddr};
+use std::{
+    io,
+    net::SocketAddr,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use chat_core::{
+    auth_service::{CredentialValidator, Registry},
+    ext_mls::{ciphersuite_by_name, user_of, CIPHERSUITE},
+};
 use futures::stream::StreamExt;
+use openmls::prelude::Ciphersuite;
 use tokio::{
     io::AsyncWriteExt,
     net::{
@@ -13,20 +24,55 @@ use tokio::{
         oneshot,
     },
     task::JoinHandle,
+    time::interval,
     try_join,
 };
 use tokio_util::codec::FramedRead;
+use tracing::Instrument;
+use uuid::Uuid;
 
 use crate::{
-    main_loop::{ServerHandle, ToDelivery},
-    telnet::{Item, TelnetCodec},
+    archive::ArchivedMessage,
+    main_loop::{Presence, ServerHandle, ToDelivery},
+    session::{Session, SessionError},
+    telnet::{parse_line, Item, TelnetCodec},
     ClientId,
 };
 
+/// How often the session checks whether its self-update is due.
+const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
 /// Messages received from the main loop.
 pub enum FromDelivery {
     // Should be decrypted data
     Message(Vec<u8>),
+    // Serialized Mls messages, decrypted by the session
+    Welcome(Vec<u8>),
+    GroupMessage(Vec<u8>),
+    // Delivery receipt of a line we sent, by its envelope id
+    Delivered { id: Uuid, recipients: usize },
+    // Outcome of our last commit
+    CommitAccepted,
+    CommitRejected,
+    // The AS revoked the credential of a device in our group
+    CredentialRevoked(String),
+}
+
+/// Checks the access tokens of the clients logging in, e.g. those of the AS.
+pub trait TokenVerifier: Send + Sync {
+    /// The user, or device, the token was issued to.
+    fn verify(&self, token: &str) -> Option<String>;
+}
+
+/// How a client talks to its actor.
+pub enum Transport {
+    Telnet(TcpStream),
+    // Lines already framed by the caller, e.g. WebSocket text messages, and
+    // the lines to send back
+    Lines {
+        incoming: UnboundedReceiver<String>,
+        outgoing: UnboundedSender<String>,
+    },
 }
 
 /// This struct is constructed by the accept loop and used as the argument to
@@ -35,21 +81,32 @@ pub struct ClientInfo {
     pub id: ClientId,
     pub ip: SocketAddr,
     pub handle: ServerHandle,
-    pub tcp: TcpStream,
+    pub transport: Transport,
+    pub registry: Arc<Registry>,
+    // Checks the leaves of the others, the registry if none
+    pub validator: Option<Arc<dyn CredentialValidator>>,
+    // Authenticated by the AS, the session is a device of this user
+    pub user: Option<String>,
+    // Lets the client log in with an access token, if any
+    pub verifier: Option<Arc<dyn TokenVerifier>>,
 }
 
 struct ClientData {
     id: ClientId,
     handle: ServerHandle,
+    registry: Arc<Registry>,
+    validator: Option<Arc<dyn CredentialValidator>>,
     recv: Receiver<FromDelivery>,
-    tcp: TcpStream,
+    transport: Transport,
+    user: Option<String>,
+    verifier: Option<Arc<dyn TokenVerifier>>,
 }
 
 /// A handle to this actor, used by the server.
 #[derive(Debug)]
 pub struct ClientHandle {
     pub id: ClientId,
-    ip: SocketAddr,
+    pub ip: SocketAddr,
     chan: Sender<FromDelivery>,
     kill: JoinHandle<()>,
 }
@@ -65,6 +122,17 @@ impl ClientHandle {
             Ok(())
         }
     }
+    /// Whether the actor still runs, the main loop keeps the handles of the
+    /// clients that left.
+    pub fn is_connected(&self) -> bool {
+        !self.kill.is_finished()
+    }
+
+    /// The messages waiting to be written to the client.
+    pub fn queued(&self) -> usize {
+        self.chan.max_capacity() - self.chan.capacity()
+    }
+
     /// Kill the actor.
     pub fn kill(self) {
         // run the destructor
@@ -84,13 +152,24 @@ pub fn spawn_client(info: ClientInfo) {
     let data = ClientData {
         id: info.id,
         handle: info.handle.clone(),
-        tcp: info.tcp,
+        transport: info.transport,
+        registry: info.registry,
+        validator: info.validator,
+        user: info.user,
+        verifier: info.verifier,
         recv,
     };
 
-    // This spawns the new task.
+    // This spawns the new task, everything it logs is in the span of the
+    // session.
     let (my_send, my_recv) = oneshot::channel();
-    let kill = tokio::spawn(start_client(my_recv, data));
+    let span = tracing::info_span!(
+        "session",
+        client_id = %info.id,
+        ip = %info.ip,
+        identity = tracing::field::Empty
+    );
+    let kill = tokio::spawn(start_client(my_recv, data).instrument(span));
 
     // Then we create a ClientHandle to this new task, and use the oneshot
     // channel to send it to the task.
@@ -117,55 +196,251 @@ async fn start_client(my_handle: oneshot::Receiver<ClientHandle>, mut data: Clie
         Err(_) => return,
     };
     data.handle.send(ToDelivery::NewClient(my_handle)).await;
+    // Until the session ends, or the server kills it
+    let _session = data.handle.metrics().session_opened();
+    tracing::info!("Session opened");
 
-    // We sent the client handle to the main loop. Start talking to the tcp
+    // We sent the client handle to the main loop. Start talking to the
     // connection.
+    let (id, mut handle) = (data.id, data.handle.clone());
     let res = client_loop(data).await;
     match res {
-        Ok(()) => {}
-        Err(err) => {
-            eprintln!("Something went wrong: {}.", err);
-        }
+        Ok(()) => tracing::info!("Session closed"),
+        Err(err) => tracing::error!(error = %err, "Session failed"),
     }
+    // Its groups see it go offline
+    handle.send(ToDelivery::ClientLeft(id)).await;
 }
 
-/// This method performs the actual job of running the client actor.
-async fn client_loop(mut data: ClientData) -> Result<(), io::Error> {
-    let (read, write) = data.tcp.split();
+/// Records the device of the session on its span.
+fn signed_in(identity: &str) {
+    tracing::Span::current().record("identity", identity);
+    tracing::info!(identity, "Signed in");
+}
 
-    // communication between tcp_read and tcp_write
+/// This method performs the actual job of running the client actor.
+async fn client_loop(data: ClientData) -> Result<(), io::Error> {
+    // communication between client_read and client_write
     let (send, recv) = unbounded_channel();
 
-    let ((), ()) = try_join! {
-        tcp_read(data.id, read, data.handle, send),
-        tcp_write(write, data.recv, recv),
-    }?;
+    // Mls state shared by both halves: commands are run by client_read and
+    // messages from the delivery service are decrypted by client_write.
+    let mut session = Session::new(data.id, data.registry);
+    if let Some(validator) = data.validator {
+        session = session.with_validator(validator);
+    }
+    if let Some(user) = &data.user {
+        let identity = session.sign_in(user).map_err(io::Error::other)?;
+        signed_in(&identity);
+        send.send(InternalMsg::Notice(format!("You are device {}.", identity)))
+            .expect("Should not be closed.");
+    }
+    let session = Mutex::new(session);
+
+    match data.transport {
+        Transport::Telnet(mut tcp) => {
+            let (read, write) = tcp.split();
+            let input = Input::Telnet(FramedRead::new(read, TelnetCodec::new()));
+
+            let ((), ()) = try_join! {
+                client_read(data.id, input, data.handle.clone(), &session, data.verifier, send),
+                client_write(Output::Telnet(write), data.recv, recv, data.handle, &session),
+            }?;
 
-    let _ = data.tcp.shutdown().await;
+            let _ = tcp.shutdown().await;
+        }
+        Transport::Lines { incoming, outgoing } => {
+            let ((), ()) = try_join! {
+                client_read(
+                    data.id,
+                    Input::Lines(incoming),
+                    data.handle.clone(),
+                    &session,
+                    data.verifier,
+                    send,
+                ),
+                client_write(Output::Lines(outgoing), data.recv, recv, data.handle, &session),
+            }?;
+        }
+    }
 
     Ok(())
 }
 
+/// Commands of the client, parsed from its transport.
+enum Input<'a> {
+    Telnet(FramedRead<ReadHalf<'a>, TelnetCodec>),
+    Lines(UnboundedReceiver<String>),
+}
+
+impl Input<'_> {
+    async fn next(&mut self) -> Option<Result<Item, io::Error>> {
+        match self {
+            Input::Telnet(telnet) => telnet.next().await,
+            Input::Lines(lines) => {
+                let line = lines.recv().await?;
+                parse_line(line.into_bytes()).map(Ok)
+            }
+        }
+    }
+}
+
+/// Where the lines for the client are written.
+enum Output<'a> {
+    Telnet(WriteHalf<'a>),
+    Lines(UnboundedSender<String>),
+}
+
+impl Output<'_> {
+    async fn write_line(&mut self, line: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Output::Telnet(write) => {
+                write.write_all(line).await?;
+                write.write_all(&[13, 10]).await
+            }
+            Output::Lines(lines) => lines
+                .send(String::from_utf8_lossy(line).into_owned())
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client is gone")),
+        }
+    }
+
+    /// Telnet option negotiation, only for telnet clients.
+    async fn write_command(&mut self, command: &[u8]) -> Result<(), io::Error> {
+        match self {
+            Output::Telnet(write) => write.write_all(command).await,
+            Output::Lines(_) => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum InternalMsg {
     GotAreYouThere,
     SendDont(u8),
     SendWont(u8),
     SendDo(u8),
+    Notice(String),
 }
 
-async fn tcp_read(
+/// Forwards the messages produced by a session command to the main loop, or
+/// tells the user why the command failed.
+async fn forward(
+    handle: &mut ServerHandle,
+    to_write: &UnboundedSender<InternalMsg>,
+    outgoing: Result<Vec<ToDelivery>, SessionError>,
+    notice: Option<String>,
+) {
+    let notice = match outgoing {
+        Ok(outgoing) => {
+            for msg in outgoing {
+                handle.send(msg).await;
+            }
+            notice
+        }
+        Err(err) => Some(err.to_string()),
+    };
+
+    if let Some(notice) = notice {
+        to_write
+            .send(InternalMsg::Notice(notice))
+            .expect("Should not be closed.");
+    }
+}
+
+/// Claims a key package of `ciphersuite` of each identity, of each device for
+/// a user. Returns the claimed ones and the identities without key package.
+async fn claim_key_packages(
+    handle: &mut ServerHandle,
+    identities: Vec<String>,
+    ciphersuite: Ciphersuite,
+) -> (Vec<(String, Vec<u8>)>, Vec<String>) {
+    let (resp, claimed) = oneshot::channel();
+    handle
+        .send(ToDelivery::ClaimKeyPackages {
+            identities: identities.clone(),
+            ciphersuite,
+            resp,
+        })
+        .await;
+    let claimed = claimed.await.unwrap_or_default();
+    let missing = identities
+        .into_iter()
+        .filter(|identity| {
+            claimed.iter().all(|(device, _)| {
+                device != identity && user_of(device.as_bytes()) != identity.as_bytes()
+            })
+        })
+        .collect();
+
+    (claimed, missing)
+}
+
+/// Fetches the archived messages of the group page by page, back from the
+/// last one, until they hold `last` lines or the archive has no more.
+async fn fetch_history(
+    handle: &mut ServerHandle,
+    session: &Mutex<Session>,
     id: ClientId,
-    read: ReadHalf<'_>,
+    group_id: Vec<u8>,
+    epochs: RangeInclusive<u64>,
+    last: usize,
+) -> Vec<ArchivedMessage> {
+    let mut history = Vec::new();
+    let mut before = None;
+    while session.lock().unwrap().lines_in(&history) < last {
+        let (resp, page) = oneshot::channel();
+        handle
+            .send(ToDelivery::FetchHistory {
+                from: id,
+                group_id: group_id.clone(),
+                epochs: epochs.clone(),
+                before,
+                last,
+                resp,
+            })
+            .await;
+        let mut page = page.await.unwrap_or_default();
+        let Some(first) = page.first() else {
+            break;
+        };
+        before = Some(first.sequence);
+        page.append(&mut history);
+        history = page;
+    }
+
+    history
+}
+
+/// The members of the group with their presence, as the delivery service
+/// sees them. Members it does not know of are offline.
+async fn who(handle: &mut ServerHandle, group_id: Vec<u8>, members: Vec<String>) -> Vec<String> {
+    let (resp, presences) = oneshot::channel();
+    handle.send(ToDelivery::Who { group_id, resp }).await;
+    let presences = presences.await.unwrap_or_default();
+
+    let mut lines = vec!["Members of the group:".to_string()];
+    lines.extend(members.into_iter().map(|member| {
+        let presence = presences
+            .iter()
+            .find(|(identity, _)| *identity == member)
+            .map_or(Presence::Offline, |(_, presence)| *presence);
+        format!("{}, {}", member, presence)
+    }));
+    lines
+}
+
+async fn client_read(
+    id: ClientId,
+    mut input: Input<'_>,
     mut handle: ServerHandle,
-    to_tcp_write: UnboundedSender<InternalMsg>,
+    session: &Mutex<Session>,
+    verifier: Option<Arc<dyn TokenVerifier>>,
+    to_write: UnboundedSender<InternalMsg>,
 ) -> Result<(), io::Error> {
-    let mut telnet = FramedRead::new(read, TelnetCodec::new());
-
-    while let Some(item) = telnet.next().await {
+    while let Some(item) = input.next().await {
         match item? {
             Item::AreYouThere => {
-                to_tcp_write
+                to_write
                     .send(InternalMsg::GotAreYouThere)
                     .expect("Should not be closed.");
             }
@@ -173,34 +448,259 @@ async fn tcp_read(
             Item::InterruptProcess => return Ok(()),
             Item::Will(3) => {
                 // suppress go-ahead
-                to_tcp_write
+                to_write
                     .send(InternalMsg::SendDo(3))
                     .expect("Should not be closed.");
             }
             Item::Will(i) => {
-                to_tcp_write
+                to_write
                     .send(InternalMsg::SendDont(i))
                     .expect("Should not be closed.");
             }
             Item::Do(i) => {
-                to_tcp_write
+                to_write
                     .send(InternalMsg::SendWont(i))
                     .expect("Should not be closed.");
             }
             Item::Line(line) => {
-                handle.send(ToDelivery::Message(id, line)).await;
+                let in_group = session.lock().unwrap().in_group();
+                if in_group {
+                    let outgoing = session.lock().unwrap().send(&line);
+                    forward(&mut handle, &to_write, outgoing, None).await;
+                } else {
+                    handle.send(ToDelivery::Message(id, line)).await;
+                }
             }
             Item::ShowKPDetails => {
-                println!("Todo");
+                tracing::warn!("Showing the key package details is not implemented");
+            }
+            Item::SetUser(user) => {
+                let notice = match session.lock().unwrap().set_user(&user) {
+                    Ok(identity) => {
+                        signed_in(&identity);
+                        format!("You are now device {}.", identity)
+                    }
+                    Err(err) => err.to_string(),
+                };
+                forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
+            }
+            Item::Login(token) => {
+                let notice = match verifier.as_ref().map(|verifier| verifier.verify(&token)) {
+                    None => "Logging in is not available.".to_string(),
+                    Some(None) => "The access token is not valid.".to_string(),
+                    Some(Some(subject)) => {
+                        let user = String::from_utf8_lossy(user_of(subject.as_bytes()));
+                        match session.lock().unwrap().sign_in(&user) {
+                            Ok(identity) => {
+                                signed_in(&identity);
+                                format!("You are device {}.", identity)
+                            }
+                            Err(err) => err.to_string(),
+                        }
+                    }
+                };
+                forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
             }
             Item::PublishKeyPackage => {
-                println!("[Client] Publishing key package of client: {}", id);
+                tracing::info!("Publishing key packages");
+                let outgoing = session.lock().unwrap().publish_key_packages();
+                let identity = session.lock().unwrap().identity();
+                forward(
+                    &mut handle,
+                    &to_write,
+                    outgoing,
+                    Some(format!("Published key packages as {}.", identity)),
+                )
+                .await;
+            }
+            Item::CreateGroup(name, identities) => {
+                let Some(ciphersuite) = ciphersuite_by_name(&name) else {
+                    let notice = format!("Unknown ciphersuite `{}`, pick x25519 or p256.", name);
+                    forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
+                    continue;
+                };
+                let (claimed, missing) =
+                    claim_key_packages(&mut handle, identities, ciphersuite).await;
+                let outgoing = session.lock().unwrap().create_group(ciphersuite, claimed);
+                let mut notice = "Created a group.".to_string();
+                if !missing.is_empty() {
+                    notice.push_str(&format!(" No key package for: {}.", missing.join(" ")));
+                }
+                forward(&mut handle, &to_write, outgoing, Some(notice)).await;
+            }
+            Item::CreateRoomGroup(name, identities) => {
+                let identity = session.lock().unwrap().identity();
+                let (resp, room) = oneshot::channel();
+                handle
+                    .send(ToDelivery::OpenRoom {
+                        identity,
+                        name: name.clone(),
+                        resp,
+                    })
+                    .await;
+                let room = match room.await {
+                    Ok(Ok(room)) => room,
+                    Ok(Err(err)) => {
+                        forward(
+                            &mut handle,
+                            &to_write,
+                            Ok(Vec::new()),
+                            Some(err.to_string()),
+                        )
+                        .await;
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+                // Their key packages would be wasted, the DS rejects them.
+                let (identities, refused): (Vec<_>, Vec<_>) = identities
+                    .into_iter()
+                    .partition(|identity| room.allows(identity));
+                let (claimed, missing) =
+                    claim_key_packages(&mut handle, identities, CIPHERSUITE).await;
+                let outgoing = session.lock().unwrap().create_room_group(&room, claimed);
+                let mut notice = format!("Created the group of room {}.", room.name);
+                if !refused.is_empty() {
+                    notice.push_str(&format!(" Not allowed in the room: {}.", refused.join(" ")));
+                }
+                if !missing.is_empty() {
+                    notice.push_str(&format!(" No key package for: {}.", missing.join(" ")));
+                }
+                forward(&mut handle, &to_write, outgoing, Some(notice)).await;
+            }
+            Item::ProposeAdd(identities) => {
+                let ciphersuite = session.lock().unwrap().ciphersuite();
+                let (claimed, missing) =
+                    claim_key_packages(&mut handle, identities, ciphersuite).await;
+                let mut notice = format!("Proposed to add {} members.", claimed.len());
+                if !missing.is_empty() {
+                    notice.push_str(&format!(" No key package for: {}.", missing.join(" ")));
+                }
+                let outgoing = session.lock().unwrap().propose_add(claimed);
+                forward(&mut handle, &to_write, outgoing, Some(notice)).await;
+            }
+            Item::ProposeRemove(identities) => {
+                let notice = format!("Proposed to remove {}.", identities.join(" "));
+                let outgoing = session.lock().unwrap().propose_remove(&identities);
+                forward(&mut handle, &to_write, outgoing, Some(notice)).await;
+            }
+            Item::ProposeUpdate => {
+                let outgoing = session.lock().unwrap().propose_update();
+                let notice = "Proposed to update your leaf.".to_string();
+                forward(&mut handle, &to_write, outgoing, Some(notice)).await;
+            }
+            Item::CommitProposals => {
+                let outgoing = session.lock().unwrap().commit_proposals();
+                forward(&mut handle, &to_write, outgoing, None).await;
+            }
+            Item::RemoveMembers(identities) => {
+                let outgoing = session.lock().unwrap().remove(&identities);
+                forward(&mut handle, &to_write, outgoing, None).await;
+            }
+            Item::LeaveGroup => {
+                let outgoing = session.lock().unwrap().leave().map(|msg| vec![msg]);
+                let notice = "Asked the group to remove you.".to_string();
+                forward(&mut handle, &to_write, outgoing, Some(notice)).await;
+            }
+            Item::JoinExternal(group) => {
+                let identity = session.lock().unwrap().identity();
+                let (resp, group_info) = oneshot::channel();
+                handle
+                    .send(ToDelivery::FetchGroupInfo {
+                        identity,
+                        group_id: group.clone().into_bytes(),
+                        resp,
+                    })
+                    .await;
+
+                let Some(group_info) = group_info.await.ok().flatten() else {
+                    let notice = format!("You are not allowed to join group {}.", group);
+                    forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
+                    continue;
+                };
+                let outgoing = session.lock().unwrap().join_external(&group_info);
+                forward(&mut handle, &to_write, outgoing, None).await;
+            }
+            Item::AllowExternalJoin(identities) => {
+                let notice = format!("Allowed {} to join.", identities.join(" "));
+                let outgoing = session.lock().unwrap().allow(identities);
+                forward(
+                    &mut handle,
+                    &to_write,
+                    outgoing.map(|msg| vec![msg]),
+                    Some(notice),
+                )
+                .await;
+            }
+            Item::Rotate => {
+                let rotated = session.lock().unwrap().rotate();
+                let (notice, outgoing) = match rotated {
+                    Ok((notice, outgoing)) => (notice, Ok(outgoing)),
+                    Err(err) => (None, Err(err)),
+                };
+                forward(&mut handle, &to_write, outgoing, notice).await;
+            }
+            Item::Revoke(identity) => {
+                let notice = match session.lock().unwrap().revoke(&identity) {
+                    Ok(notice) => notice,
+                    Err(err) => err.to_string(),
+                };
+                forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
+            }
+            Item::Away => {
+                handle
+                    .send(ToDelivery::SetPresence {
+                        from: id,
+                        presence: Presence::Away,
+                    })
+                    .await;
+                let notice = "You are away, /back when you are back.".to_string();
+                forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
+            }
+            Item::Back => {
+                handle
+                    .send(ToDelivery::SetPresence {
+                        from: id,
+                        presence: Presence::Online,
+                    })
+                    .await;
+                let notice = "You are back.".to_string();
+                forward(&mut handle, &to_write, Ok(Vec::new()), Some(notice)).await;
+            }
+            Item::Typing => {
+                let outgoing = session.lock().unwrap().send_typing().map(|msg| vec![msg]);
+                forward(&mut handle, &to_write, outgoing, None).await;
+            }
+            Item::History(last) => {
+                let range = session.lock().unwrap().history_range();
+                let lines = match range {
+                    Ok((group_id, epochs)) => {
+                        let history =
+                            fetch_history(&mut handle, session, id, group_id, epochs, last).await;
+                        session.lock().unwrap().read_history(history, last)
+                    }
+                    Err(err) => vec![err.to_string()],
+                };
+                for line in lines {
+                    to_write
+                        .send(InternalMsg::Notice(line))
+                        .expect("Should not be closed.");
+                }
+            }
+            Item::Who => {
+                let members = session.lock().unwrap().members();
+                let lines = match members {
+                    Ok((group_id, members)) => who(&mut handle, group_id, members).await,
+                    Err(err) => vec![err.to_string()],
+                };
+                for line in lines {
+                    to_write
+                        .send(InternalMsg::Notice(line))
+                        .expect("Should not be closed.");
+                }
             }
             item => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Unable to handle {:?}", item),
-                ));
+                return Err(io::Error::other(format!("Unable to handle {:?}", item)));
             }
         }
     }
@@ -210,34 +710,125 @@ async fn tcp_read(
     Ok(())
 }
 
-async fn tcp_write(
-    mut write: WriteHalf<'_>,
+async fn client_write(
+    mut write: Output<'_>,
     mut recv: Receiver<FromDelivery>,
-    mut from_tcp_read: UnboundedReceiver<InternalMsg>,
+    mut from_read: UnboundedReceiver<InternalMsg>,
+    mut handle: ServerHandle,
+    session: &Mutex<Session>,
 ) -> Result<(), io::Error> {
+    let mut update_check = interval(UPDATE_CHECK_INTERVAL);
+
     loop {
         select! {
+            _ = update_check.tick() => {
+                let rotation = session.lock().unwrap().rotate_if_due();
+                match rotation {
+                    Ok(Some((line, outgoing))) => {
+                        for msg in outgoing {
+                            handle.send(msg).await;
+                        }
+                        write.write_line(line.as_bytes()).await?;
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        write.write_line(err.to_string().as_bytes()).await?;
+                    }
+                }
+                let update = session.lock().unwrap().update_if_due();
+                match update {
+                    Ok(Some(msg)) => handle.send(msg).await,
+                    Ok(None) => {}
+                    Err(err) => {
+                        write.write_line(err.to_string().as_bytes()).await?;
+                    }
+                }
+            },
             msg = recv.recv() => match msg {
                 Some(FromDelivery::Message(msg)) => {
-                    write.write_all(&msg).await?;
-                    write.write_all(&[13, 10]).await?;
+                    write.write_line(&msg).await?;
+                },
+                Some(FromDelivery::Welcome(welcome)) => {
+                    let joined = session.lock().unwrap().join(&welcome);
+                    let line = match joined {
+                        Ok(group) => format!("Joined group {}.", group),
+                        Err(err) => err.to_string(),
+                    };
+                    write.write_line(line.as_bytes()).await?;
+                },
+                Some(FromDelivery::GroupMessage(msg)) => {
+                    let received = session.lock().unwrap().receive(&msg);
+                    let line = match received {
+                        Ok((line, outgoing)) => {
+                            for msg in outgoing {
+                                handle.send(msg).await;
+                            }
+                            line
+                        }
+                        Err(err) => Some(err.to_string()),
+                    };
+                    if let Some(line) = line {
+                        write.write_line(line.as_bytes()).await?;
+                    }
+                },
+                Some(FromDelivery::Delivered { id, recipients }) => {
+                    let line = session.lock().unwrap().delivered(id, recipients);
+                    if let Some(line) = line {
+                        write.write_line(line.as_bytes()).await?;
+                    }
+                },
+                Some(FromDelivery::CommitAccepted) => {
+                    let accepted = session.lock().unwrap().commit_accepted();
+                    let line = match accepted {
+                        Ok((line, group_info)) => {
+                            handle.send(group_info).await;
+                            line
+                        }
+                        Err(err) => Some(err.to_string()),
+                    };
+                    if let Some(line) = line {
+                        write.write_line(line.as_bytes()).await?;
+                    }
+                },
+                Some(FromDelivery::CredentialRevoked(identity)) => {
+                    let revoked = session.lock().unwrap().credential_revoked(&identity);
+                    let line = match revoked {
+                        Ok((line, outgoing)) => {
+                            for msg in outgoing {
+                                handle.send(msg).await;
+                            }
+                            line
+                        }
+                        Err(err) => Some(err.to_string()),
+                    };
+                    if let Some(line) = line {
+                        write.write_line(line.as_bytes()).await?;
+                    }
+                },
+                Some(FromDelivery::CommitRejected) => {
+                    let rejected = session.lock().unwrap().commit_rejected();
+                    let line = rejected.unwrap_or_else(|err| err.to_string());
+                    write.write_line(line.as_bytes()).await?;
                 },
                 None => {
                     break;
                 },
             },
-            msg = from_tcp_read.recv() => match msg {
+            msg = from_read.recv() => match msg {
                 Some(InternalMsg::GotAreYouThere) => {
-                    write.write_all(b"Yes.\r\n").await?;
+                    write.write_line(b"Yes.").await?;
                 },
                 Some(InternalMsg::SendDont(i)) => {
-                    write.write_all(&[0xff, 254, i]).await?;
+                    write.write_command(&[0xff, 254, i]).await?;
                 },
                 Some(InternalMsg::SendWont(i)) => {
-                    write.write_all(&[0xff, 252, i]).await?;
+                    write.write_command(&[0xff, 252, i]).await?;
                 },
                 Some(InternalMsg::SendDo(i)) => {
-                    write.write_all(&[0xff, 253, i]).await?;
+                    write.write_command(&[0xff, 253, i]).await?;
+                },
+                Some(InternalMsg::Notice(notice)) => {
+                    write.write_line(notice.as_bytes()).await?;
                 },
                 None => {
                     break;
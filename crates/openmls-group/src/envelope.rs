@@ -0,0 +1,64 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[cfg(test)]
+mod serialization;
+
+/// What an application message of a group carries.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Content {
+    // A line of the chat
+    Text { text: String },
+    // The sender is typing a line
+    Typing,
+    // The recipient read the lines of these ids
+    Read { ids: Vec<Uuid> },
+}
+
+/// The typed content of an application message, serialized inside it. The
+/// members check the group id and the sender leaf against the ones of the
+/// Mls message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub id: Uuid,
+    pub group_id: Vec<u8>,
+    // Leaf index of the sender in the group
+    pub sender: u32,
+    // Milliseconds since the Unix epoch, by the clock of the sender
+    pub sent_at: u64,
+    pub content: Content,
+}
+
+impl Envelope {
+    pub fn new(group_id: Vec<u8>, sender: u32, content: Content) -> Self {
+        let sent_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        Self {
+            id: Uuid::new_v4(),
+            group_id,
+            sender,
+            sent_at,
+            content,
+        }
+    }
+
+    /// The time of day it was sent, `HH:MM` UTC, as the lines show it.
+    pub fn time(&self) -> String {
+        let minutes = self.sent_at / 60_000;
+
+        format!("{:02}:{:02}", minutes / 60 % 24, minutes % 60)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("An envelope is always serializable.")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        serde_json::from_slice(bytes).ok()
+    }
+}
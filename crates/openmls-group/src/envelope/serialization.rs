@@ -0,0 +1,57 @@
+use uuid::Uuid;
+
+use super::{Content, Envelope};
+
+#[test]
+fn an_envelope_is_read_back_from_its_bytes() {
+    let contents = [
+        Content::Text {
+            text: "hello".to_string(),
+        },
+        Content::Typing,
+        Content::Read {
+            ids: vec![Uuid::new_v4(), Uuid::new_v4()],
+        },
+    ];
+
+    for content in contents {
+        let envelope = Envelope::new(b"group".to_vec(), 1, content);
+
+        assert_eq!(Envelope::from_bytes(&envelope.to_bytes()), Some(envelope));
+    }
+}
+
+#[test]
+fn the_content_is_tagged_by_its_type() {
+    let envelope = Envelope::new(b"group".to_vec(), 0, Content::Typing);
+
+    let json: serde_json::Value = serde_json::from_slice(&envelope.to_bytes()).unwrap();
+
+    assert_eq!(json["content"], serde_json::json!({ "type": "typing" }));
+}
+
+#[test]
+fn other_bytes_are_not_an_envelope() {
+    let envelope =
+        serde_json::to_value(Envelope::new(b"group".to_vec(), 0, Content::Typing)).unwrap();
+    let mut unknown_type = envelope.clone();
+    unknown_type["content"]["type"] = "shout".into();
+    let mut missing_sender = envelope.clone();
+    missing_sender.as_object_mut().unwrap().remove("sender");
+
+    let test_cases = [
+        (b"hello".to_vec(), "a plain line"),
+        (Vec::new(), "empty"),
+        (unknown_type.to_string().into_bytes(), "of an unknown type"),
+        (missing_sender.to_string().into_bytes(), "without a sender"),
+    ];
+
+    for (bytes, description) in test_cases {
+        assert_eq!(
+            Envelope::from_bytes(&bytes),
+            None,
+            "Bytes {} were read as an envelope.",
+            description
+        );
+    }
+}
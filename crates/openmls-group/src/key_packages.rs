@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use chat_core::{
+    auth_service::{CredentialError, CredentialValidator, Registry},
+    ext_mls::{
+        identity_of, read_key_package, user_of, validate_credential, ChatError, MemoryProvider,
+    },
+};
+use openmls::prelude::{Ciphersuite, KeyPackage};
+
+#[derive(thiserror::Error, Debug)]
+pub enum DirectoryError {
+    #[error("Invalid key package: {0}")]
+    InvalidKeyPackage(#[from] ChatError),
+    #[error("A key package of `{0}` can not be published for `{1}`")]
+    IdentityMismatch(String, String),
+    #[error(transparent)]
+    Credential(#[from] CredentialError),
+}
+
+/// A published key package, kept serialized as it is handed out.
+#[derive(Debug, Clone)]
+struct Published {
+    ciphersuite: Ciphersuite,
+    key_package: Vec<u8>,
+}
+
+impl Published {
+    /// Verifies the signature and the lifetime of a serialized key package.
+    fn read(key_package: &[u8]) -> Result<(KeyPackage, Self), DirectoryError> {
+        let parsed = read_key_package(&MemoryProvider::default(), key_package)?;
+        let published = Self {
+            ciphersuite: parsed.ciphersuite(),
+            key_package: key_package.to_vec(),
+        };
+
+        Ok((parsed, published))
+    }
+
+    /// Whether the key package can still be used, its lifetime not over.
+    fn is_valid(&self) -> bool {
+        read_key_package(&MemoryProvider::default(), &self.key_package).is_ok()
+    }
+}
+
+/// The key packages of a device.
+#[derive(Debug, Default)]
+struct Pool {
+    // Each used once
+    key_packages: Vec<Published>,
+    // Handed out when no other is left, by ciphersuite
+    last_resort: HashMap<Ciphersuite, Published>,
+}
+
+impl Pool {
+    /// Takes a key package of `ciphersuite`, expired ones are dropped.
+    fn claim(&mut self, ciphersuite: Ciphersuite) -> Option<(Vec<u8>, bool)> {
+        self.key_packages.retain(Published::is_valid);
+        self.last_resort.retain(|_, published| published.is_valid());
+
+        match self
+            .key_packages
+            .iter()
+            .position(|published| published.ciphersuite == ciphersuite)
+        {
+            Some(position) => Some((self.key_packages.remove(position).key_package, false)),
+            None => self
+                .last_resort
+                .get(&ciphersuite)
+                .map(|published| (published.key_package.clone(), true)),
+        }
+    }
+}
+
+/// A key package handed out by the directory, TLS serialized.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClaimedKeyPackage {
+    pub identity: String,
+    pub key_package: Vec<u8>,
+    // Stays in the directory, handed out again until replaced
+    pub last_resort: bool,
+}
+
+/// The key packages left of an identity, for the admin console.
+#[derive(Clone, Debug)]
+pub struct KeyPackageStats {
+    pub identity: String,
+    // By ciphersuite
+    pub counts: Vec<(Ciphersuite, usize)>,
+}
+
+/// Key packages published by the devices, by identity. Shared by the web API
+/// and the delivery service, the clients of both claim from the same pools.
+#[derive(Debug)]
+pub struct KeyPackageDirectory {
+    pools: Mutex<HashMap<String, Pool>>,
+    // The AS, a key package is signed with the key it registered
+    validator: Arc<dyn CredentialValidator>,
+}
+
+/// Trusts no identity, nothing can be published.
+impl Default for KeyPackageDirectory {
+    fn default() -> Self {
+        Self::new(Arc::new(Registry::default()))
+    }
+}
+
+impl KeyPackageDirectory {
+    /// A directory of the key packages signed with the keys `validator`
+    /// trusts.
+    pub fn new(validator: Arc<dyn CredentialValidator>) -> Self {
+        Self {
+            pools: Mutex::default(),
+            validator,
+        }
+    }
+
+    /// Publishes a batch of serialized key packages of `identity`, all or
+    /// none. Each must be signed with the signature key the AS registered for
+    /// the identity, and within its lifetime. The chain of an X.509 one must
+    /// end at the trust anchor of the AS. A last resort key package
+    /// replaces the previous one of its ciphersuite.
+    pub fn publish(
+        &self,
+        identity: &str,
+        key_packages: &[Vec<u8>],
+    ) -> Result<usize, DirectoryError> {
+        let mut batch = Vec::with_capacity(key_packages.len());
+        for key_package in key_packages {
+            let (parsed, published) = Published::read(key_package)?;
+            let leaf_node = parsed.leaf_node();
+            let owner = identity_of(leaf_node.credential());
+            if owner != identity.as_bytes() {
+                return Err(DirectoryError::IdentityMismatch(
+                    String::from_utf8_lossy(&owner).into_owned(),
+                    identity.to_string(),
+                ));
+            }
+            validate_credential(
+                self.validator.as_ref(),
+                leaf_node.credential(),
+                leaf_node.signature_key().as_slice(),
+            )
+            .map_err(|err| match err {
+                ChatError::InvalidCredential(err) => DirectoryError::Credential(err),
+                err => DirectoryError::InvalidKeyPackage(err),
+            })?;
+            batch.push((parsed.last_resort(), published));
+        }
+
+        let mut pools = self.pools.lock().unwrap();
+        let pool = pools.entry(identity.to_string()).or_default();
+        for (last_resort, published) in batch {
+            if last_resort {
+                pool.last_resort.insert(published.ciphersuite, published);
+            } else {
+                pool.key_packages.push(published);
+            }
+        }
+
+        Ok(key_packages.len())
+    }
+
+    /// Claims a key package of `ciphersuite` of each identity, of each
+    /// device for a user. The key packages are taken under one lock, two
+    /// claims never get the same one, but last resort ones.
+    pub fn claim(&self, identities: &[String], ciphersuite: Ciphersuite) -> Vec<ClaimedKeyPackage> {
+        let mut pools = self.pools.lock().unwrap();
+
+        pools
+            .iter_mut()
+            .filter(|(device, _)| {
+                identities.iter().any(|identity| {
+                    *device == identity || user_of(device.as_bytes()) == identity.as_bytes()
+                })
+            })
+            .filter_map(|(device, pool)| {
+                let (key_package, last_resort) = pool.claim(ciphersuite)?;
+                Some(ClaimedKeyPackage {
+                    identity: device.clone(),
+                    key_package,
+                    last_resort,
+                })
+            })
+            .collect()
+    }
+
+    /// Drops the key packages of `identity`, e.g. revoked, they can no
+    /// longer be added to a group.
+    pub fn remove(&self, identity: &str) {
+        self.pools.lock().unwrap().remove(identity);
+    }
+
+    /// The number of key packages of `ciphersuite` left for `identity`, and
+    /// whether it has a last resort one.
+    pub fn count(&self, identity: &str, ciphersuite: Ciphersuite) -> (usize, bool) {
+        let pools = self.pools.lock().unwrap();
+        let Some(pool) = pools.get(identity) else {
+            return (0, false);
+        };
+        let count = pool
+            .key_packages
+            .iter()
+            .filter(|published| published.ciphersuite == ciphersuite)
+            .count();
+
+        (count, pool.last_resort.contains_key(&ciphersuite))
+    }
+
+    /// The number of key packages left to claim, of every identity, but the
+    /// last resort ones.
+    pub fn total(&self) -> usize {
+        let pools = self.pools.lock().unwrap();
+
+        pools.values().map(|pool| pool.key_packages.len()).sum()
+    }
+
+    /// The key packages left of each identity, by ciphersuite, but the last
+    /// resort ones.
+    pub fn stats(&self) -> Vec<KeyPackageStats> {
+        let pools = self.pools.lock().unwrap();
+
+        pools
+            .iter()
+            .map(|(identity, pool)| {
+                let mut counts: Vec<(Ciphersuite, usize)> = Vec::new();
+                for published in &pool.key_packages {
+                    match counts
+                        .iter_mut()
+                        .find(|(known, _)| *known == published.ciphersuite)
+                    {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((published.ciphersuite, 1)),
+                    }
+                }
+                KeyPackageStats {
+                    identity: identity.clone(),
+                    counts,
+                }
+            })
+            .collect()
+    }
+}
@@ -1,19 +1,66 @@
-use openmls_group::{accept::start_accept, main_loop::spawn_main_loop};
+use std::sync::Arc;
+
+use chat_core::auth_service::Registry;
+use openmls_group::{
+    accept::start_accept,
+    admin::start_admin_console,
+    key_packages::KeyPackageDirectory,
+    main_loop::spawn_main_loop,
+    metrics::{serve_metrics, Metrics},
+    rooms::RoomCatalog,
+    telemetry::{get_subscriber, init_subscriber},
+};
 
 #[tokio::main]
 async fn main() {
-    let (handle, join) = spawn_main_loop();
+    let subscriber = get_subscriber("openmls-group".into(), "info".into(), std::io::stdout);
+    init_subscriber(subscriber);
+
+    // In-process stand-in for the AS, shared by all the sessions
+    let registry = Arc::new(Registry::default());
+    // No admin manages rooms here, the catalog stays empty
+    let metrics = Arc::new(Metrics::default());
+    let (handle, join) = spawn_main_loop(
+        Arc::new(RoomCatalog::default()),
+        Arc::new(KeyPackageDirectory::new(registry.clone())),
+        metrics.clone(),
+    );
     let port = 3456;
+    registry.subscribe(Arc::new(handle.clone()));
+
+    // The admin console, on localhost only, if a port is set
+    if let Some(console_port) = env_port("ADMIN_CONSOLE_PORT") {
+        let bind = ([127, 0, 0, 1], console_port).into();
+        tokio::spawn(start_admin_console(bind, handle.clone()));
+        tracing::info!(
+            console_port,
+            "Admin console on `telnet 127.0.0.1 {}`",
+            console_port
+        );
+    }
 
     tokio::spawn(async move {
-        let bind = ([0, 0, 0, 0], port.clone()).into();
-        start_accept(bind, handle).await;
+        let bind = ([0, 0, 0, 0], port).into();
+        start_accept(bind, handle, registry, None, None).await;
     });
 
-    println!("[Server] Starting on port {}", port);
-    println!("[Server] Use:");
-    println!("[Server]      telnet 127.0.0.1 {}", port);
-    println!("[Server] to connect.");
+    // The metrics are served on the admin port, if one is set
+    if let Some(admin_port) = env_port("ADMIN_PORT") {
+        tokio::spawn(async move {
+            let bind = ([127, 0, 0, 1], admin_port).into();
+            if let Err(err) = serve_metrics(bind, metrics).await {
+                tracing::error!(error = %err, "The admin port failed");
+            }
+        });
+        tracing::info!(admin_port, "Serving the metrics on /metrics");
+    }
+
+    tracing::info!(port, "Starting, connect with `telnet 127.0.0.1 {}`", port);
 
     join.await.unwrap();
 }
+
+/// The port set in the environment variable `name`, if any.
+fn env_port(name: &str) -> Option<u16> {
+    std::env::var(name).ok()?.parse().ok()
+}
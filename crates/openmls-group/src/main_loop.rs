@@ -1,16 +1,36 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fmt::Display,
     io,
+    net::SocketAddr,
+    ops::RangeInclusive,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
 };
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{
+    mpsc::{channel, Receiver, Sender},
+    oneshot,
+};
 use tokio::task::JoinHandle;
+use tracing::{info_span, Span};
+use uuid::Uuid;
+
+use chat_core::{auth_service::RevocationListener, ext_mls::user_of};
+use openmls::prelude::{tls_codec::Deserialize as _, Ciphersuite, ContentType, MlsMessageIn};
+
+#[cfg(test)]
+mod external_commits;
+#[cfg(test)]
+mod proposal_queue;
 
 use crate::{
+    archive::{Archive, ArchivedMessage},
     client::{ClientHandle, FromDelivery},
+    key_packages::{KeyPackageDirectory, KeyPackageStats},
+    metrics::Metrics,
+    rooms::{Room, RoomCatalog, RoomError},
     ClientId,
 };
 
@@ -18,15 +38,178 @@ use crate::{
 pub enum ToDelivery {
     NewClient(ClientHandle),
     Message(ClientId, Vec<u8>),
+    // Mls: the delivery service only routes serialized messages, it never
+    // sees the group secrets.
+    // Checked and kept by the key package directory
+    PublishKeyPackage {
+        from: ClientId,
+        identity: String,
+        key_package: Vec<u8>,
+    },
+    // Only key packages of the group's ciphersuite can be added to it
+    ClaimKeyPackages {
+        identities: Vec<String>,
+        ciphersuite: Ciphersuite,
+        resp: oneshot::Sender<Vec<(String, Vec<u8>)>>,
+    },
+    // The room of a group to create, if `identity` can create it
+    OpenRoom {
+        identity: String,
+        name: String,
+        resp: oneshot::Sender<Result<Room, RoomError>>,
+    },
+    CreateGroup {
+        from: ClientId,
+        identity: String,
+        group_id: Vec<u8>,
+        group_info: Vec<u8>,
+        // Id of the room the group is created for
+        room: Option<String>,
+    },
+    GroupMessage {
+        from: ClientId,
+        group_id: Vec<u8>,
+        message: Vec<u8>,
+        // Envelope id of a line, the sender is told once it was routed
+        receipt: Option<Uuid>,
+    },
+    // An application message that is not a line, e.g. typing or a read
+    // receipt: routed but not archived, to the client of `to` only if set
+    Signal {
+        from: ClientId,
+        group_id: Vec<u8>,
+        message: Vec<u8>,
+        to: Option<String>,
+    },
+    // The last `last` archived application messages of `epochs`, before the
+    // sequence `before` if any, for the members of the group only
+    FetchHistory {
+        from: ClientId,
+        group_id: Vec<u8>,
+        epochs: RangeInclusive<u64>,
+        before: Option<u64>,
+        last: usize,
+        resp: oneshot::Sender<Vec<ArchivedMessage>>,
+    },
+    // Signed by the last committer, for clients joining by external commit
+    PublishGroupInfo {
+        from: ClientId,
+        group_id: Vec<u8>,
+        epoch: u64,
+        group_info: Vec<u8>,
+    },
+    AllowExternalJoin {
+        from: ClientId,
+        group_id: Vec<u8>,
+        identities: Vec<String>,
+    },
+    // `None` if the identity is not allowed to join the group. The group of
+    // a room is also found by the room's name.
+    FetchGroupInfo {
+        identity: String,
+        group_id: Vec<u8>,
+        resp: oneshot::Sender<Option<Vec<u8>>>,
+    },
+    // Only the first commit for an epoch is accepted, the committer merges it
+    // once told so. The routing of the group follows the added and removed
+    // members. A commit from a client outside the group is an external join
+    // of the identity in `added`.
+    Commit {
+        from: ClientId,
+        group_id: Vec<u8>,
+        commit: Vec<u8>,
+        welcome: Option<Vec<u8>>,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+    // From the AS: the leaves with this credential are removed from their
+    // groups, by the remaining member with the lowest leaf index.
+    CredentialRevoked {
+        identity: String,
+    },
+    // The session of the client ended, the members of its group see it
+    // offline
+    ClientLeft(ClientId),
+    // Told to the members of the client's group
+    SetPresence {
+        from: ClientId,
+        presence: Presence,
+    },
+    // The members of a group the delivery service knows, with their presence
+    Who {
+        group_id: Vec<u8>,
+        resp: oneshot::Sender<Vec<(String, Presence)>>,
+    },
+    // Admin console: the state of the delivery service, and what an operator
+    // can do about it
+    ListSessions {
+        resp: oneshot::Sender<Vec<SessionSummary>>,
+    },
+    ListGroups {
+        resp: oneshot::Sender<Vec<GroupSummary>>,
+    },
+    // Whether the client was connected
+    Kick {
+        id: ClientId,
+        resp: oneshot::Sender<bool>,
+    },
+    // The number of clients the notice was sent to
+    Broadcast {
+        notice: String,
+        resp: oneshot::Sender<usize>,
+    },
+    KeyPackageStats {
+        resp: oneshot::Sender<Vec<KeyPackageStats>>,
+    },
     FatalError(io::Error),
 }
 
+/// Whether a member of a group is there, as the other members see it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    Online,
+    Away,
+    Offline,
+}
+
+impl Display for Presence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Online => write!(f, "online"),
+            Self::Away => write!(f, "away"),
+            Self::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// A connected client, for the admin console.
+#[derive(Clone, Debug)]
+pub struct SessionSummary {
+    pub id: ClientId,
+    pub ip: SocketAddr,
+    // Once it published key packages
+    pub identity: Option<String>,
+    // Messages waiting to be written to the client
+    pub queued: usize,
+}
+
+/// A group routed by the delivery service, for the admin console.
+#[derive(Clone, Debug)]
+pub struct GroupSummary {
+    pub group_id: String,
+    pub epoch: u64,
+    pub members: usize,
+    pub pending_proposals: usize,
+    pub room: Option<String>,
+}
+
 /// This struct is used by client actors to send messages to the main loop. The
 /// message type is `ToDelivery`.
 #[derive(Clone, Debug)]
 pub struct ServerHandle {
     chan: Sender<ToDelivery>,
     next_id: Arc<AtomicUsize>,
+    metrics: Arc<Metrics>,
 }
 
 impl ServerHandle {
@@ -40,27 +223,267 @@ impl ServerHandle {
         let id = self.next_id.fetch_add(1, Ordering::Relaxed);
         ClientId(id)
     }
+
+    /// The metrics of the delivery service, shared with its clients.
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+}
+
+/// The notification path from the AS to the delivery service. Revocations
+/// happen within the runtime, while handling a client command.
+impl RevocationListener for ServerHandle {
+    fn revoked(&self, identity: &str) {
+        let mut handle = self.clone();
+        let identity = identity.to_string();
+        tokio::spawn(async move {
+            handle
+                .send(ToDelivery::CredentialRevoked { identity })
+                .await
+        });
+    }
 }
 
 #[derive(Default, Debug)]
 struct Data {
     clients: HashMap<ClientId, ClientHandle>,
+    // Managed by the admins, enforced for the groups of rooms
+    rooms: Arc<RoomCatalog>,
+    // Identity of the credential a client published its key packages with
+    identities: HashMap<String, ClientId>,
+    // Shared with the web API
+    key_packages: Arc<KeyPackageDirectory>,
+    groups: HashMap<Vec<u8>, GroupData>,
+    // The application messages of the groups, still encrypted
+    archive: Archive,
+    // Connected clients that stepped away
+    away: HashSet<ClientId>,
+    metrics: Arc<Metrics>,
 }
 
-pub fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
+#[derive(Default, Debug)]
+struct GroupData {
+    // Clients to route the messages of the group to
+    members: HashSet<ClientId>,
+    // Epoch the next accepted commit is sent in
+    epoch: u64,
+    // GroupInfo of the current epoch
+    group_info: Vec<u8>,
+    // Identities allowed to join by external commit: the members, and the
+    // ones a member allowed
+    allowed: HashSet<String>,
+    // Proposals sent in the current epoch, the next commit includes them
+    proposals: Vec<Vec<u8>>,
+    // Revoked identities still in the group, until a commit removes them
+    revoked: HashSet<String>,
+    // Identities of the kicked clients, their leaves stay in the group until
+    // a commit removes them
+    kicked: HashSet<String>,
+    // Id of the room of the group, its policy applies on top
+    room: Option<String>,
+}
+
+impl GroupData {
+    /// Queues a proposal for the next commit. A proposal of a past epoch can
+    /// no longer be committed, it is dropped: false, it is not routed either.
+    fn queue_if_proposal(&mut self, message: &[u8]) -> bool {
+        let Some((ContentType::Proposal, epoch)) = content_type_and_epoch(message) else {
+            return true;
+        };
+        if epoch != self.epoch {
+            tracing::info!(epoch, "Dropped a proposal of a past epoch");
+            return false;
+        }
+        self.proposals.push(message.to_vec());
+        tracing::info!(epoch, pending = self.proposals.len(), "Queued a proposal");
+
+        true
+    }
+
+    /// Whether a device can join by external commit, itself or its user
+    /// being allowed. Whoever its room allows joins the group of a room.
+    fn is_allowed(&self, identity: &str, rooms: &RoomCatalog) -> bool {
+        if let Some(room) = &self.room {
+            return rooms.admits(room, identity);
+        }
+        let user = String::from_utf8_lossy(user_of(identity.as_bytes()));
+        self.allowed.contains(identity) || self.allowed.contains(user.as_ref())
+    }
+
+    /// Whether the room of the group, if any, takes `identity` as a member.
+    fn admits(&self, identity: &str, rooms: &RoomCatalog) -> bool {
+        self.room
+            .as_ref()
+            .is_none_or(|room| rooms.admits(room, identity))
+    }
+}
+
+impl Data {
+    /// Whether the message reached the client, it is dropped if the client
+    /// left or lags behind.
+    fn send_to(&mut self, id: ClientId, msg: FromDelivery) -> bool {
+        let Some(handle) = self.clients.get_mut(&id) else {
+            return false;
+        };
+        if let Err(err) = handle.send(msg) {
+            self.metrics.dropped.inc();
+            tracing::warn!(client_id = %id, error = %err, "Dropped a message for a client");
+            return false;
+        }
+
+        true
+    }
+
+    /// The identity a client published its key packages or joined with.
+    fn identity_of(&self, id: ClientId) -> Option<String> {
+        self.identities
+            .iter()
+            .find(|(_, client)| **client == id)
+            .map(|(identity, _)| identity.clone())
+    }
+
+    fn presence(&self, id: ClientId) -> Presence {
+        match self.clients.get(&id) {
+            Some(handle) if handle.is_connected() && self.away.contains(&id) => Presence::Away,
+            Some(handle) if handle.is_connected() => Presence::Online,
+            _ => Presence::Offline,
+        }
+    }
+
+    /// Tells the other members of the groups of a client that it is
+    /// `presence` now.
+    fn announce(&mut self, id: ClientId, presence: Presence) {
+        let Some(identity) = self.identity_of(id) else {
+            return;
+        };
+        let notice = format!(
+            "{} is {}.",
+            String::from_utf8_lossy(user_of(identity.as_bytes())),
+            presence
+        );
+        let recipients: Vec<ClientId> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.members.contains(&id))
+            .flat_map(|(group_id, _)| self.recipients(group_id, id))
+            .collect();
+        let _span = delivery_span(&self.metrics, "presence", recipients.len()).entered();
+        for recipient in recipients {
+            self.send_to(recipient, FromDelivery::Message(notice.as_bytes().to_vec()));
+        }
+    }
+
+    /// Updates the size of the key package pool.
+    fn key_packages_changed(&self) {
+        self.metrics
+            .key_packages
+            .with_label_values(&["directory"])
+            .set(self.key_packages.total() as i64);
+    }
+
+    /// Asks the members of the group to remove its revoked identities, the
+    /// designated member commits. Sent again after every commit until then.
+    fn notify_revoked(&mut self, group_id: &[u8]) {
+        let Some(group) = self.groups.get(group_id) else {
+            return;
+        };
+        let mut notices = Vec::new();
+        for identity in group.revoked.iter() {
+            // The revoked device learns it was removed from the commit.
+            let revoked = self.identities.get(identity);
+            for id in group.members.iter().filter(|id| Some(*id) != revoked) {
+                notices.push((*id, identity.clone()));
+            }
+        }
+        for (id, identity) in notices {
+            self.send_to(id, FromDelivery::CredentialRevoked(identity));
+        }
+    }
+
+    /// Members of the group other than `from`.
+    fn recipients(&self, group_id: &[u8], from: ClientId) -> Vec<ClientId> {
+        self.groups
+            .get(group_id)
+            .map(|group| {
+                group
+                    .members
+                    .iter()
+                    .copied()
+                    .filter(|id| *id != from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The span of the handling of a message about a group.
+fn group_span(group_id: &[u8], from: ClientId) -> Span {
+    info_span!(
+        "group",
+        group_id = %String::from_utf8_lossy(group_id),
+        from = %from
+    )
+}
+
+/// The span of the delivery of a message to `recipients` clients, counted by
+/// its kind.
+fn delivery_span(metrics: &Metrics, kind: &'static str, recipients: usize) -> Span {
+    metrics.messages.with_label_values(&[kind]).inc();
+    info_span!("deliver_message", kind, recipients)
+}
+
+/// Content type and epoch of a serialized Mls message, they are in the clear
+/// even for encrypted messages.
+fn content_type_and_epoch(message: &[u8]) -> Option<(ContentType, u64)> {
+    let message = MlsMessageIn::tls_deserialize_exact(message)
+        .ok()?
+        .try_into_protocol_message()
+        .ok()?;
+
+    Some((message.content_type(), message.epoch().as_u64()))
+}
+
+/// Whether a serialized message is an external commit, of a client joining
+/// the group. The session of the client reports the identity of its leaf.
+fn is_external_commit(message: &[u8]) -> bool {
+    MlsMessageIn::tls_deserialize_exact(message)
+        .ok()
+        .and_then(|message| message.try_into_protocol_message().ok())
+        .is_some_and(|message| {
+            message.content_type() == ContentType::Commit && message.is_external()
+        })
+}
+
+/// Epoch of a serialized commit, `None` for any other message.
+fn commit_epoch(message: &[u8]) -> Option<u64> {
+    match content_type_and_epoch(message)? {
+        (ContentType::Commit, epoch) => Some(epoch),
+        _ => None,
+    }
+}
+
+/// Spawns the main loop, the groups of the rooms of `rooms` take only the
+/// identities the rooms allow. Its clients publish and claim the key packages
+/// of `key_packages`. It and its clients update `metrics`.
+pub fn spawn_main_loop(
+    rooms: Arc<RoomCatalog>,
+    key_packages: Arc<KeyPackageDirectory>,
+    metrics: Arc<Metrics>,
+) -> (ServerHandle, JoinHandle<()>) {
     let (send, recv) = channel(64);
 
     let handle = ServerHandle {
         chan: send,
         next_id: Default::default(),
+        metrics: metrics.clone(),
     };
 
     let join = tokio::spawn(async move {
-        let res = main_loop(recv).await;
+        let res = main_loop(recv, rooms, key_packages, metrics).await;
         match res {
             Ok(()) => {}
             Err(err) => {
-                eprintln!("Oops {}.", err);
+                tracing::error!(error = %err, "The main loop failed");
             }
         }
     });
@@ -68,43 +491,32 @@ pub fn spawn_main_loop() -> (ServerHandle, JoinHandle<()>) {
     (handle, join)
 }
 
-async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
-    let mut data = Data::default();
+async fn main_loop(
+    mut recv: Receiver<ToDelivery>,
+    rooms: Arc<RoomCatalog>,
+    key_packages: Arc<KeyPackageDirectory>,
+    metrics: Arc<Metrics>,
+) -> Result<(), io::Error> {
+    let mut data = Data {
+        rooms,
+        key_packages,
+        metrics,
+        ..Data::default()
+    };
 
     while let Some(msg) = recv.recv().await {
         match msg {
             ToDelivery::NewClient(handle) => {
-                println!("[Delivery Service] received new client");
+                tracing::info!(client_id = %handle.id, ip = %handle.ip, "Registered a client");
                 data.clients.insert(handle.id, handle);
-
-                let otp = "1234";
-                println!("[Delivery Service] generated new OTP: {}", otp);
-                println!("[Delivery Service] sent OTP to client");
-                let msg_to_client = "Please provide the otp!";
-                let msg = FromDelivery::Message(msg_to_client.as_bytes().to_vec());
-
-                for (id, handle) in data.clients.iter_mut() {
-                    let id = *id;
-
-                    // Don't send it to the client who sent it to us.
-                    if id == handle.id {
-                        match handle.send(msg) {
-                            Ok(()) => {}
-                            Err(err) => {
-                                eprintln!("[Delivery Service] Something went wrong: {}.", err);
-                            }
-                        };
-
-                        break;
-                    }
-                }
             }
             ToDelivery::Message(from_id, msg) => {
                 // If we fail to send messages to any actor, we need to remove
                 // it, but we can't do so while iterating.
                 // let mut to_remove = Vec::new();
 
-                println!("[Delivery Service] received message");
+                let recipients = data.clients.keys().filter(|id| **id != from_id).count();
+                let _span = delivery_span(&data.metrics, "broadcast", recipients).entered();
                 // Iterate through clients so we can send the message.
                 for (id, handle) in data.clients.iter_mut() {
                     let id = *id;
@@ -116,14 +528,426 @@ async fn main_loop(mut recv: Receiver<ToDelivery>) -> Result<(), io::Error> {
 
                     let msg = FromDelivery::Message(msg.clone());
 
-                    match handle.send(msg) {
-                        Ok(()) => {}
-                        Err(err) => {
-                            eprintln!("[Delivery Service] Something went wrong: {}.", err);
+                    if let Err(err) = handle.send(msg) {
+                        data.metrics.dropped.inc();
+                        tracing::warn!(client_id = %id, error = %err, "Dropped a message for a client");
+                    }
+                }
+            }
+            ToDelivery::PublishKeyPackage {
+                from,
+                identity,
+                key_package,
+            } => match data.key_packages.publish(&identity, &[key_package]) {
+                Ok(_) => {
+                    tracing::info!(%identity, "Stored a key package");
+                    data.identities.insert(identity, from);
+                    data.key_packages_changed();
+                }
+                Err(err) => {
+                    tracing::warn!(%identity, error = %err, "Rejected a key package");
+                    let notice = format!("Your key package was rejected: {}.", err);
+                    data.send_to(from, FromDelivery::Message(notice.into_bytes()));
+                }
+            },
+            ToDelivery::ClaimKeyPackages {
+                identities,
+                ciphersuite,
+                resp,
+            } => {
+                // A user gets one claimed for each of its devices, of the
+                // requested ciphersuite.
+                let claimed = data
+                    .key_packages
+                    .claim(&identities, ciphersuite)
+                    .into_iter()
+                    .map(|claimed| (claimed.identity, claimed.key_package))
+                    .collect();
+                data.key_packages_changed();
+                let _ = resp.send(claimed);
+            }
+            ToDelivery::OpenRoom {
+                identity,
+                name,
+                resp,
+            } => {
+                let room = data.rooms.open(&name, &identity).and_then(|room| {
+                    match data.groups.contains_key(room.id.as_bytes()) {
+                        true => Err(RoomError::HasGroup(room.name)),
+                        false => Ok(room),
+                    }
+                });
+                let _ = resp.send(room);
+            }
+            ToDelivery::CreateGroup {
+                from,
+                identity,
+                group_id,
+                group_info,
+                room,
+            } => {
+                let _span = group_span(&group_id, from).entered();
+                // Another member may have created the room's group since
+                // it was opened.
+                if room.is_some() && data.groups.contains_key(&group_id) {
+                    tracing::info!("Dropped a second group of the room");
+                    let notice = "The room already has a group.".as_bytes().to_vec();
+                    data.send_to(from, FromDelivery::Message(notice));
+                    continue;
+                }
+                tracing::info!(%identity, room, "Created the group");
+                data.identities.insert(identity.clone(), from);
+                let group = GroupData {
+                    members: HashSet::from([from]),
+                    epoch: 0,
+                    group_info,
+                    allowed: HashSet::from([identity]),
+                    proposals: Vec::new(),
+                    revoked: HashSet::new(),
+                    kicked: HashSet::new(),
+                    room,
+                };
+                data.groups.insert(group_id, group);
+                data.metrics.groups.set(data.groups.len() as i64);
+            }
+            ToDelivery::PublishGroupInfo {
+                from,
+                group_id,
+                epoch,
+                group_info,
+            } => {
+                let _span = group_span(&group_id, from).entered();
+                // Outdated if another commit was accepted meanwhile, its
+                // committer publishes the next one.
+                match data.groups.get_mut(&group_id) {
+                    Some(group) if group.members.contains(&from) && group.epoch == epoch => {
+                        tracing::info!(epoch, "Stored the group info");
+                        group.group_info = group_info;
+                    }
+                    _ => tracing::info!(epoch, "Dropped an outdated group info"),
+                }
+            }
+            ToDelivery::AllowExternalJoin {
+                from,
+                group_id,
+                identities,
+            } => {
+                if let Some(group) = data.groups.get_mut(&group_id) {
+                    if group.members.contains(&from) {
+                        group.allowed.extend(identities);
+                    }
+                }
+            }
+            ToDelivery::FetchGroupInfo {
+                identity,
+                group_id,
+                resp,
+            } => {
+                let group_id = String::from_utf8(group_id.clone())
+                    .ok()
+                    .and_then(|name| data.rooms.by_name(&name))
+                    .map(|room| room.id.into_bytes())
+                    .filter(|id| data.groups.contains_key(id))
+                    .unwrap_or(group_id);
+                let group_info = data
+                    .groups
+                    .get(&group_id)
+                    .filter(|group| group.is_allowed(&identity, &data.rooms))
+                    .map(|group| group.group_info.clone());
+                let _ = resp.send(group_info);
+            }
+            ToDelivery::GroupMessage {
+                from,
+                group_id,
+                message,
+                receipt,
+            } => {
+                let _span = group_span(&group_id, from).entered();
+                let Some(group) = data.groups.get_mut(&group_id) else {
+                    continue;
+                };
+                if !group.queue_if_proposal(&message) {
+                    continue;
+                }
+                if let Some((ContentType::Application, epoch)) = content_type_and_epoch(&message) {
+                    let sequence = data.archive.append(&group_id, epoch, message.clone());
+                    tracing::debug!(epoch, sequence, "Archived the message");
+                }
+                let recipients = data.recipients(&group_id, from);
+                let _span =
+                    delivery_span(&data.metrics, "group_message", recipients.len()).entered();
+                // Only the members still connected, whose client took it
+                let mut delivered = 0;
+                for id in recipients {
+                    if data.send_to(id, FromDelivery::GroupMessage(message.clone())) {
+                        delivered += 1;
+                    }
+                }
+                tracing::info!("Delivered the message");
+                if let Some(id) = receipt {
+                    data.send_to(
+                        from,
+                        FromDelivery::Delivered {
+                            id,
+                            recipients: delivered,
+                        },
+                    );
+                }
+            }
+            ToDelivery::Signal {
+                from,
+                group_id,
+                message,
+                to,
+            } => {
+                let _span = group_span(&group_id, from).entered();
+                let mut recipients = data.recipients(&group_id, from);
+                if let Some(to) = to {
+                    let to = data.identities.get(&to).copied();
+                    recipients.retain(|id| Some(*id) == to);
+                }
+                let _span = delivery_span(&data.metrics, "signal", recipients.len()).entered();
+                for id in recipients {
+                    data.send_to(id, FromDelivery::GroupMessage(message.clone()));
+                }
+            }
+            ToDelivery::FetchHistory {
+                from,
+                group_id,
+                epochs,
+                before,
+                last,
+                resp,
+            } => {
+                let is_member = data
+                    .groups
+                    .get(&group_id)
+                    .is_some_and(|group| group.members.contains(&from));
+                let history = match is_member {
+                    true => data.archive.range(&group_id, epochs, before, last),
+                    false => Vec::new(),
+                };
+                let _ = resp.send(history);
+            }
+            ToDelivery::Commit {
+                from,
+                group_id,
+                commit,
+                welcome,
+                added,
+                removed,
+            } => {
+                let _span = group_span(&group_id, from).entered();
+                let Some(group) = data.groups.get_mut(&group_id) else {
+                    tracing::warn!("Dropped a commit for an unknown group");
+                    continue;
+                };
+                let external = !group.members.contains(&from);
+                let allowed = match added.as_slice() {
+                    // The identity of the leaf the commit adds
+                    [identity] if external => {
+                        is_external_commit(&commit) && group.is_allowed(identity, &data.rooms)
+                    }
+                    _ => !external && added.iter().all(|id| group.admits(id, &data.rooms)),
+                };
+                // The members reject the leaves of a revoked credential.
+                let revoked = data
+                    .identities
+                    .iter()
+                    .any(|(identity, id)| *id == from && group.revoked.contains(identity));
+                if !allowed || revoked || commit_epoch(&commit) != Some(group.epoch) {
+                    tracing::info!(epoch = group.epoch, "Rejected a commit");
+                    data.send_to(from, FromDelivery::CommitRejected);
+                    continue;
+                }
+                group.epoch += 1;
+                group.proposals.clear();
+                group.allowed.extend(added.iter().cloned());
+                for identity in removed.iter() {
+                    group.allowed.remove(identity);
+                    group.revoked.remove(identity);
+                    group.kicked.remove(identity);
+                }
+                tracing::info!(epoch = group.epoch, "Accepted a commit");
+
+                // The removed members get the commit too, so they learn
+                // they were removed.
+                let recipients = data.recipients(&group_id, from);
+                let delivery = delivery_span(&data.metrics, "commit", recipients.len()).entered();
+                for id in recipients {
+                    data.send_to(id, FromDelivery::GroupMessage(commit.clone()));
+                }
+                tracing::info!("Delivered the message");
+                drop(delivery);
+                data.send_to(from, FromDelivery::CommitAccepted);
+                data.notify_revoked(&group_id);
+
+                if external {
+                    // A client rejoining with lost state replaces its
+                    // previous connection.
+                    let identity = added[0].clone();
+                    tracing::info!(%identity, "Joined by external commit");
+                    let previous = data.identities.insert(identity.clone(), from);
+                    if let Some(group) = data.groups.get_mut(&group_id) {
+                        if let Some(previous) = previous {
+                            group.members.remove(&previous);
                         }
+                        group.kicked.remove(&identity);
+                        group.members.insert(from);
+                    }
+                    data.announce(from, Presence::Online);
+                    continue;
+                }
+
+                for identity in removed {
+                    if let Some(id) = data.identities.get(&identity).copied() {
+                        if let Some(group) = data.groups.get_mut(&group_id) {
+                            group.members.remove(&id);
+                        }
+                    }
+                }
+                let Some(welcome) = welcome else {
+                    continue;
+                };
+                let _span = delivery_span(&data.metrics, "welcome", added.len()).entered();
+                for identity in added {
+                    let Some(id) = data.identities.get(&identity).copied() else {
+                        tracing::warn!(%identity, "No client to welcome");
+                        continue;
                     };
+                    if let Some(group) = data.groups.get_mut(&group_id) {
+                        group.members.insert(id);
+                    }
+                    data.send_to(id, FromDelivery::Welcome(welcome.clone()));
+                    data.announce(id, data.presence(id));
+                }
+            }
+            ToDelivery::CredentialRevoked { identity } => {
+                tracing::info!(%identity, "Credential revoked");
+                // Its key packages can no longer be added to a group.
+                data.key_packages.remove(&identity);
+                data.key_packages_changed();
+                // The groups of its client, or of its leaves left by a kick
+                let id = data.identities.get(&identity).copied();
+                let group_ids: Vec<Vec<u8>> = data
+                    .groups
+                    .iter_mut()
+                    .filter(|(_, group)| {
+                        id.is_some_and(|id| group.members.contains(&id))
+                            || group.kicked.contains(&identity)
+                    })
+                    .map(|(group_id, group)| {
+                        group.allowed.remove(&identity);
+                        group.revoked.insert(identity.clone());
+                        group_id.clone()
+                    })
+                    .collect();
+                for group_id in group_ids {
+                    data.notify_revoked(&group_id);
+                }
+            }
+            ToDelivery::ClientLeft(id) => {
+                if data.clients.remove(&id).is_some() {
+                    data.away.remove(&id);
+                    data.announce(id, Presence::Offline);
+                }
+            }
+            ToDelivery::SetPresence { from, presence } => {
+                let changed = match presence {
+                    Presence::Away => data.away.insert(from),
+                    _ => data.away.remove(&from),
+                };
+                if changed {
+                    data.announce(from, presence);
                 }
             }
+            ToDelivery::Who { group_id, resp } => {
+                let members = data
+                    .groups
+                    .get(&group_id)
+                    .map(|group| {
+                        group
+                            .members
+                            .iter()
+                            .filter_map(|id| Some((data.identity_of(*id)?, data.presence(*id))))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let _ = resp.send(members);
+            }
+            ToDelivery::ListSessions { resp } => {
+                let sessions = data
+                    .clients
+                    .values()
+                    .filter(|handle| handle.is_connected())
+                    .map(|handle| SessionSummary {
+                        id: handle.id,
+                        ip: handle.ip,
+                        identity: data.identity_of(handle.id),
+                        queued: handle.queued(),
+                    })
+                    .collect();
+                let _ = resp.send(sessions);
+            }
+            ToDelivery::ListGroups { resp } => {
+                let groups = data
+                    .groups
+                    .iter()
+                    .map(|(group_id, group)| GroupSummary {
+                        group_id: String::from_utf8_lossy(group_id).into_owned(),
+                        epoch: group.epoch,
+                        members: group.members.len(),
+                        pending_proposals: group.proposals.len(),
+                        room: group.room.clone(),
+                    })
+                    .collect();
+                let _ = resp.send(groups);
+            }
+            ToDelivery::Kick { id, resp } => {
+                // Dropping its handle kills the client, its groups stop
+                // routing to it. Its leaves stay until a member removes them,
+                // its key packages are no longer handed out.
+                let kicked = data.clients.remove(&id).is_some();
+                if kicked {
+                    tracing::info!(client_id = %id, "Kicked a client");
+                    data.away.remove(&id);
+                    data.announce(id, Presence::Offline);
+                    let key_packages = &data.key_packages;
+                    let mut identities = Vec::new();
+                    data.identities.retain(|identity, client| {
+                        if *client == id {
+                            key_packages.remove(identity);
+                            identities.push(identity.clone());
+                        }
+                        *client != id
+                    });
+                    data.key_packages_changed();
+                    // A revocation still finds the groups of its leaves.
+                    for group in data.groups.values_mut() {
+                        if group.members.remove(&id) {
+                            group.kicked.extend(identities.iter().cloned());
+                        }
+                    }
+                }
+                let _ = resp.send(kicked);
+            }
+            ToDelivery::Broadcast { notice, resp } => {
+                let recipients: Vec<ClientId> = data
+                    .clients
+                    .values()
+                    .filter(|handle| handle.is_connected())
+                    .map(|handle| handle.id)
+                    .collect();
+                let _span = delivery_span(&data.metrics, "notice", recipients.len()).entered();
+                let line = format!("Server notice: {}", notice);
+                for id in recipients.iter() {
+                    data.send_to(*id, FromDelivery::Message(line.as_bytes().to_vec()));
+                }
+                let _ = resp.send(recipients.len());
+            }
+            ToDelivery::KeyPackageStats { resp } => {
+                let _ = resp.send(data.key_packages.stats());
+            }
             ToDelivery::FatalError(err) => return Err(err),
         }
     }
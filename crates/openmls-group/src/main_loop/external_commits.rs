@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use chat_core::{
+    auth_service::{AcceptAll, Registry},
+    ext_mls::{export_group_info, receive_message, send_message, setup_group, Received},
+};
+use openmls::prelude::{
+    tls_codec::{Deserialize as _, Serialize as _},
+    MlsMessageIn,
+};
+
+use super::{is_external_commit, ToDelivery};
+use crate::{session::Session, ClientId};
+
+#[test]
+fn a_joining_session_reports_the_identity_of_its_leaf() {
+    let mut members = setup_group("external_committer", 2);
+    let group_info = export_group_info(&members[0].0, &members[0].1)
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    let mut joining = Session::new(ClientId(2), Arc::new(Registry::default()))
+        .with_validator(Arc::new(AcceptAll));
+
+    let outgoing = joining.join_external(&group_info).unwrap();
+
+    let [ToDelivery::Commit { commit, added, .. }] = outgoing.as_slice() else {
+        panic!("Expected a commit");
+    };
+    assert!(is_external_commit(commit));
+    let (group, member) = &mut members[1];
+    let commit = MlsMessageIn::tls_deserialize_exact(commit).unwrap();
+    let Received::ExternalJoin { sender, .. } = receive_message(group, member, commit).unwrap()
+    else {
+        panic!("Expected an external join");
+    };
+    assert_eq!(added, &[String::from_utf8(sender).unwrap()]);
+}
+
+#[test]
+fn other_messages_are_not_external_commits() {
+    let mut members = setup_group("not_external", 2);
+    let (group, member) = &mut members[0];
+
+    let message = send_message(group, member, b"hello").unwrap();
+
+    assert!(!is_external_commit(
+        &message.tls_serialize_detached().unwrap()
+    ));
+    assert!(!is_external_commit(b"not a message"));
+}
@@ -0,0 +1,46 @@
+use chat_core::ext_mls::{propose_update, send_message, setup_group};
+use openmls::prelude::tls_codec::Serialize as _;
+
+use super::{content_type_and_epoch, GroupData};
+
+#[test]
+fn proposals_are_queued_in_their_epoch_only() {
+    let mut members = setup_group("stale_proposal", 2);
+    let (group, member) = &mut members[0];
+    let proposal = propose_update(group, member)
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    let (_, epoch) = content_type_and_epoch(&proposal).unwrap();
+    let mut current = GroupData {
+        epoch,
+        ..GroupData::default()
+    };
+    let mut next = GroupData {
+        epoch: epoch + 1,
+        ..GroupData::default()
+    };
+
+    assert!(current.queue_if_proposal(&proposal));
+    assert!(!next.queue_if_proposal(&proposal));
+    assert_eq!(current.proposals, vec![proposal]);
+    assert!(next.proposals.is_empty());
+}
+
+#[test]
+fn other_messages_are_routed_without_being_queued() {
+    let mut members = setup_group("not_a_proposal", 2);
+    let (group, member) = &mut members[0];
+    let message = send_message(group, member, b"hello")
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    // Whatever the epoch of the group, only proposals are checked
+    let mut group = GroupData {
+        epoch: 42,
+        ..GroupData::default()
+    };
+
+    assert!(group.queue_if_proposal(&message));
+    assert!(group.proposals.is_empty());
+}
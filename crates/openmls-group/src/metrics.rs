@@ -0,0 +1,137 @@
+// This module keeps the metrics of the delivery service, in a Prometheus
+// registry the web crate adds its own metrics to. They are scraped in the
+// Prometheus text format, from `/metrics` of the AS or from the admin port of
+// the standalone server.
+
+use std::{io, net::SocketAddr, sync::Arc};
+
+use prometheus::{
+    Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+pub struct Metrics {
+    registry: Registry,
+    // Connected sessions, of every transport
+    pub sessions: IntGauge,
+    // Messages the delivery service routed, by kind
+    pub messages: IntCounterVec,
+    // Messages dropped because the queue of their client was full or closed
+    pub dropped: IntCounter,
+    // Groups the delivery service routes
+    pub groups: IntGauge,
+    // Key packages waiting to be claimed, by pool
+    pub key_packages: IntGaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let sessions = IntGauge::new("ds_sessions", "Connected sessions").unwrap();
+        let messages = IntCounterVec::new(
+            Opts::new(
+                "ds_messages_total",
+                "Messages routed by the delivery service",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        let dropped = IntCounter::new(
+            "ds_dropped_messages_total",
+            "Messages dropped on a full or closed client queue",
+        )
+        .unwrap();
+        let groups = IntGauge::new("ds_groups", "Groups routed by the delivery service").unwrap();
+        let key_packages = IntGaugeVec::new(
+            Opts::new("key_packages", "Key packages waiting to be claimed"),
+            &["pool"],
+        )
+        .unwrap();
+
+        let metrics = Self {
+            registry: Registry::new(),
+            sessions,
+            messages,
+            dropped,
+            groups,
+            key_packages,
+        };
+        metrics.register(Box::new(metrics.sessions.clone()));
+        metrics.register(Box::new(metrics.messages.clone()));
+        metrics.register(Box::new(metrics.dropped.clone()));
+        metrics.register(Box::new(metrics.groups.clone()));
+        metrics.register(Box::new(metrics.key_packages.clone()));
+
+        metrics
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    /// Adds a metric, its name must not be taken.
+    pub fn register(&self, metric: Box<dyn prometheus::core::Collector>) {
+        self.registry
+            .register(metric)
+            .expect("The metric names are unique");
+    }
+
+    /// Counts a session as connected until the guard is dropped, also when
+    /// its task is aborted.
+    pub fn session_opened(self: &Arc<Self>) -> SessionGuard {
+        self.sessions.inc();
+        SessionGuard(self.clone())
+    }
+
+    /// All the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("The metrics encode");
+
+        String::from_utf8(buffer).expect("The text format is UTF-8")
+    }
+}
+
+pub struct SessionGuard(Arc<Metrics>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.sessions.dec();
+    }
+}
+
+/// Serves `GET /metrics` on `bind`, for the standalone server.
+pub async fn serve_metrics(bind: SocketAddr, metrics: Arc<Metrics>) -> Result<(), io::Error> {
+    let listen = TcpListener::bind(bind).await?;
+
+    loop {
+        let (mut tcp, _) = listen.accept().await?;
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // The request line fits in the first read
+            let mut request = [0; 1024];
+            let read = tcp.read(&mut request).await?;
+            let response = if request[..read].starts_with(b"GET /metrics ") {
+                let body = metrics.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            tcp.write_all(response.as_bytes()).await?;
+            tcp.shutdown().await
+        });
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::RwLock,
+};
+
+use chat_core::ext_mls::user_of;
+use uuid::Uuid;
+
+const MAX_ROOM_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RoomError {
+    #[error("`{0}` is not a valid room name.")]
+    InvalidName(String),
+    #[error("A room named {0} already exists.")]
+    NameTaken(String),
+    #[error("There is no room {0}.")]
+    NotFound(String),
+    #[error("Room {0} is archived.")]
+    Archived(String),
+    #[error("You are not allowed in room {0}.")]
+    NotAllowed(String),
+    #[error("Room {0} already has a group, join it with c#jxc {0}.")]
+    HasGroup(String),
+}
+
+/// A named room, its group takes only the allowed identities.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Room {
+    // Also the Mls group id of the room's group, it stays when renamed
+    pub id: String,
+    pub name: String,
+    // Users, for all their devices, or single devices
+    pub allowed: BTreeSet<String>,
+    // Takes no new members
+    pub archived: bool,
+}
+
+impl Room {
+    /// Whether a device is allowed in the room, itself or its user being
+    /// listed.
+    pub fn allows(&self, identity: &str) -> bool {
+        let user = String::from_utf8_lossy(user_of(identity.as_bytes()));
+        self.allowed.contains(identity) || self.allowed.contains(user.as_ref())
+    }
+}
+
+/// The rooms an admin manages, shared by the web API and the delivery
+/// service, which enforces their membership.
+#[derive(Debug, Default)]
+pub struct RoomCatalog {
+    // By id
+    rooms: RwLock<HashMap<String, Room>>,
+}
+
+fn parse_name(name: &str) -> Result<String, RoomError> {
+    let name = name.trim();
+    // Telnet clients separate the arguments of a command by whitespace.
+    let is_valid = !name.is_empty()
+        && name.chars().count() <= MAX_ROOM_NAME_LENGTH
+        && !name.chars().any(|c| c.is_whitespace() || c.is_control());
+    if !is_valid {
+        return Err(RoomError::InvalidName(name.to_string()));
+    }
+
+    Ok(name.to_string())
+}
+
+impl RoomCatalog {
+    pub fn create(
+        &self,
+        name: &str,
+        allowed: impl IntoIterator<Item = String>,
+    ) -> Result<Room, RoomError> {
+        let name = parse_name(name)?;
+        let mut rooms = self.rooms.write().unwrap();
+        if rooms.values().any(|room| room.name == name) {
+            return Err(RoomError::NameTaken(name));
+        }
+        let room = Room {
+            id: Uuid::new_v4().to_string(),
+            name,
+            allowed: allowed.into_iter().collect(),
+            archived: false,
+        };
+        rooms.insert(room.id.clone(), room.clone());
+
+        Ok(room)
+    }
+
+    /// All the rooms, archived ones included, by name.
+    pub fn list(&self) -> Vec<Room> {
+        let mut rooms: Vec<Room> = self.rooms.read().unwrap().values().cloned().collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+
+        rooms
+    }
+
+    pub fn get(&self, id: &str) -> Option<Room> {
+        self.rooms.read().unwrap().get(id).cloned()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Room> {
+        self.rooms
+            .read()
+            .unwrap()
+            .values()
+            .find(|room| room.name == name)
+            .cloned()
+    }
+
+    pub fn rename(&self, id: &str, name: &str) -> Result<Room, RoomError> {
+        let name = parse_name(name)?;
+        let mut rooms = self.rooms.write().unwrap();
+        if rooms
+            .values()
+            .any(|room| room.name == name && room.id != id)
+        {
+            return Err(RoomError::NameTaken(name));
+        }
+
+        Self::update(&mut rooms, id, |room| room.name = name)
+    }
+
+    /// Replaces the identities allowed in the room. The members no longer
+    /// allowed stay until removed, but can't rejoin.
+    pub fn set_allowed(
+        &self,
+        id: &str,
+        allowed: impl IntoIterator<Item = String>,
+    ) -> Result<Room, RoomError> {
+        let allowed = allowed.into_iter().collect();
+        Self::update(&mut self.rooms.write().unwrap(), id, |room| {
+            room.allowed = allowed
+        })
+    }
+
+    /// The group of an archived room keeps its members, and takes no new
+    /// ones.
+    pub fn archive(&self, id: &str) -> Result<Room, RoomError> {
+        Self::update(&mut self.rooms.write().unwrap(), id, |room| {
+            room.archived = true
+        })
+    }
+
+    /// Deletes the room, its group takes no new members.
+    pub fn delete(&self, id: &str) -> Result<Room, RoomError> {
+        self.rooms
+            .write()
+            .unwrap()
+            .remove(id)
+            .ok_or_else(|| RoomError::NotFound(id.to_string()))
+    }
+
+    /// The room named `name`, if `identity` can create its group.
+    pub fn open(&self, name: &str, identity: &str) -> Result<Room, RoomError> {
+        let room = self
+            .by_name(name)
+            .ok_or_else(|| RoomError::NotFound(name.to_string()))?;
+        if room.archived {
+            return Err(RoomError::Archived(room.name));
+        }
+        if !room.allows(identity) {
+            return Err(RoomError::NotAllowed(room.name));
+        }
+
+        Ok(room)
+    }
+
+    /// Whether `identity` can join the group of room `id`: the room exists,
+    /// is not archived, and allows it.
+    pub fn admits(&self, id: &str, identity: &str) -> bool {
+        self.get(id)
+            .is_some_and(|room| !room.archived && room.allows(identity))
+    }
+
+    fn update(
+        rooms: &mut HashMap<String, Room>,
+        id: &str,
+        change: impl FnOnce(&mut Room),
+    ) -> Result<Room, RoomError> {
+        let room = rooms
+            .get_mut(id)
+            .ok_or_else(|| RoomError::NotFound(id.to_string()))?;
+        change(room);
+
+        Ok(room.clone())
+    }
+}
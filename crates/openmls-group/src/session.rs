@@ -0,0 +1,1182 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chat_core::auth_service::{
+    Credential, CredentialError, CredentialValidator, Registry, CREDENTIAL_VALIDITY,
+};
+use chat_core::ext_mls::{
+    add_members, commit_pending_proposals, create_group_config, export_group_info, find_devices,
+    group_info_ciphersuite, identity_of, join_by_external_commit, join_group, leave_group,
+    merge_commit, propose_add, propose_remove, propose_update, read_key_package, receive_message,
+    remove_leaves, remove_members, self_update, send_message, user_of, welcome_ciphersuite,
+    ChatError, Member, Received, CIPHERSUITE, DEVICE_SEPARATOR, MAX_PAST_EPOCHS,
+    SUPPORTED_CIPHERSUITES,
+};
+use openmls::{
+    group::{GroupId, MlsGroup},
+    prelude::{
+        tls_codec::{self, Deserialize as _, Serialize as _},
+        Ciphersuite, LeafNodeIndex, MlsMessageIn, MlsMessageOut, OpenMlsProvider, Proposal, Sender,
+    },
+};
+
+use uuid::Uuid;
+
+#[cfg(test)]
+mod committer_rule;
+#[cfg(test)]
+mod devices;
+#[cfg(test)]
+mod history;
+#[cfg(test)]
+mod pending_proposals;
+
+use crate::{
+    archive::ArchivedMessage,
+    envelope::{Content, Envelope},
+    main_loop::ToDelivery,
+    rooms::Room,
+    ClientId,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SessionError {
+    #[error("You are not in a group.")]
+    NoGroup,
+    #[error("You are already in a group.")]
+    AlreadyInGroup,
+    #[error("A user name is not empty and has no `{}`.", DEVICE_SEPARATOR)]
+    InvalidUser,
+    #[error("You signed in as {0}, the user can not be changed.")]
+    SignedIn(String),
+    #[error("Log in with c#lgn <token> to become a device of a user.")]
+    SignInRequired,
+    #[error("There are no pending proposals.")]
+    NoProposals,
+    #[error("`{0}` is not one of your devices.")]
+    NotYourDevice(String),
+    #[error("Your previous commit is still waiting for the delivery service.")]
+    CommitPending,
+    #[error("Proposals are pending, commit them first with c#cmt.")]
+    ProposalsPending,
+    #[error("A message of the group has no valid envelope.")]
+    InvalidEnvelope,
+    #[error(transparent)]
+    Credential(#[from] CredentialError),
+    #[error(transparent)]
+    Mls(#[from] ChatError),
+    #[error(transparent)]
+    Codec(#[from] tls_codec::Error),
+}
+
+/// When a session rotates its leaf key with a self-update commit, whichever
+/// limit is reached first. Until then a leaked key decrypts the group traffic.
+#[derive(Clone, Copy, Debug)]
+pub struct UpdatePolicy {
+    /// Application messages sent in the group.
+    pub max_messages: usize,
+    /// Time since joining the group or the last accepted commit.
+    pub max_age: Duration,
+}
+
+impl Default for UpdatePolicy {
+    fn default() -> Self {
+        Self {
+            max_messages: 100,
+            max_age: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// How long the credential of a session is valid, and how long before it
+/// expires the session rotates to a new signature key.
+#[derive(Clone, Copy, Debug)]
+pub struct CredentialPolicy {
+    pub validity: Duration,
+    pub rotate_before: Duration,
+}
+
+impl Default for CredentialPolicy {
+    fn default() -> Self {
+        Self {
+            validity: CREDENTIAL_VALIDITY,
+            rotate_before: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// A new device `id` of `user`, for groups of `ciphersuite`. The member only
+/// trusts the leaves `validator` knows, not expired and not revoked.
+fn new_member(
+    user: &str,
+    id: ClientId,
+    ciphersuite: Ciphersuite,
+    validator: &Arc<dyn CredentialValidator>,
+) -> Member {
+    // Not in a group yet, the group id is set on create or join.
+    Member::new_with_ciphersuite(
+        user,
+        id.0.to_string(),
+        GroupId::from_slice(&[]),
+        ciphersuite,
+    )
+    .with_validator(validator.clone())
+}
+
+/// The credential the AS registers for a member's signature key.
+fn credential_of(member: &Member, validity: Duration) -> Result<Credential, ChatError> {
+    Ok(Credential::basic(
+        String::from_utf8_lossy(member.identity()).into_owned(),
+        member.credential_with_key.signature_key.as_slice().to_vec(),
+        member.signature_scheme()?,
+        validity,
+    ))
+}
+
+/// A new device `id` of `user`: its member for the default ciphersuite, and
+/// the ones for the other supported ciphersuites.
+fn new_device(
+    user: &str,
+    id: ClientId,
+    validator: &Arc<dyn CredentialValidator>,
+) -> (Member, Vec<Member>) {
+    let mut members = SUPPORTED_CIPHERSUITES
+        .into_iter()
+        .map(|ciphersuite| new_member(user, id, ciphersuite, validator));
+    let member = members.next().expect("At least one supported ciphersuite");
+
+    (member, members.collect())
+}
+
+/// A rotation of the session's signature key: the device rejoins its group
+/// by an external commit signed with the new key.
+struct Rotation {
+    member: Member,
+    group: MlsGroup,
+    // Registered again if the delivery service rejects the commit
+    previous: Credential,
+}
+
+/// The Mls client of a telnet or WebSocket session.
+///
+/// These clients only speak plaintext, so the session holds the member's
+/// keys and group state on their behalf: lines are encrypted here before they
+/// reach the delivery service, which only routes serialized Mls messages.
+pub struct Session {
+    id: ClientId,
+    member: Member,
+    // The device in the other supported ciphersuites, one is swapped with
+    // `member` to create or join a group of its ciphersuite
+    other_suites: Vec<Member>,
+    // Where the session registers its credentials
+    registry: Arc<Registry>,
+    // Where it checks the others', the registry unless an AS runs
+    validator: Arc<dyn CredentialValidator>,
+    // User authenticated by the AS, the session can't pick another one
+    signed_in: Option<String>,
+    // Revoked identities the user was told about
+    revoked: HashSet<String>,
+    credential_policy: CredentialPolicy,
+    // Waiting for the delivery service to accept the rotation commit
+    rotation: Option<Rotation>,
+    group: Option<MlsGroup>,
+    // Shown once the delivery service accepted our pending commit
+    pending_notice: Option<String>,
+    // Joined by an external commit the delivery service did not accept yet
+    joining: bool,
+    update_policy: UpdatePolicy,
+    messages_since_update: usize,
+    last_update: Instant,
+    // The messages sent and read, by ciphertext, with their epoch and line
+    // if any. The key of a message is deleted once
+    // used, its line is kept for the history.
+    lines: HashMap<Vec<u8>, (u64, Option<TimedLine>)>,
+    // The lines sent, by envelope id, for their receipts
+    sent: HashMap<Uuid, (u64, String)>,
+}
+
+impl Session {
+    pub fn new(id: ClientId, registry: Arc<Registry>) -> Self {
+        // Each session is a device of its own user, until it picks one.
+        let validator: Arc<dyn CredentialValidator> = registry.clone();
+        let (member, other_suites) = new_device(&format!("client_{}", id.0), id, &validator);
+
+        let session = Self {
+            id,
+            member,
+            other_suites,
+            registry,
+            validator,
+            signed_in: None,
+            revoked: HashSet::new(),
+            credential_policy: CredentialPolicy::default(),
+            rotation: None,
+            group: None,
+            pending_notice: None,
+            joining: false,
+            update_policy: UpdatePolicy::default(),
+            messages_since_update: 0,
+            last_update: Instant::now(),
+            lines: HashMap::new(),
+            sent: HashMap::new(),
+        };
+        session.register();
+        session
+    }
+
+    /// Checks the leaves of the others against `validator`, e.g. the AS,
+    /// rather than the registry.
+    pub fn with_validator(mut self, validator: Arc<dyn CredentialValidator>) -> Self {
+        for member in std::iter::once(&mut self.member).chain(&mut self.other_suites) {
+            member.validator = validator.clone();
+        }
+        self.validator = validator;
+        self
+    }
+
+    pub fn with_update_policy(mut self, update_policy: UpdatePolicy) -> Self {
+        self.update_policy = update_policy;
+        self
+    }
+
+    /// Registers the credentials again with the validity of the policy.
+    pub fn with_credential_policy(mut self, credential_policy: CredentialPolicy) -> Self {
+        self.credential_policy = credential_policy;
+        self.register();
+        self
+    }
+
+    /// Registers a credential for the key of each ciphersuite. Where the
+    /// registry requires it, only once signed in.
+    fn register(&self) {
+        if self.signed_in.is_none() && self.registry.requires_sign_in() {
+            return;
+        }
+        for member in std::iter::once(&self.member).chain(&self.other_suites) {
+            let credential = credential_of(member, self.credential_policy.validity)
+                .expect("The supported ciphersuites sign with a known scheme");
+            self.registry.register(credential);
+        }
+    }
+
+    /// Ciphersuite of the session's group, the default one out of a group.
+    pub fn ciphersuite(&self) -> Ciphersuite {
+        self.member.ciphersuite
+    }
+
+    /// Makes the member of `ciphersuite` the one taking part in the group.
+    fn use_ciphersuite(&mut self, ciphersuite: Ciphersuite) -> Result<(), SessionError> {
+        if self.member.ciphersuite == ciphersuite {
+            return Ok(());
+        }
+        let member = self
+            .other_suites
+            .iter_mut()
+            .find(|member| member.ciphersuite == ciphersuite)
+            .ok_or(ChatError::UnsupportedCiphersuite(ciphersuite))?;
+        std::mem::swap(&mut self.member, member);
+
+        Ok(())
+    }
+
+    /// Identity of the session's device.
+    pub fn identity(&self) -> String {
+        String::from_utf8_lossy(self.member.identity()).into_owned()
+    }
+
+    /// Makes the session a device of `user`, with new keys. Adding the user
+    /// to a group adds all of its devices that published a key package. Only
+    /// without an AS, a registry that does not require signing in.
+    pub fn set_user(&mut self, user: &str) -> Result<String, SessionError> {
+        self.check_user(user)?;
+        if self.registry.requires_sign_in() {
+            return Err(SessionError::SignInRequired);
+        }
+
+        Ok(self.become_device_of(user))
+    }
+
+    /// Becomes a device of `user`, authenticated by the AS, for good.
+    pub fn sign_in(&mut self, user: &str) -> Result<String, SessionError> {
+        self.check_user(user)?;
+        self.signed_in = Some(user.to_string());
+
+        Ok(self.become_device_of(user))
+    }
+
+    /// Whether the session can become a device of `user`.
+    fn check_user(&self, user: &str) -> Result<(), SessionError> {
+        if let Some(signed_in) = &self.signed_in {
+            return Err(SessionError::SignedIn(signed_in.clone()));
+        }
+        if self.group.is_some() {
+            return Err(SessionError::AlreadyInGroup);
+        }
+        if user.is_empty() || user.contains(DEVICE_SEPARATOR) {
+            return Err(SessionError::InvalidUser);
+        }
+
+        Ok(())
+    }
+
+    fn become_device_of(&mut self, user: &str) -> String {
+        (self.member, self.other_suites) = new_device(user, self.id, &self.validator);
+        self.register();
+        self.identity()
+    }
+
+    pub fn in_group(&self) -> bool {
+        self.group.is_some()
+    }
+
+    /// A key package for each supported ciphersuite.
+    pub fn publish_key_packages(&self) -> Result<Vec<ToDelivery>, SessionError> {
+        std::iter::once(&self.member)
+            .chain(&self.other_suites)
+            .map(|member| {
+                Ok(ToDelivery::PublishKeyPackage {
+                    from: self.id,
+                    identity: self.identity(),
+                    key_package: member.key_package()?.tls_serialize_detached()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Creates a group of `ciphersuite` named after the session's identity
+    /// and adds the owners of the claimed key packages.
+    pub fn create_group(
+        &mut self,
+        ciphersuite: Ciphersuite,
+        key_packages: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<ToDelivery>, SessionError> {
+        let group_id = self.member.identity().to_vec();
+        self.create_group_with_id(group_id, None, ciphersuite, key_packages)
+    }
+
+    /// Creates the group of a room, of the default ciphersuite. Its id is the
+    /// room's, and the owners of the claimed key packages are added.
+    pub fn create_room_group(
+        &mut self,
+        room: &Room,
+        key_packages: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<ToDelivery>, SessionError> {
+        let group_id = room.id.clone().into_bytes();
+        self.create_group_with_id(group_id, Some(room.id.clone()), CIPHERSUITE, key_packages)
+    }
+
+    fn create_group_with_id(
+        &mut self,
+        group_id: Vec<u8>,
+        room: Option<String>,
+        ciphersuite: Ciphersuite,
+        key_packages: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<ToDelivery>, SessionError> {
+        if self.group.is_some() {
+            return Err(SessionError::AlreadyInGroup);
+        }
+        self.use_ciphersuite(ciphersuite)?;
+
+        let mut group = MlsGroup::new_with_group_id(
+            &self.member.provider,
+            &self.member.signer,
+            &create_group_config(ciphersuite),
+            GroupId::from_slice(&group_id),
+            self.member.credential_with_key.clone(),
+        )
+        .map_err(ChatError::from)?;
+
+        let group_info = export_group_info(&group, &self.member)?;
+        let mut outgoing = vec![ToDelivery::CreateGroup {
+            from: self.id,
+            identity: self.identity(),
+            group_id: group_id.clone(),
+            group_info: group_info.tls_serialize_detached()?,
+            room,
+        }];
+
+        if !key_packages.is_empty() {
+            let (added, key_packages): (Vec<_>, Vec<_>) = key_packages.into_iter().unzip();
+            let key_packages = key_packages
+                .iter()
+                .map(|bytes| read_key_package(&self.member.provider, bytes))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (commit, welcome) = add_members(&mut group, &self.member, &key_packages)?;
+
+            self.pending_notice = Some(format!("Added {}.", added.join(" ")));
+            outgoing.push(ToDelivery::Commit {
+                from: self.id,
+                group_id,
+                commit: commit.tls_serialize_detached()?,
+                welcome: Some(welcome.tls_serialize_detached()?),
+                added,
+                removed: Vec::new(),
+            });
+        }
+
+        self.member.group_id = group.group_id().clone();
+        self.group = Some(group);
+        self.reset_update_policy();
+
+        Ok(outgoing)
+    }
+
+    /// Joins the group of a Welcome, returns the group name.
+    pub fn join(&mut self, welcome: &[u8]) -> Result<String, SessionError> {
+        if self.group.is_some() {
+            return Err(SessionError::AlreadyInGroup);
+        }
+        self.use_ciphersuite(welcome_ciphersuite(welcome)?)?;
+
+        let group = join_group(&self.member, welcome)?;
+        let name = String::from_utf8_lossy(group.group_id().as_slice()).into_owned();
+        self.member.group_id = group.group_id().clone();
+        self.group = Some(group);
+        self.reset_update_policy();
+
+        Ok(name)
+    }
+
+    /// Joins a group by external commit from the GroupInfo published to the
+    /// delivery service. The group is ours once the commit is accepted.
+    pub fn join_external(&mut self, group_info: &[u8]) -> Result<Vec<ToDelivery>, SessionError> {
+        if self.group.is_some() {
+            return Err(SessionError::AlreadyInGroup);
+        }
+        self.use_ciphersuite(group_info_ciphersuite(group_info)?)?;
+
+        let (group, commit) = join_by_external_commit(&self.member, group_info)?;
+        let name = String::from_utf8_lossy(group.group_id().as_slice()).into_owned();
+        self.member.group_id = group.group_id().clone();
+        self.group = Some(group);
+        self.joining = true;
+        self.pending_notice = Some(format!("Joined group {}.", name));
+
+        // The delivery service admits the identity of the leaf of the commit
+        let joining = identity_of(&self.member.credential_with_key.credential);
+        let mut commit = self.to_commit(commit, Vec::new())?;
+        if let ToDelivery::Commit { added, .. } = &mut commit {
+            added.push(String::from_utf8_lossy(&joining).into_owned());
+        }
+
+        Ok(vec![commit])
+    }
+
+    /// Allows identities to join the group by external commit.
+    pub fn allow(&self, identities: Vec<String>) -> Result<ToDelivery, SessionError> {
+        if self.group.is_none() {
+            return Err(SessionError::NoGroup);
+        }
+
+        Ok(ToDelivery::AllowExternalJoin {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            identities,
+        })
+    }
+
+    /// Encrypts a line for the group, followed by a self-update commit when
+    /// the update policy is due.
+    pub fn send(&mut self, line: &[u8]) -> Result<Vec<ToDelivery>, SessionError> {
+        let text = String::from_utf8_lossy(line).into_owned();
+        let (envelope, message) = self.seal(Content::Text { text: text.clone() })?;
+        self.messages_since_update += 1;
+        let line = line_of(&envelope, self.member.identity(), &text);
+        self.keep_line(message.clone(), Some((envelope.sent_at, line)));
+        let id = envelope.id;
+        if let Some(epoch) = self.epoch() {
+            self.sent.insert(id, (epoch, text));
+        }
+
+        // The delivery service tells us once it routed the line.
+        let mut outgoing = vec![ToDelivery::GroupMessage {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            message,
+            receipt: Some(id),
+        }];
+        outgoing.extend(self.update_if_due()?);
+
+        Ok(outgoing)
+    }
+
+    /// Tells the group the user is typing, encrypted like the lines.
+    pub fn send_typing(&mut self) -> Result<ToDelivery, SessionError> {
+        let (_, message) = self.seal(Content::Typing)?;
+        self.messages_since_update += 1;
+        self.keep_line(message.clone(), None);
+
+        Ok(self.to_signal(message, None))
+    }
+
+    /// The line of a delivery receipt of the delivery service.
+    pub fn delivered(&self, id: Uuid, recipients: usize) -> Option<String> {
+        let (_, text) = self.sent.get(&id)?;
+
+        Some(format!("Delivered \"{}\" to {} members.", text, recipients))
+    }
+
+    /// Encrypts the envelope of `content` for the group, returns it with the
+    /// serialized message.
+    fn seal(&mut self, content: Content) -> Result<(Envelope, Vec<u8>), SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let envelope = Envelope::new(
+            self.member.group_id.to_vec(),
+            group.own_leaf_index().u32(),
+            content,
+        );
+        let message = send_message(group, &self.member, &envelope.to_bytes())?;
+
+        Ok((envelope, message.tls_serialize_detached()?))
+    }
+
+    /// The envelope of an application message from `sender`, if it was sent
+    /// in the group from one of its leaves.
+    fn open(&self, sender: &[u8], content: &[u8]) -> Result<Envelope, SessionError> {
+        let group = self.group.as_ref().ok_or(SessionError::NoGroup)?;
+        Envelope::from_bytes(content)
+            .filter(|envelope| envelope.group_id == self.member.group_id.as_slice())
+            .filter(|envelope| {
+                group
+                    .member(LeafNodeIndex::new(envelope.sender))
+                    .is_some_and(|credential| identity_of(credential) == sender)
+            })
+            .ok_or(SessionError::InvalidEnvelope)
+    }
+
+    /// The lines read by a member, among the ones we sent.
+    fn read_by(&self, sender: &[u8], ids: &[Uuid]) -> Option<String> {
+        let texts: Vec<String> = ids
+            .iter()
+            .filter_map(|id| self.sent.get(id))
+            .map(|(_, text)| format!("\"{}\"", text))
+            .collect();
+        if texts.is_empty() {
+            return None;
+        }
+
+        Some(format!(
+            "{} read {}.",
+            String::from_utf8_lossy(user_of(sender)),
+            texts.join(", ")
+        ))
+    }
+
+    fn epoch(&self) -> Option<u64> {
+        self.group.as_ref().map(|group| group.epoch().as_u64())
+    }
+
+    /// The id of the group and the identities of its members.
+    pub fn members(&self) -> Result<(Vec<u8>, Vec<String>), SessionError> {
+        let group = self.group.as_ref().ok_or(SessionError::NoGroup)?;
+        let members = group
+            .members()
+            .map(|member| String::from_utf8_lossy(&identity_of(&member.credential)).into_owned())
+            .collect();
+
+        Ok((self.member.group_id.to_vec(), members))
+    }
+
+    /// The group id and the epochs the history is fetched for, the ones the
+    /// group keeps the secrets of.
+    pub fn history_range(&self) -> Result<(Vec<u8>, RangeInclusive<u64>), SessionError> {
+        let group = self.group.as_ref().ok_or(SessionError::NoGroup)?;
+        let epoch = group.epoch().as_u64();
+
+        Ok((
+            self.member.group_id.to_vec(),
+            epoch.saturating_sub(MAX_PAST_EPOCHS as u64)..=epoch,
+        ))
+    }
+
+    /// How many of the archived messages may be lines, the receipts and
+    /// typing indicators already read are not.
+    pub fn lines_in(&self, history: &[ArchivedMessage]) -> usize {
+        history
+            .iter()
+            .filter(|archived| !matches!(self.lines.get(&archived.message), Some((_, None))))
+            .count()
+    }
+
+    /// The last `last` lines of the archived messages, in the order they were
+    /// sent by the clocks of their senders, the archive order between lines of
+    /// the same time. The ones sent before the device joined or of epochs it
+    /// no longer keeps can not be decrypted.
+    pub fn read_history(&mut self, history: Vec<ArchivedMessage>, last: usize) -> Vec<String> {
+        let mut lines: Vec<TimedLine> = Vec::new();
+        let mut unreadable = 0;
+        for archived in history {
+            if let Some((_, line)) = self.lines.get(&archived.message) {
+                lines.extend(line.clone());
+                continue;
+            }
+            match self.decrypt(&archived.message) {
+                Some(Some(line)) => lines.push(line),
+                // A typing indicator or a read receipt
+                Some(None) => {}
+                None => unreadable += 1,
+            }
+        }
+        lines.sort_by_key(|(sent_at, _)| *sent_at);
+        let mut lines: Vec<String> = lines
+            .split_off(lines.len().saturating_sub(last))
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect();
+        if lines.is_empty() && unreadable == 0 {
+            lines.push("There is no history.".to_string());
+        }
+        if unreadable > 0 {
+            lines.push(format!("{} messages can not be decrypted.", unreadable));
+        }
+
+        lines
+    }
+
+    /// The line of an archived application message the device did not read.
+    fn decrypt(&mut self, message: &[u8]) -> Option<Option<TimedLine>> {
+        let group = self.group.as_mut()?;
+        let deserialized = MlsMessageIn::tls_deserialize_exact(message).ok()?;
+        let Ok(Received::Application { sender, content }) =
+            receive_message(group, &self.member, deserialized)
+        else {
+            return None;
+        };
+        let envelope = self.open(&sender, &content).ok()?;
+        let line = match &envelope.content {
+            Content::Text { text } => Some((envelope.sent_at, line_of(&envelope, &sender, text))),
+            Content::Typing | Content::Read { .. } => None,
+        };
+        self.keep_line(message.to_vec(), line.clone());
+
+        Some(line)
+    }
+
+    /// Keeps the line of a message of the current epoch, and forgets the
+    /// lines of the epochs the group no longer keeps.
+    fn keep_line(&mut self, message: Vec<u8>, line: Option<TimedLine>) {
+        let Some(epoch) = self.epoch() else {
+            return;
+        };
+        let oldest = epoch.saturating_sub(MAX_PAST_EPOCHS as u64);
+        self.lines.retain(|_, (epoch, _)| *epoch >= oldest);
+        self.sent.retain(|_, (epoch, _)| *epoch >= oldest);
+        self.lines.insert(message, (epoch, line));
+    }
+
+    /// Issues a self-update commit if the update policy is due, no other
+    /// commit of ours is waiting for the delivery service and no proposal is
+    /// queued, the commit would carry it along.
+    pub fn update_if_due(&mut self) -> Result<Option<ToDelivery>, SessionError> {
+        let Some(group) = self.group.as_mut() else {
+            return Ok(None);
+        };
+        if group.pending_commit().is_some()
+            || group.pending_proposals().next().is_some()
+            || self.rotation.is_some()
+            || (self.messages_since_update < self.update_policy.max_messages
+                && self.last_update.elapsed() < self.update_policy.max_age)
+        {
+            return Ok(None);
+        }
+
+        let commit = self_update(group, &self.member)?;
+        let epoch = group.epoch().as_u64();
+        tracing::info!(identity = %self.identity(), epoch, "Updating the leaf");
+
+        Ok(Some(self.to_commit(commit, Vec::new())?))
+    }
+
+    pub fn remove(&mut self, identities: &[String]) -> Result<Vec<ToDelivery>, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        if group.pending_proposals().next().is_some() {
+            return Err(SessionError::ProposalsPending);
+        }
+        let removed: Vec<&[u8]> = identities.iter().map(|i| i.as_bytes()).collect();
+        // Users are removed with all their devices, the delivery service
+        // routes per device.
+        let devices = removed
+            .iter()
+            .flat_map(|identity| find_devices(group, identity))
+            .filter_map(|index| group.member(index))
+            .map(|credential| String::from_utf8_lossy(&identity_of(credential)).into_owned())
+            .collect();
+        let commit = remove_members(group, &self.member, &removed)?;
+
+        self.pending_notice = Some(format!("Removed {}.", identities.join(" ")));
+        Ok(vec![self.to_commit(commit, devices)?])
+    }
+
+    /// Proposes to add the owners of the claimed key packages, without
+    /// committing. Any member can commit the queued proposals later.
+    pub fn propose_add(
+        &mut self,
+        key_packages: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<ToDelivery>, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let mut proposals = Vec::new();
+        for (_, bytes) in key_packages {
+            let key_package = read_key_package(&self.member.provider, &bytes)?;
+            proposals.push(propose_add(group, &self.member, &key_package)?);
+        }
+
+        self.to_proposals(proposals)
+    }
+
+    /// Proposes to remove members, without committing.
+    pub fn propose_remove(
+        &mut self,
+        identities: &[String],
+    ) -> Result<Vec<ToDelivery>, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let mut proposals = Vec::new();
+        for identity in identities {
+            proposals.extend(propose_remove(group, &self.member, identity.as_bytes())?);
+        }
+
+        self.to_proposals(proposals)
+    }
+
+    /// Proposes to rotate our leaf key, another member commits it.
+    pub fn propose_update(&mut self) -> Result<Vec<ToDelivery>, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let proposal = propose_update(group, &self.member)?;
+
+        self.to_proposals(vec![proposal])
+    }
+
+    /// Commits the proposals queued in the current epoch.
+    pub fn commit_proposals(&mut self) -> Result<Vec<ToDelivery>, SessionError> {
+        let group = self.group.as_ref().ok_or(SessionError::NoGroup)?;
+        let queued = group.pending_proposals().count();
+        if queued == 0 {
+            return Err(SessionError::NoProposals);
+        }
+
+        self.pending_notice = Some(format!("Committed {} pending proposal(s).", queued));
+        Ok(vec![self.commit()?])
+    }
+
+    /// Sends a proposal to remove ourselves, another member commits it.
+    pub fn leave(&mut self) -> Result<ToDelivery, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let proposal = leave_group(group, &self.member)?;
+
+        Ok(self.to_group(proposal.tls_serialize_detached()?))
+    }
+
+    /// The delivery service accepted our pending commit for the epoch, it is
+    /// merged and the GroupInfo of the new epoch is published. Any commit we
+    /// make rotates our leaf, so the update policy starts over.
+    pub fn commit_accepted(&mut self) -> Result<(Option<String>, ToDelivery), SessionError> {
+        if let Some(rotation) = self.rotation.take() {
+            self.member = rotation.member;
+            self.group = Some(rotation.group);
+        }
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        merge_commit(group, &self.member)?;
+        let group_info = ToDelivery::PublishGroupInfo {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            epoch: group.epoch().as_u64(),
+            group_info: export_group_info(group, &self.member)?.tls_serialize_detached()?,
+        };
+        self.joining = false;
+        self.reset_update_policy();
+
+        Ok((self.pending_notice.take(), group_info))
+    }
+
+    /// Another commit was accepted for the epoch first, ours is dropped. An
+    /// external join is dropped with the group, the GroupInfo is outdated.
+    pub fn commit_rejected(&mut self) -> Result<String, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        self.pending_notice = None;
+        if let Some(rotation) = self.rotation.take() {
+            self.registry.register(rotation.previous);
+            return Ok(
+                "Your credential rotation was rejected, the group moved to a new epoch."
+                    .to_string(),
+            );
+        }
+        if self.joining {
+            self.group = None;
+            self.joining = false;
+            return Ok("Your join was rejected, the group moved to a new epoch.".to_string());
+        }
+        group
+            .clear_pending_commit(self.member.provider.storage())
+            .map_err(ChatError::from)?;
+
+        Ok("Your commit was rejected, the group moved to a new epoch.".to_string())
+    }
+
+    /// Rotates to a new signature key: the new credential is registered with
+    /// the AS, and in a group the device replaces its leaf by an external
+    /// commit signed with it. The other members remove the previous leaf.
+    pub fn rotate(&mut self) -> Result<(Option<String>, Vec<ToDelivery>), SessionError> {
+        if self.rotation.is_some()
+            || self
+                .group
+                .as_ref()
+                .is_some_and(|group| group.pending_commit().is_some())
+        {
+            return Err(SessionError::CommitPending);
+        }
+        let user = String::from_utf8_lossy(self.member.user()).into_owned();
+        // The keys of the other ciphersuites are in no group
+        for idle in self.other_suites.iter_mut() {
+            let member = new_member(&user, self.id, idle.ciphersuite, &self.validator);
+            self.registry
+                .rotate(credential_of(&member, self.credential_policy.validity)?)?;
+            *idle = member;
+        }
+        let member = new_member(&user, self.id, self.member.ciphersuite, &self.validator);
+        let previous = self
+            .registry
+            .rotate(credential_of(&member, self.credential_policy.validity)?)?;
+
+        let Some(group) = self.group.as_ref() else {
+            self.member = member;
+            let notice = "Rotated your credential, publish a new key package.".to_string();
+            return Ok((Some(notice), Vec::new()));
+        };
+        let joined = export_group_info(group, &self.member)
+            .map_err(SessionError::from)
+            .and_then(|group_info| Ok(group_info.tls_serialize_detached()?))
+            .and_then(|group_info| Ok(join_by_external_commit(&member, &group_info)?));
+        let (group, commit) = match joined {
+            Ok(joined) => joined,
+            Err(err) => {
+                self.registry.register(previous);
+                return Err(err);
+            }
+        };
+        let mut member = member;
+        member.group_id = group.group_id().clone();
+
+        self.rotation = Some(Rotation {
+            member,
+            group,
+            previous,
+        });
+        self.pending_notice = Some("Rotated your credential.".to_string());
+        Ok((None, vec![self.to_commit(commit, Vec::new())?]))
+    }
+
+    /// Rotates the signature key once the credential is about to expire.
+    pub fn rotate_if_due(&mut self) -> Result<Option<(String, Vec<ToDelivery>)>, SessionError> {
+        let scheme = self.member.signature_scheme()?;
+        let Some(credential) = self.registry.get(&self.identity(), scheme) else {
+            return Ok(None);
+        };
+        let Some(valid_until) = credential.valid_until else {
+            return Ok(None);
+        };
+        let now = SystemTime::now();
+        if credential.revoked
+            || self.rotation.is_some()
+            || self.joining
+            || valid_until > now + self.credential_policy.rotate_before
+            || self
+                .group
+                .as_ref()
+                .is_some_and(|group| group.pending_commit().is_some())
+        {
+            return Ok(None);
+        }
+
+        let line = match valid_until.duration_since(now) {
+            Ok(left) => format!(
+                "Your credential expires in {} seconds, rotating it.",
+                left.as_secs()
+            ),
+            Err(_) => "Your credential expired, rotating it.".to_string(),
+        };
+        let (notice, outgoing) = self.rotate()?;
+        let line = match notice {
+            Some(notice) => format!("{} {}", line, notice),
+            None => line,
+        };
+
+        Ok(Some((line, outgoing)))
+    }
+
+    /// Revokes the credential of one of the user's devices with the AS, e.g.
+    /// a lost one. The delivery service has it removed from its groups.
+    pub fn revoke(&self, identity: &str) -> Result<String, SessionError> {
+        if user_of(identity.as_bytes()) != self.member.user() {
+            return Err(SessionError::NotYourDevice(identity.to_string()));
+        }
+        self.registry.revoke(identity)?;
+
+        Ok(format!("Revoked the credential of {}.", identity))
+    }
+
+    /// The AS revoked the credential of a device in the group. Its leaves
+    /// are removed by the remaining member with the lowest leaf index, once
+    /// no proposal is queued. The delivery service asks again after each
+    /// commit until one removes them.
+    pub fn credential_revoked(
+        &mut self,
+        identity: &str,
+    ) -> Result<(Option<String>, Vec<ToDelivery>), SessionError> {
+        let group = self.group.as_ref().ok_or(SessionError::NoGroup)?;
+        let leaves: Vec<LeafNodeIndex> = group
+            .members()
+            .filter(|member| identity_of(&member.credential) == identity.as_bytes())
+            .map(|member| member.index)
+            .collect();
+        let line = self
+            .revoked
+            .insert(identity.to_string())
+            .then(|| format!("The credential of {} was revoked.", identity));
+        if leaves.is_empty()
+            || !self.is_committer(&leaves)
+            || group.pending_proposals().next().is_some()
+        {
+            return Ok((line, Vec::new()));
+        }
+
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let commit = remove_leaves(group, &self.member, &leaves)?;
+        self.pending_notice = Some(format!("Removed {}.", identity));
+        Ok((
+            line,
+            vec![self.to_commit(commit, vec![identity.to_string()])?],
+        ))
+    }
+
+    /// Processes a message routed by the delivery service.
+    ///
+    /// Returns the line to show to the user, if any, and the messages to
+    /// send back to the delivery service.
+    pub fn receive(
+        &mut self,
+        message: &[u8],
+    ) -> Result<(Option<String>, Vec<ToDelivery>), SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let serialized = message;
+        let message = MlsMessageIn::tls_deserialize_exact(message)?;
+
+        match receive_message(group, &self.member, message)? {
+            Received::Application { sender, content } => {
+                let envelope = self.open(&sender, &content)?;
+                let user = String::from_utf8_lossy(user_of(&sender)).into_owned();
+                match envelope.content {
+                    Content::Text { ref text } => {
+                        let line = line_of(&envelope, &sender, text);
+                        self.keep_line(serialized.to_vec(), Some((envelope.sent_at, line.clone())));
+                        // Shown, so read
+                        let (_, receipt) = self.seal(Content::Read {
+                            ids: vec![envelope.id],
+                        })?;
+                        self.keep_line(receipt.clone(), None);
+                        // Only the sender of the line cares it was read.
+                        let to = String::from_utf8_lossy(&sender).into_owned();
+
+                        Ok((Some(line), vec![self.to_signal(receipt, Some(to))]))
+                    }
+                    Content::Typing => {
+                        self.keep_line(serialized.to_vec(), None);
+
+                        Ok((Some(format!("{} is typing...", user)), Vec::new()))
+                    }
+                    Content::Read { ids } => {
+                        self.keep_line(serialized.to_vec(), None);
+
+                        Ok((self.read_by(&sender, &ids), Vec::new()))
+                    }
+                }
+            }
+            Received::Proposal { sender } => {
+                let sender = String::from_utf8_lossy(user_of(&sender)).into_owned();
+                // Other proposals wait for a member to commit the queue, a
+                // member leaving can not.
+                if !has_leave_proposal(group) || !self.is_committer(&[]) {
+                    return Ok((Some(format!("{} sent a proposal.", sender)), Vec::new()));
+                }
+
+                self.pending_notice = Some(format!("Committed the proposal of {}.", sender));
+                Ok((None, vec![self.commit()?]))
+            }
+            Received::Commit if !group.is_active() => {
+                self.group = None;
+                Ok((
+                    Some("You are no longer in the group.".to_string()),
+                    Vec::new(),
+                ))
+            }
+            Received::Commit => Ok((None, Vec::new())),
+            Received::ExternalJoin { sender, stale } => {
+                // A device rejoining, after a rotation or a lost state, leaves
+                // its previous leaf behind.
+                let line = format!(
+                    "{} {} the group.",
+                    String::from_utf8_lossy(user_of(&sender)),
+                    if stale.is_empty() {
+                        "joined"
+                    } else {
+                        "rejoined"
+                    }
+                );
+                // The sender does not process its own commit.
+                let leaving: Vec<LeafNodeIndex> = group
+                    .members()
+                    .filter(|member| identity_of(&member.credential) == sender)
+                    .map(|member| member.index)
+                    .collect();
+                if stale.is_empty() || !self.is_committer(&leaving) {
+                    return Ok((Some(line), Vec::new()));
+                }
+
+                // The leaves the sender left behind are never updated again.
+                let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+                let commit = remove_leaves(group, &self.member, &stale)?;
+                Ok((Some(line), vec![self.to_commit(commit, Vec::new())?]))
+            }
+        }
+    }
+
+    /// Commits the pending proposals.
+    fn commit(&mut self) -> Result<ToDelivery, SessionError> {
+        let group = self.group.as_mut().ok_or(SessionError::NoGroup)?;
+        let added = added_identities(group);
+        let removed = removed_identities(group);
+        let (commit, welcome) = commit_pending_proposals(group, &self.member)?;
+
+        Ok(ToDelivery::Commit {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            commit: commit.tls_serialize_detached()?,
+            welcome: welcome
+                .map(|welcome| welcome.tls_serialize_detached())
+                .transpose()?,
+            added,
+            removed,
+        })
+    }
+
+    /// Leave proposals need a commit from another member. To avoid competing
+    /// commits, it is made by the remaining member with the lowest leaf index,
+    /// `leaving` being the other leaves about to be removed.
+    fn is_committer(&self, leaving: &[LeafNodeIndex]) -> bool {
+        let Some(group) = self.group.as_ref() else {
+            return false;
+        };
+        if group.pending_commit().is_some() {
+            return false;
+        }
+        let removed = removed_leaves(group);
+
+        group
+            .members()
+            .map(|member| member.index)
+            .filter(|index| !removed.contains(index) && !leaving.contains(index))
+            .min()
+            == Some(group.own_leaf_index())
+    }
+
+    fn reset_update_policy(&mut self) {
+        self.messages_since_update = 0;
+        self.last_update = Instant::now();
+    }
+
+    fn to_group(&self, message: Vec<u8>) -> ToDelivery {
+        ToDelivery::GroupMessage {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            message,
+            receipt: None,
+        }
+    }
+
+    /// Not a line, the delivery service does not archive it.
+    fn to_signal(&self, message: Vec<u8>, to: Option<String>) -> ToDelivery {
+        ToDelivery::Signal {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            message,
+            to,
+        }
+    }
+
+    fn to_proposals(&self, proposals: Vec<MlsMessageOut>) -> Result<Vec<ToDelivery>, SessionError> {
+        proposals
+            .into_iter()
+            .map(|proposal| Ok(self.to_group(proposal.tls_serialize_detached()?)))
+            .collect()
+    }
+
+    fn to_commit(
+        &self,
+        commit: MlsMessageOut,
+        removed: Vec<String>,
+    ) -> Result<ToDelivery, SessionError> {
+        Ok(ToDelivery::Commit {
+            from: self.id,
+            group_id: self.member.group_id.to_vec(),
+            commit: commit.tls_serialize_detached()?,
+            welcome: None,
+            added: Vec::new(),
+            removed,
+        })
+    }
+}
+
+/// A line of the chat and the time it was sent, in milliseconds since the
+/// Unix epoch by the clock of its sender.
+type TimedLine = (u64, String);
+
+/// The line of a text of `sender`, after the time it was sent.
+fn line_of(envelope: &Envelope, sender: &[u8], text: &str) -> String {
+    format!(
+        "[{}] {}: {}",
+        envelope.time(),
+        String::from_utf8_lossy(user_of(sender)),
+        text
+    )
+}
+
+fn removed_leaves(group: &MlsGroup) -> Vec<LeafNodeIndex> {
+    group
+        .pending_proposals()
+        .filter_map(|queued| match queued.proposal() {
+            Proposal::Remove(remove) => Some(remove.removed()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn removed_identities(group: &MlsGroup) -> Vec<String> {
+    removed_leaves(group)
+        .into_iter()
+        .filter_map(|index| group.member(index))
+        .map(|credential| String::from_utf8_lossy(&identity_of(credential)).into_owned())
+        .collect()
+}
+
+fn added_identities(group: &MlsGroup) -> Vec<String> {
+    group
+        .pending_proposals()
+        .filter_map(|queued| match queued.proposal() {
+            Proposal::Add(add) => Some(add.key_package().leaf_node().credential()),
+            _ => None,
+        })
+        .map(|credential| String::from_utf8_lossy(&identity_of(credential)).into_owned())
+        .collect()
+}
+
+/// Whether a member proposed to remove itself, see [`Session::leave`].
+fn has_leave_proposal(group: &MlsGroup) -> bool {
+    group
+        .pending_proposals()
+        .any(|queued| match (queued.proposal(), queued.sender()) {
+            (Proposal::Remove(remove), Sender::Member(sender)) => remove.removed() == *sender,
+            _ => false,
+        })
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use chat_core::{
+    auth_service::Registry,
+    ext_mls::{leave_group, receive_message, self_update, setup_group},
+};
+use openmls::prelude::{
+    tls_codec::{Deserialize as _, Serialize as _},
+    LeafNodeIndex, MlsMessageIn, MlsMessageOut,
+};
+
+use super::Session;
+use crate::ClientId;
+
+/// The sessions of the members of a new group, by leaf index.
+fn sessions(group_name: &str, num: usize) -> Vec<Session> {
+    setup_group(group_name, num)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (group, member))| {
+            let mut session = Session::new(ClientId(index), Arc::new(Registry::default()));
+            session.member = member;
+            session.group = Some(group);
+            session
+        })
+        .collect()
+}
+
+fn receive(session: &mut Session, message: &MlsMessageOut) {
+    let message =
+        MlsMessageIn::tls_deserialize_exact(message.tls_serialize_detached().unwrap()).unwrap();
+    let group = session.group.as_mut().unwrap();
+    receive_message(group, &session.member, message).unwrap();
+}
+
+#[test]
+fn the_member_with_the_lowest_leaf_commits() {
+    let sessions = sessions("lowest_leaf", 3);
+
+    let committers: Vec<bool> = sessions
+        .iter()
+        .map(|session| session.is_committer(&[]))
+        .collect();
+
+    assert_eq!(committers, [true, false, false]);
+}
+
+#[test]
+fn the_leaves_about_to_be_removed_do_not_commit() {
+    let sessions = sessions("removed_leaves", 3);
+    let leaving = [LeafNodeIndex::new(0)];
+
+    let committers: Vec<bool> = sessions
+        .iter()
+        .map(|session| session.is_committer(&leaving))
+        .collect();
+
+    assert_eq!(committers, [false, true, false]);
+}
+
+#[test]
+fn a_member_who_proposed_to_leave_does_not_commit() {
+    let mut sessions = sessions("leaving", 3);
+    let first = &mut sessions[0];
+    let proposal = leave_group(first.group.as_mut().unwrap(), &first.member).unwrap();
+
+    for session in &mut sessions[1..] {
+        receive(session, &proposal);
+    }
+
+    assert!(sessions[1].is_committer(&[]));
+    assert!(!sessions[2].is_committer(&[]));
+}
+
+#[test]
+fn a_member_waiting_on_its_commit_does_not_commit_again() {
+    let mut sessions = sessions("pending_commit", 2);
+    let first = &mut sessions[0];
+
+    self_update(first.group.as_mut().unwrap(), &first.member).unwrap();
+
+    assert!(!sessions[0].is_committer(&[]));
+    assert!(!sessions[1].is_committer(&[]));
+}
+
+#[test]
+fn a_session_without_group_does_not_commit() {
+    let session = Session::new(ClientId(0), Arc::new(Registry::default()));
+
+    assert!(!session.is_committer(&[]));
+}
@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use chat_core::auth_service::{Registry, SignatureScheme};
+
+use super::{Session, SessionError};
+use crate::ClientId;
+
+#[test]
+fn a_device_picks_its_user_without_an_as() {
+    let registry = Arc::new(Registry::default());
+    let mut session = Session::new(ClientId(1), registry.clone());
+
+    let identity = session.set_user("alice").unwrap();
+
+    assert_eq!(identity, "alice:1");
+    assert!(registry.get(&identity, SignatureScheme::Ed25519).is_some());
+}
+
+#[test]
+fn a_device_is_registered_once_signed_in_where_the_as_requires_it() {
+    let registry = Arc::new(Registry::default().signed_in_only());
+    let mut session = Session::new(ClientId(2), registry.clone());
+    let anonymous = session.identity();
+
+    let picked = session.set_user("mallory");
+    let identity = session.sign_in("alice").unwrap();
+
+    assert!(matches!(picked, Err(SessionError::SignInRequired)));
+    assert!(registry.get(&anonymous, SignatureScheme::Ed25519).is_none());
+    assert!(registry.get(&identity, SignatureScheme::Ed25519).is_some());
+}
+
+#[test]
+fn a_signed_in_device_keeps_its_user() {
+    let mut session = Session::new(ClientId(3), Arc::new(Registry::default()));
+    session.sign_in("alice").unwrap();
+
+    let picked = session.set_user("mallory");
+
+    assert!(matches!(picked, Err(SessionError::SignedIn(user)) if user == "alice"));
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use chat_core::{
+    auth_service::Registry,
+    ext_mls::{send_message, setup_group},
+};
+use openmls::prelude::tls_codec::Serialize as _;
+
+use super::Session;
+use crate::{
+    archive::ArchivedMessage,
+    envelope::{Content, Envelope},
+    ClientId,
+};
+
+/// The sessions of the two members of a new group.
+fn two_members(group_name: &str) -> Vec<Session> {
+    setup_group(group_name, 2)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (group, member))| {
+            let mut session = Session::new(ClientId(index), Arc::new(Registry::default()));
+            session.member = member;
+            session.group = Some(group);
+            session
+        })
+        .collect()
+}
+
+/// A line of the first member, sent at `sent_at` by its clock.
+fn line_sent_at(sessions: &mut [Session], text: &str, sent_at: u64) -> Vec<u8> {
+    let sender = &mut sessions[0];
+    let group = sender.group.as_mut().unwrap();
+    let mut envelope = Envelope::new(
+        sender.member.group_id.to_vec(),
+        group.own_leaf_index().u32(),
+        Content::Text {
+            text: text.to_string(),
+        },
+    );
+    envelope.sent_at = sent_at;
+
+    send_message(group, &sender.member, &envelope.to_bytes())
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap()
+}
+
+#[test]
+fn a_received_line_shows_the_time_it_was_sent() {
+    let mut sessions = two_members("timed_line");
+    // 01:02 UTC, a day after the epoch
+    let message = line_sent_at(&mut sessions, "hello", (25 * 60 + 2) * 60_000);
+
+    let (line, _) = sessions[1].receive(&message).unwrap();
+
+    assert_eq!(line.as_deref(), Some("[01:02] Member_0: hello"));
+}
+
+#[test]
+fn the_history_is_in_the_order_the_lines_were_sent() {
+    let mut sessions = two_members("ordered_history");
+    // Routed in another order than they were sent
+    let history: Vec<ArchivedMessage> = [("second", 120_000), ("first", 60_000)]
+        .into_iter()
+        .enumerate()
+        .map(|(sequence, (text, sent_at))| ArchivedMessage {
+            epoch: 1,
+            sequence: sequence as u64,
+            message: line_sent_at(&mut sessions, text, sent_at),
+        })
+        .collect();
+
+    let lines = sessions[1].read_history(history.clone(), 10);
+
+    assert_eq!(
+        lines,
+        ["[00:01] Member_0: first", "[00:02] Member_0: second"]
+    );
+    // Kept once read, in the same order
+    assert_eq!(
+        sessions[1].read_history(history, 1),
+        ["[00:02] Member_0: second"]
+    );
+}
@@ -0,0 +1,91 @@
+use std::{sync::Arc, time::Duration};
+
+use chat_core::{
+    auth_service::Registry,
+    ext_mls::{propose_update, receive_message, setup_group},
+};
+use openmls::prelude::{
+    tls_codec::{Deserialize as _, Serialize as _},
+    MlsMessageIn,
+};
+
+use super::{Session, SessionError, UpdatePolicy};
+use crate::ClientId;
+
+/// The sessions of two members of a new group, the first queued a proposal
+/// of the second.
+fn with_a_queued_proposal(group_name: &str) -> Vec<Session> {
+    let mut sessions: Vec<Session> = setup_group(group_name, 2)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (group, member))| {
+            let mut session = Session::new(ClientId(index), Arc::new(Registry::default()));
+            session.member = member;
+            session.group = Some(group);
+            session
+        })
+        .collect();
+    let second = &mut sessions[1];
+    let proposal = propose_update(second.group.as_mut().unwrap(), &second.member).unwrap();
+    let proposal =
+        MlsMessageIn::tls_deserialize_exact(proposal.tls_serialize_detached().unwrap()).unwrap();
+    let first = &mut sessions[0];
+    receive_message(first.group.as_mut().unwrap(), &first.member, proposal).unwrap();
+
+    sessions
+}
+
+#[test]
+fn a_due_update_waits_for_the_queue_to_be_committed() {
+    let mut sessions = with_a_queued_proposal("queued_update");
+    sessions[0].update_policy = UpdatePolicy {
+        max_messages: 0,
+        max_age: Duration::ZERO,
+    };
+
+    let update = sessions[0].update_if_due().unwrap();
+
+    assert!(update.is_none());
+    assert!(sessions[0]
+        .group
+        .as_ref()
+        .unwrap()
+        .pending_commit()
+        .is_none());
+}
+
+#[test]
+fn a_removal_asks_to_commit_the_queue_first() {
+    let mut sessions = with_a_queued_proposal("queued_remove");
+    let other = sessions[1].identity();
+
+    let removed = sessions[0].remove(&[other]);
+
+    assert!(matches!(removed, Err(SessionError::ProposalsPending)));
+    assert!(sessions[0]
+        .group
+        .as_ref()
+        .unwrap()
+        .pending_commit()
+        .is_none());
+}
+
+#[test]
+fn a_revoked_leaf_is_removed_once_the_queue_is_committed() {
+    let mut sessions = with_a_queued_proposal("queued_revocation");
+    let other = sessions[1].identity();
+
+    let (line, outgoing) = sessions[0].credential_revoked(&other).unwrap();
+
+    assert_eq!(
+        line,
+        Some(format!("The credential of {} was revoked.", other))
+    );
+    assert!(outgoing.is_empty());
+    // The delivery service asks again once the queue is committed.
+    sessions[0].commit_proposals().unwrap();
+    sessions[0].commit_accepted().unwrap();
+    let (line, outgoing) = sessions[0].credential_revoked(&other).unwrap();
+    assert_eq!(line, None);
+    assert_eq!(outgoing.len(), 1);
+}
@@ -0,0 +1,94 @@
+// This module sets up the bunyan JSON tracing of the delivery service, and of
+// the web crate that runs it. The test sink keeps what is written so the
+// tests can assert on the emitted events.
+
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, EnvFilter, Registry};
+
+pub fn get_subscriber<Sink>(
+    name: String,
+    env_filter: String,
+    sink: Sink,
+) -> impl Subscriber + Send + Sync
+where
+    Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    let formatting_layer = BunyanFormattingLayer::new(name, sink);
+    Registry::default()
+        .with(env_filter)
+        .with(JsonStorageLayer)
+        .with(formatting_layer)
+}
+
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    LogTracer::init().expect("Failed to set logger");
+    set_global_default(subscriber).expect("Failed to set subscriber");
+}
+
+/// Keeps the lines the subscriber writes, for the tests. Clones share them.
+#[derive(Clone, Debug, Default)]
+pub struct TestSink {
+    written: Arc<Mutex<Vec<u8>>>,
+    // Also writes them to stdout
+    echo: bool,
+}
+
+impl TestSink {
+    /// A sink that also writes the lines to stdout, to see them.
+    pub fn echoing() -> Self {
+        Self {
+            echo: true,
+            ..Self::default()
+        }
+    }
+
+    /// The records written so far, oldest first: the events, and the start
+    /// and end of the spans, with the fields of their spans.
+    pub fn records(&self) -> Vec<serde_json::Value> {
+        let written = self.written.lock().unwrap();
+
+        String::from_utf8_lossy(&written)
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()
+    }
+
+    /// The records with `field` set to `value`, e.g. those of a span.
+    pub fn records_with(&self, field: &str, value: &str) -> Vec<serde_json::Value> {
+        self.records()
+            .into_iter()
+            .filter(|record| record[field].as_str() == Some(value))
+            .collect()
+    }
+}
+
+impl Write for TestSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().unwrap().extend_from_slice(buf);
+        if self.echo {
+            io::stdout().write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for TestSink {
+    type Writer = Self;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
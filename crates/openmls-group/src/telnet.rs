@@ -1,6 +1,9 @@
 use std::io;
 use tokio_util::{bytes::Buf, codec::Decoder};
 
+/// The ciphersuite of groups created with `c#cgw`.
+const DEFAULT_CIPHERSUITE: &str = "x25519";
+
 pub struct TelnetCodec {
     current_line: Vec<u8>,
 }
@@ -13,11 +16,40 @@ impl TelnetCodec {
     }
 }
 
+impl Default for TelnetCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug)]
 pub enum Item {
     Line(Vec<u8>),
+    SetUser(String),
+    // Access token of the AS
+    Login(String),
     PublishKeyPackage,
-    CreateGroup(Vec<u8>),
+    // Ciphersuite by name, and identities
+    CreateGroup(String, Vec<String>),
+    // Room by name, and identities
+    CreateRoomGroup(String, Vec<String>),
+    RemoveMembers(Vec<String>),
+    LeaveGroup,
+    ProposeAdd(Vec<String>),
+    ProposeRemove(Vec<String>),
+    ProposeUpdate,
+    CommitProposals,
+    JoinExternal(String),
+    AllowExternalJoin(Vec<String>),
+    Revoke(String),
+    Rotate,
+    // Presence in the group
+    Who,
+    Away,
+    Back,
+    Typing,
+    // The last lines of the group
+    History(usize),
     ShowKPDetails,
     SE,
     DataMark,
@@ -56,7 +88,7 @@ impl Decoder for TelnetCodec {
                     }
                     ParseIacResult::NeedMore => return Ok(None),
                     ParseIacResult::Item(item) => return Ok(Some(item)),
-                    ParseIacResult::NOP => { /* go around loop */ }
+                    ParseIacResult::Nop => { /* go around loop */ }
                     ParseIacResult::EraseCharacter => {
                         self.current_line.pop();
                     }
@@ -92,7 +124,7 @@ enum ParseIacResult {
     Invalid(String),
     NeedMore,
     Item(Item),
-    NOP,
+    Nop,
     EraseCharacter,
     EraseLine,
     Escaped,
@@ -111,7 +143,7 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
 
     match bytes[1] {
         240 => (ParseIacResult::Item(Item::SE), 2),
-        241 => (ParseIacResult::NOP, 2),
+        241 => (ParseIacResult::Nop, 2),
         242 => (ParseIacResult::Item(Item::DataMark), 2),
         243 => (ParseIacResult::Item(Item::Break), 2),
         244 => (ParseIacResult::Item(Item::InterruptProcess), 2),
@@ -134,45 +166,156 @@ fn try_parse_iac(bytes: &[u8]) -> (ParseIacResult, usize) {
 }
 
 fn is_three_byte_iac(byte: u8) -> bool {
-    match byte {
-        251..=254 => true,
-        _ => false,
-    }
+    matches!(byte, 251..=254)
 }
 
 // Mark: Openmls
-fn parse_line(line: Vec<u8>) -> Option<Item> {
-    println!("[Client] sent command in byte {:?}", line);
-    // c#pkp == command: publish key package
-    if line.to_vec() == b"c#pkp" {
-        println!("[Client] Publishing its keypackage");
+// Also parses the text messages of WebSocket clients
+pub fn parse_line(line: Vec<u8>) -> Option<Item> {
+    // c#lgn == command: [l]o[g] i[n] with an access token of the AS, as a
+    // device of its user. Parsed before the line is logged.
+    if let Some(args) = line.strip_prefix(b"c#lgn") {
+        let token = String::from_utf8_lossy(args).trim().to_string();
+
+        return Some(Item::Login(token));
+    }
 
+    tracing::debug!(line = ?line, "Received a line");
+    // c#usr == command: become a device of [us]e[r], before joining a group
+    if let Some(args) = line.strip_prefix(b"c#usr") {
+        let user = String::from_utf8_lossy(args).trim().to_string();
+
+        return Some(Item::SetUser(user));
+    }
+
+    // c#pkp == command: publish key package
+    if line == b"c#pkp" {
         return Some(Item::PublishKeyPackage);
     }
 
-    // c#cgw == command: [c]reate [g]roup [w]ith ids of participants
+    // c#cgw == command: [c]reate [g]roup [w]ith identities of participants
     // separates by space
-    if line.to_vec()[0..4] == b"c#cgw".to_vec() {
-        let curr = &line[5..];
-        let mut ids: Vec<u8> = Vec::new();
-        for value in curr {
-            // 32 is space
-            if value == &32 {
-                continue;
-            }
+    if let Some(args) = line.strip_prefix(b"c#cgw") {
+        return Some(Item::CreateGroup(
+            DEFAULT_CIPHERSUITE.to_string(),
+            parse_identities(args),
+        ));
+    }
 
-            ids.push(value.clone());
-        }
+    // c#cgr == command: [c]reate the [g]roup of a [r]oom, with identities of
+    // participants the room allows, separated by space
+    if let Some(args) = line.strip_prefix(b"c#cgr") {
+        let mut args = parse_identities(args).into_iter();
+        let room = args.next().unwrap_or_default();
 
-        return Some(Item::CreateGroup(ids));
+        return Some(Item::CreateRoomGroup(room, args.collect()));
     }
 
-    // c#skd == command: [s]how [k]akacge [d]etails
-    if line.to_vec() == b"c#skd" {
-        println!("[Client] Show keypackage details");
+    // c#cgs == command: [c]reate [g]roup with a ciphersuite, x25519 or p256,
+    // and identities of participants, separated by space
+    if let Some(args) = line.strip_prefix(b"c#cgs") {
+        let mut args = parse_identities(args).into_iter();
+        let ciphersuite = args.next().unwrap_or_default();
+
+        return Some(Item::CreateGroup(ciphersuite, args.collect()));
+    }
+
+    // c#rmm == command: [r]e[m]ove [m]embers with identities
+    // separates by space
+    if let Some(args) = line.strip_prefix(b"c#rmm") {
+        return Some(Item::RemoveMembers(parse_identities(args)));
+    }
+
+    // c#lvg == command: [l]ea[v]e [g]roup
+    if line == b"c#lvg" {
+        return Some(Item::LeaveGroup);
+    }
+
+    // c#pad == command: [p]ropose to [ad]d identities, without committing
+    // separates by space
+    if let Some(args) = line.strip_prefix(b"c#pad") {
+        return Some(Item::ProposeAdd(parse_identities(args)));
+    }
+
+    // c#prm == command: [p]ropose to [r]e[m]ove identities, without committing
+    // separates by space
+    if let Some(args) = line.strip_prefix(b"c#prm") {
+        return Some(Item::ProposeRemove(parse_identities(args)));
+    }
 
+    // c#pup == command: [p]ropose to [up]date its leaf, without committing
+    if line == b"c#pup" {
+        return Some(Item::ProposeUpdate);
+    }
+
+    // c#cmt == command: [c]o[m]mi[t] the pending proposals
+    if line == b"c#cmt" {
+        return Some(Item::CommitProposals);
+    }
+
+    // c#jxc == command: [j]oin group by e[x]ternal [c]ommit
+    if let Some(args) = line.strip_prefix(b"c#jxc") {
+        let group = String::from_utf8_lossy(args).trim().to_string();
+
+        return Some(Item::JoinExternal(group));
+    }
+
+    // c#alw == command: [al]lo[w] identities to join by external commit
+    // separates by space
+    if let Some(args) = line.strip_prefix(b"c#alw") {
+        return Some(Item::AllowExternalJoin(parse_identities(args)));
+    }
+
+    // c#rvk == command: [r]e[v]o[k]e the credential of one of its devices
+    if let Some(args) = line.strip_prefix(b"c#rvk") {
+        let identity = String::from_utf8_lossy(args).trim().to_string();
+
+        return Some(Item::Revoke(identity));
+    }
+
+    // c#rot == command: [rot]ate its credential to a new signature key
+    if line == b"c#rot" {
+        return Some(Item::Rotate);
+    }
+
+    // /who == command: the members of the group, with their presence
+    if line == b"/who" {
+        return Some(Item::Who);
+    }
+
+    // /away == command: tell the group you stepped away, /back when you are
+    // back
+    if line == b"/away" {
+        return Some(Item::Away);
+    }
+    if line == b"/back" {
+        return Some(Item::Back);
+    }
+
+    // /typing == command: tell the group you are typing, encrypted
+    if line == b"/typing" {
+        return Some(Item::Typing);
+    }
+
+    // /history N == command: the last N lines of the group archived by the
+    // delivery service, the ones the device can decrypt
+    if let Some(args) = line.strip_prefix(b"/history") {
+        if let Ok(last) = String::from_utf8_lossy(args).trim().parse() {
+            return Some(Item::History(last));
+        }
+    }
+
+    // c#skd == command: [s]how [k]akacge [d]etails
+    if line == b"c#skd" {
         return Some(Item::ShowKPDetails);
     }
 
-    return Some(Item::Line(line));
+    Some(Item::Line(line))
+}
+
+fn parse_identities(args: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(args)
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
 }
@@ -0,0 +1,7 @@
+-- Users log in with a password, only its argon2 hash is kept
+CREATE TABLE users (
+    username TEXT NOT NULL PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    -- Seconds since the Unix epoch
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,7 @@
+-- The credential registered for each identity, serialized as JSON
+CREATE TABLE credentials (
+    identity TEXT NOT NULL PRIMARY KEY,
+    credential TEXT NOT NULL,
+    -- Seconds since the Unix epoch
+    updated_at BIGINT NOT NULL
+);
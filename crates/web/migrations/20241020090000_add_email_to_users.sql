@@ -0,0 +1,3 @@
+-- Where the login OTPs and the device-link codes of a user are sent, none if
+-- not given
+ALTER TABLE users ADD COLUMN email TEXT;
@@ -0,0 +1,11 @@
+-- The signing keys of the AS, generated on the first start: the receipts and
+-- certificates issued before a restart still verify
+CREATE TABLE signing_keys (
+    name TEXT NOT NULL PRIMARY KEY,
+    -- Base64 encoded PKCS#8
+    private_key TEXT NOT NULL,
+    -- Base64 encoded DER, for the key of a CA
+    certificate TEXT,
+    -- Seconds since the Unix epoch
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,101 @@
+// This module turns the errors of the routes into responses: a `ResponseData`
+// JSON body with a stable error code for the clients to match on, a message
+// for humans and the HTTP status. An unexpected error hides its cause from the
+// client, and logs its whole chain.
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+
+use crate::utils::{error_chain_fmt, ResponseData};
+
+#[derive(thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("Something went wrong.")]
+    Unexpected(#[from] anyhow::Error),
+}
+
+/// The data of an error response.
+#[derive(serde::Serialize, serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct ErrorData {
+    pub error: String,
+}
+
+impl ApiError {
+    pub fn invalid_request(message: impl ToString) -> Self {
+        Self::InvalidRequest(message.to_string())
+    }
+
+    pub fn unauthorized(message: impl ToString) -> Self {
+        Self::Unauthorized(message.to_string())
+    }
+
+    pub fn forbidden(message: impl ToString) -> Self {
+        Self::Forbidden(message.to_string())
+    }
+
+    pub fn not_found(message: impl ToString) -> Self {
+        Self::NotFound(message.to_string())
+    }
+
+    pub fn conflict(message: impl ToString) -> Self {
+        Self::Conflict(message.to_string())
+    }
+
+    pub fn unexpected(err: impl Into<anyhow::Error>) -> Self {
+        Self::Unexpected(err.into())
+    }
+
+    /// The code of the error, it stays when the message changes.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::Conflict(_) => "conflict",
+            Self::Unexpected(_) => "internal_error",
+        }
+    }
+}
+
+impl std::fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::Unexpected(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if let Self::Unexpected(_) = self {
+            tracing::error!("{:?}", self);
+        }
+
+        HttpResponse::build(self.status_code()).json(ResponseData {
+            data: ErrorData {
+                error: self.error_code().to_string(),
+            },
+            message: self.to_string(),
+            code: self.status_code().as_u16(),
+        })
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::auth_service::{
+    Credential, CredentialError, CredentialType, CredentialValidator, Registry, RevocationListener,
+    SignatureScheme, TrustAnchor,
+};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, IsCa, KeyUsagePurpose, PublicKeyData,
+    SignatureAlgorithm, PKCS_ECDSA_P256_SHA256, PKCS_ED25519,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{self, Ed25519KeyPair, KeyPair},
+};
+
+use crate::storage::{SigningKey, Storage};
+
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+    #[error("Identity `{0}` is already registered")]
+    AlreadyRegistered(String),
+    #[error("Identity `{0}` is not registered")]
+    NotRegistered(String),
+    #[error("The credential of `{0}` is revoked")]
+    Revoked(String),
+    #[error(transparent)]
+    Storage(#[from] sqlx::Error),
+}
+
+/// Credentials registered with the AS, by identity. They are read from
+/// memory, the leaves are checked against them without waiting on the
+/// database, and written through to the storage if any.
+#[derive(Default, Debug)]
+pub struct CredentialStore {
+    credentials: RwLock<HashMap<String, Credential>>,
+    storage: Option<Storage>,
+    // Told about revocations, e.g. the delivery service
+    listeners: RwLock<Vec<Arc<dyn RevocationListener>>>,
+    // The CA the chains of X.509 credentials must end at
+    trust_anchor: Option<TrustAnchor>,
+    // The credentials of the DS sessions, registered once their user signed
+    // in with an access token
+    devices: Arc<Registry>,
+}
+
+impl CredentialStore {
+    pub fn with_trust_anchor(mut self, trust_anchor: TrustAnchor) -> Self {
+        self.trust_anchor = Some(trust_anchor);
+        self
+    }
+
+    /// A store of the credentials kept in `storage`.
+    pub async fn load(storage: Storage) -> Result<Self, StoreError> {
+        let credentials = storage
+            .credentials()
+            .await?
+            .into_iter()
+            .map(|credential| (credential.identity.clone(), credential))
+            .collect();
+
+        Ok(Self {
+            credentials: RwLock::new(credentials),
+            storage: Some(storage),
+            // The AS authenticates the users, a session picks none itself.
+            devices: Arc::new(Registry::default().signed_in_only()),
+            ..Self::default()
+        })
+    }
+
+    pub async fn insert(&self, credential: Credential) -> Result<(), StoreError> {
+        if self.get(&credential.identity).is_some() {
+            return Err(StoreError::AlreadyRegistered(credential.identity));
+        }
+        if let Some(storage) = &self.storage {
+            // Registered meanwhile
+            if !storage.insert_credential(&credential).await? {
+                return Err(StoreError::AlreadyRegistered(credential.identity));
+            }
+        }
+        let mut credentials = self.credentials.write().unwrap();
+        if credentials.contains_key(&credential.identity) {
+            return Err(StoreError::AlreadyRegistered(credential.identity));
+        }
+        credentials.insert(credential.identity.clone(), credential);
+
+        Ok(())
+    }
+
+    pub fn get(&self, identity: &str) -> Option<Credential> {
+        self.credentials.read().unwrap().get(identity).cloned()
+    }
+
+    /// Replaces the credential of a registered identity with one for a new
+    /// signature key, returns the previous one. A revoked credential can not
+    /// be rotated, an expired one can.
+    pub async fn rotate(&self, credential: Credential) -> Result<Credential, StoreError> {
+        let previous = self
+            .get(&credential.identity)
+            .ok_or_else(|| StoreError::NotRegistered(credential.identity.clone()))?;
+        if previous.revoked {
+            return Err(StoreError::Revoked(credential.identity));
+        }
+        if let Some(storage) = &self.storage {
+            storage.update_credential(&credential).await?;
+        }
+        self.credentials
+            .write()
+            .unwrap()
+            .insert(credential.identity.clone(), credential);
+
+        Ok(previous)
+    }
+
+    /// Where the DS sessions register the credentials of their devices.
+    pub fn devices(&self) -> Arc<Registry> {
+        self.devices.clone()
+    }
+
+    pub fn subscribe(&self, listener: Arc<dyn RevocationListener>) {
+        self.listeners.write().unwrap().push(listener);
+    }
+
+    /// Marks the credential of an identity as revoked and tells the
+    /// listeners, or revokes the device of a DS session if the identity is
+    /// one. Revoking twice is not an error.
+    pub async fn revoke(&self, identity: &str) -> Result<Credential, StoreError> {
+        let Some(mut credential) = self.get(identity) else {
+            // The registry tells the delivery service itself
+            return self
+                .devices
+                .revoke(identity)
+                .ok()
+                .and_then(|revoked| revoked.into_iter().next())
+                .ok_or_else(|| StoreError::NotRegistered(identity.to_string()));
+        };
+        credential.revoked = true;
+        if let Some(storage) = &self.storage {
+            storage.update_credential(&credential).await?;
+        }
+        if let Some(stored) = self.credentials.write().unwrap().get_mut(identity) {
+            stored.revoked = true;
+        }
+        for listener in self.listeners.read().unwrap().iter() {
+            listener.revoked(identity);
+        }
+
+        Ok(credential)
+    }
+}
+
+/// Lets a group member embedded in the service check leaves against the store,
+/// or the devices of the DS sessions for the keys not registered here.
+impl CredentialValidator for CredentialStore {
+    fn validate(&self, identity: &[u8], signature_key: &[u8]) -> Result<(), CredentialError> {
+        let Some(credential) = self.get(&String::from_utf8_lossy(identity)) else {
+            return self.devices.validate(identity, signature_key);
+        };
+
+        credential
+            .check(signature_key, SystemTime::now(), self.trust_anchor.as_ref())
+            .or_else(|err| match err {
+                // A session signed in as this identity, unless it is revoked
+                CredentialError::KeyMismatch(_) if !credential.revoked => self
+                    .devices
+                    .validate(identity, signature_key)
+                    .map_err(|_| err),
+                err => Err(err),
+            })
+    }
+
+    fn trust_anchor(&self) -> Option<&TrustAnchor> {
+        self.trust_anchor.as_ref()
+    }
+}
+
+/// Whether `signature` over `message` was made with the signature key of
+/// `credential`, e.g. to prove a rotation was asked by the key holder.
+pub fn signed_by(credential: &Credential, message: &[u8], signature: &[u8]) -> bool {
+    let algorithm: &dyn signature::VerificationAlgorithm = match credential.signature_scheme {
+        SignatureScheme::Ed25519 => &signature::ED25519,
+        SignatureScheme::EcdsaSecp256r1 => &signature::ECDSA_P256_SHA256_ASN1,
+    };
+
+    signature::UnparsedPublicKey::new(algorithm, &credential.public_key)
+        .verify(message, signature)
+        .is_ok()
+}
+
+/// How far from ours the time a session request was signed at can be.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// What a device signs with its registered key to open a session.
+pub fn session_message(identity: &str, signed_at: u64) -> Vec<u8> {
+    format!("session:{}:{}", identity, signed_at).into_bytes()
+}
+
+/// What a device signs with the key it registers, to prove it holds it.
+pub fn registration_message(identity: &str) -> Vec<u8> {
+    format!("register:{}", identity).into_bytes()
+}
+
+/// The AS signing key, receipts are verified with its public key.
+pub struct Issuer {
+    key_pair: Ed25519KeyPair,
+}
+
+impl Issuer {
+    /// The issuer key kept in `storage`, generated on the first start.
+    pub async fn load(storage: &Storage) -> Result<Self, anyhow::Error> {
+        let key = load_signing_key(storage, "issuer", || {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .map_err(|_| anyhow::anyhow!("Failed to generate the issuer key"))?;
+
+            Ok(SigningKey {
+                private_key: pkcs8.as_ref().to_vec(),
+                certificate: None,
+            })
+        })
+        .await?;
+        let key_pair = Ed25519KeyPair::from_pkcs8(&key.private_key)
+            .map_err(|_| anyhow::anyhow!("Failed to load the issuer key"))?;
+
+        Ok(Self { key_pair })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        self.key_pair.public_key().as_ref()
+    }
+
+    /// Signs the issuance of `credential`.
+    pub fn issue(&self, credential: &Credential) -> IssuanceReceipt {
+        let credential = CredentialRecord::from(credential);
+        let issued_at = unix_seconds(SystemTime::now());
+        let signature = self.key_pair.sign(&signed_bytes(&credential, issued_at));
+
+        IssuanceReceipt {
+            credential,
+            issued_at,
+            issuer_key: STANDARD.encode(self.public_key()),
+            signature: STANDARD.encode(signature),
+        }
+    }
+}
+
+/// The local CA of the AS, it certifies the signature keys of registered
+/// identities for X.509 credentials. Its certificate is the trust anchor.
+pub struct CertificateAuthority {
+    certificate: rcgen::Certificate,
+    key_pair: rcgen::KeyPair,
+    // As stored, the trust anchor of the chains issued before a restart
+    der: Vec<u8>,
+}
+
+impl CertificateAuthority {
+    /// The CA key and certificate kept in `storage`, generated on the first
+    /// start.
+    pub async fn load(storage: &Storage) -> Result<Self, anyhow::Error> {
+        let key = load_signing_key(storage, "ca", || {
+            let key_pair = rcgen::KeyPair::generate_for(&PKCS_ED25519)?;
+            let mut params = CertificateParams::new(Vec::new())?;
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "Chat AS local CA");
+            // Only issues leaf certificates
+            params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign];
+            let certificate = params.self_signed(&key_pair)?;
+
+            Ok(SigningKey {
+                private_key: key_pair.serialize_der(),
+                certificate: Some(certificate.der().to_vec()),
+            })
+        })
+        .await?;
+        let key_pair = rcgen::KeyPair::try_from(key.private_key.as_slice())?;
+        let der = key
+            .certificate
+            .ok_or_else(|| anyhow::anyhow!("The CA key has no certificate"))?;
+        // Signs the leaves under the same name and key identifier
+        let certificate =
+            CertificateParams::from_ca_cert_der(&der.clone().into())?.self_signed(&key_pair)?;
+
+        Ok(Self {
+            certificate,
+            key_pair,
+            der,
+        })
+    }
+
+    pub fn trust_anchor(&self) -> TrustAnchor {
+        TrustAnchor::from_der(self.der.clone()).expect("The CA certificate is a CA certificate")
+    }
+
+    /// Certifies the key of a basic credential, the X.509 credential is valid
+    /// for the same time.
+    pub fn certify(&self, credential: &Credential) -> Result<Credential, anyhow::Error> {
+        let mut params = CertificateParams::new(Vec::new())?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, credential.identity.as_str());
+        params.not_before = credential.valid_from.into();
+        if let Some(valid_until) = credential.valid_until {
+            params.not_after = valid_until.into();
+        }
+        let leaf = params.signed_by(&SubjectKey(credential), &self.certificate, &self.key_pair)?;
+
+        Ok(Credential::x509(
+            vec![leaf.der().to_vec()],
+            &self.trust_anchor(),
+        )?)
+    }
+}
+
+/// The signing key `name` kept in `storage`, the one of `generate` on the
+/// first start.
+async fn load_signing_key(
+    storage: &Storage,
+    name: &str,
+    generate: impl FnOnce() -> Result<SigningKey, anyhow::Error>,
+) -> Result<SigningKey, anyhow::Error> {
+    if let Some(key) = storage.signing_key(name).await? {
+        return Ok(key);
+    }
+    storage.insert_signing_key(name, &generate()?).await?;
+
+    // Another instance sharing the database may have stored its key first
+    storage
+        .signing_key(name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("The {} key was not stored", name))
+}
+
+/// The signature key of a credential, as the CA certifies it.
+struct SubjectKey<'a>(&'a Credential);
+
+impl PublicKeyData for SubjectKey<'_> {
+    fn der_bytes(&self) -> &[u8] {
+        &self.0.public_key
+    }
+
+    fn algorithm(&self) -> &SignatureAlgorithm {
+        match self.0.signature_scheme {
+            SignatureScheme::Ed25519 => &PKCS_ED25519,
+            SignatureScheme::EcdsaSecp256r1 => &PKCS_ECDSA_P256_SHA256,
+        }
+    }
+}
+
+/// A credential as exposed by the API, keys in base64 and times in seconds
+/// since the Unix epoch.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, utoipa::ToSchema)]
+pub struct CredentialRecord {
+    pub identity: String,
+    #[schema(value_type = crate::openapi::CredentialType)]
+    pub credential_type: CredentialType,
+    #[schema(value_type = crate::openapi::SignatureScheme)]
+    pub signature_scheme: SignatureScheme,
+    pub signature_key: String,
+    pub valid_from: u64,
+    pub valid_until: Option<u64>,
+    pub revoked: bool,
+    // Base64 encoded DER, leaf first, for X.509 credentials
+    #[serde(default)]
+    pub certificate_chain: Vec<String>,
+}
+
+impl From<&Credential> for CredentialRecord {
+    fn from(credential: &Credential) -> Self {
+        Self {
+            identity: credential.identity.clone(),
+            credential_type: credential.credential_type,
+            signature_scheme: credential.signature_scheme,
+            signature_key: STANDARD.encode(&credential.public_key),
+            valid_from: unix_seconds(credential.valid_from),
+            valid_until: credential.valid_until.map(unix_seconds),
+            revoked: credential.revoked,
+            certificate_chain: credential
+                .certificate_chain
+                .iter()
+                .map(|certificate| STANDARD.encode(certificate))
+                .collect(),
+        }
+    }
+}
+
+/// Proof that the AS registered a credential, signed by the [`Issuer`].
+#[derive(serde::Serialize, serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct IssuanceReceipt {
+    pub credential: CredentialRecord,
+    pub issued_at: u64,
+    pub issuer_key: String,
+    pub signature: String,
+}
+
+impl IssuanceReceipt {
+    /// Whether the receipt was signed by the issuer with `issuer_key`.
+    pub fn verify(&self, issuer_key: &[u8]) -> bool {
+        let Ok(signature) = STANDARD.decode(&self.signature) else {
+            return false;
+        };
+
+        signature::UnparsedPublicKey::new(&signature::ED25519, issuer_key)
+            .verify(&signed_bytes(&self.credential, self.issued_at), &signature)
+            .is_ok()
+    }
+}
+
+fn signed_bytes(credential: &CredentialRecord, issued_at: u64) -> Vec<u8> {
+    serde_json::to_vec(&(credential, issued_at)).expect("A record always serializes")
+}
+
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
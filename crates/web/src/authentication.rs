@@ -0,0 +1,14 @@
+// This module authenticates the clients of the web API and of the delivery
+// service: users log in with a password or an emailed OTP, devices with their
+// registered key or an emailed device-link code, and all get an access token
+// and a refresh token.
+
+mod codes;
+mod middleware;
+mod tokens;
+mod users;
+
+pub use codes::*;
+pub use middleware::*;
+pub use tokens::*;
+pub use users::*;
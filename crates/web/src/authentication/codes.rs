@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// How long an emailed login OTP is accepted.
+pub const OTP_VALIDITY: Duration = Duration::from_secs(10 * 60);
+/// Wrong guesses before a login OTP is dropped.
+pub const MAX_OTP_ATTEMPTS: u32 = 5;
+/// How long an emailed device-link code can be redeemed.
+pub const DEVICE_LINK_VALIDITY: Duration = Duration::from_secs(10 * 60);
+
+const OTP_DIGITS: usize = 6;
+const DEVICE_LINK_CODE_LENGTH: usize = 8;
+// No 0/O or 1/I to mix up when typed on the new device
+const DEVICE_LINK_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+#[derive(Debug)]
+struct PendingOtp {
+    otp: String,
+    expires_at: SystemTime,
+    attempts: u32,
+}
+
+/// The one-time codes the AS emailed: the login OTPs of the users, and the
+/// codes that link a new device to a user.
+#[derive(Debug, Default)]
+pub struct OneTimeCodes {
+    // By username, a new one replaces the last
+    login_otps: RwLock<HashMap<String, PendingOtp>>,
+    // User and expiry, by code
+    device_links: RwLock<HashMap<String, (String, SystemTime)>>,
+}
+
+impl OneTimeCodes {
+    /// A new login OTP of `username`, to email.
+    pub fn login_otp(&self, username: &str) -> String {
+        let otp = random_code(b"0123456789", OTP_DIGITS);
+
+        let mut login_otps = self.login_otps.write().unwrap();
+        login_otps.retain(|_, pending| pending.expires_at > SystemTime::now());
+        login_otps.insert(
+            username.to_string(),
+            PendingOtp {
+                otp: otp.clone(),
+                expires_at: SystemTime::now() + OTP_VALIDITY,
+                attempts: 0,
+            },
+        );
+
+        otp
+    }
+
+    /// Uses up the login OTP of `username` if `otp` is it. Too many wrong
+    /// guesses drop it.
+    pub fn check_login_otp(&self, username: &str, otp: &str) -> bool {
+        let mut login_otps = self.login_otps.write().unwrap();
+        let Some(pending) = login_otps.get_mut(username) else {
+            return false;
+        };
+        if pending.expires_at <= SystemTime::now() {
+            login_otps.remove(username);
+            return false;
+        }
+        if pending.otp != otp {
+            pending.attempts += 1;
+            if pending.attempts >= MAX_OTP_ATTEMPTS {
+                login_otps.remove(username);
+            }
+            return false;
+        }
+        login_otps.remove(username);
+
+        true
+    }
+
+    /// A new code that opens a session of `user` on another device, to email.
+    pub fn device_link(&self, user: &str) -> String {
+        let code = random_code(DEVICE_LINK_ALPHABET, DEVICE_LINK_CODE_LENGTH);
+
+        let mut device_links = self.device_links.write().unwrap();
+        device_links.retain(|_, (_, expires_at)| *expires_at > SystemTime::now());
+        device_links.insert(
+            code.clone(),
+            (user.to_string(), SystemTime::now() + DEVICE_LINK_VALIDITY),
+        );
+
+        code
+    }
+
+    /// Uses up a device-link code, returns the user to open a session of.
+    pub fn redeem_device_link(&self, code: &str) -> Option<String> {
+        let (user, expires_at) = self
+            .device_links
+            .write()
+            .unwrap()
+            .remove(&code.trim().to_uppercase())?;
+
+        (expires_at > SystemTime::now()).then_some(user)
+    }
+}
+
+fn random_code(alphabet: &[u8], length: usize) -> String {
+    // Bytes past the last whole round of the alphabet are drawn again, no
+    // character is likelier than another
+    let limit = 256 - 256 % alphabet.len();
+    let random = SystemRandom::new();
+    let mut code = String::with_capacity(length);
+    while code.len() < length {
+        let mut byte = [0u8; 1];
+        random
+            .fill(&mut byte)
+            .expect("The system random generator is available");
+        if (byte[0] as usize) < limit {
+            code.push(alphabet[byte[0] as usize % alphabet.len()] as char);
+        }
+    }
+
+    code
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::HashSet,
+    future::{ready, Ready},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Payload, ServiceRequest, ServiceResponse},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    web, FromRequest, HttpMessage, HttpRequest,
+};
+use chat_core::ext_mls::user_of;
+use openmls_group::client::TokenVerifier;
+
+use super::{TokenError, TokenIssuer};
+use crate::{api_error::ApiError, auth_service::CredentialStore};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error(transparent)]
+    Token(#[from] TokenError),
+    #[error("The credential of `{0}` is revoked")]
+    Revoked(String),
+}
+
+/// Checks access tokens, for the middleware and the telnet logins.
+pub struct Authenticator {
+    tokens: web::Data<TokenIssuer>,
+    store: web::Data<CredentialStore>,
+}
+
+impl Authenticator {
+    pub fn new(tokens: web::Data<TokenIssuer>, store: web::Data<CredentialStore>) -> Self {
+        Self { tokens, store }
+    }
+
+    /// The subject of a valid access token. The token of a device is refused
+    /// as soon as its credential is revoked.
+    pub fn authenticate(&self, token: &str) -> Result<String, AuthError> {
+        let claims = self.tokens.verify(token)?;
+        if self
+            .store
+            .get(&claims.sub)
+            .is_some_and(|credential| credential.revoked)
+        {
+            return Err(AuthError::Revoked(claims.sub));
+        }
+
+        Ok(claims.sub)
+    }
+}
+
+impl TokenVerifier for Authenticator {
+    fn verify(&self, token: &str) -> Option<String> {
+        self.authenticate(token).ok()
+    }
+}
+
+/// The user or device an access token was issued to, set by
+/// [`reject_anonymous_users`].
+#[derive(Clone, Debug)]
+pub struct Authenticated(pub String);
+
+impl Authenticated {
+    /// The user, of a device or itself.
+    pub fn user(&self) -> String {
+        String::from_utf8_lossy(user_of(self.0.as_bytes())).into_owned()
+    }
+}
+
+impl FromRequest for Authenticated {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<Authenticated>()
+                .cloned()
+                .ok_or_else(|| ApiError::unauthorized("Not authenticated.")),
+        )
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct TokenQuery {
+    // Browsers can't set the header of a WebSocket request
+    token: Option<String>,
+}
+
+/// The access token of a request, as a bearer token or a query parameter.
+fn access_token(req: &HttpRequest) -> Option<String> {
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    bearer.or_else(|| {
+        web::Query::<TokenQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().token)
+    })
+}
+
+/// The users allowed on the admin API, from the configuration.
+#[derive(Debug, Default)]
+pub struct Admins(pub HashSet<String>);
+
+/// The identity of the access token of a request.
+fn authenticate(req: &ServiceRequest) -> Result<Authenticated, ApiError> {
+    let token = access_token(req.request())
+        .ok_or_else(|| ApiError::unauthorized("An access token is required."))?;
+    let authenticator = req
+        .app_data::<web::Data<Authenticator>>()
+        .ok_or_else(|| anyhow::anyhow!("The authenticator is not configured."))?;
+    let identity = authenticator
+        .authenticate(&token)
+        .map_err(|err| match err {
+            AuthError::Token(_) => ApiError::unauthorized(err),
+            AuthError::Revoked(_) => ApiError::forbidden(err),
+        })?;
+
+    Ok(Authenticated(identity))
+}
+
+/// Lets only the requests with a valid access token through, and hands the
+/// [`Authenticated`] identity to the route.
+pub async fn reject_anonymous_users(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let authenticated = authenticate(&req)?;
+
+    req.extensions_mut().insert(authenticated);
+    next.call(req).await
+}
+
+/// Lets only the requests of an admin, or of one of its devices, through.
+pub async fn reject_non_admin_users(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let authenticated = authenticate(&req)?;
+    let is_admin = req
+        .app_data::<web::Data<Admins>>()
+        .is_some_and(|admins| admins.0.contains(&authenticated.user()));
+    if !is_admin {
+        return Err(ApiError::forbidden(format!("{} is not an admin.", authenticated.0)).into());
+    }
+
+    req.extensions_mut().insert(authenticated);
+    next.call(req).await
+}
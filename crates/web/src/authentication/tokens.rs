@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use ring::rand::{SecureRandom, SystemRandom};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+use crate::auth_service::unix_seconds;
+
+/// How long an access token is accepted, a revoked identity keeps it until
+/// then where the store is not checked.
+pub const ACCESS_TOKEN_VALIDITY: Duration = Duration::from_secs(15 * 60);
+/// How long a refresh token can be traded for a new session.
+pub const REFRESH_TOKEN_VALIDITY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[derive(thiserror::Error, Debug)]
+pub enum TokenError {
+    #[error("The token is malformed")]
+    Malformed,
+    #[error("The token is not signed by the AS")]
+    InvalidSignature,
+    #[error("The token was not issued by the AS, or was used up")]
+    Unknown,
+    #[error("The token expired")]
+    Expired,
+}
+
+/// What an access token asserts: its subject, a user name or the identity of
+/// a device, until `exp` in seconds since the Unix epoch.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: u64,
+}
+
+/// The tokens of a session as exposed by the API, expiry of the access token
+/// in seconds since the Unix epoch.
+#[derive(serde::Serialize, serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct SessionToken {
+    pub identity: String,
+    // Sent as a bearer token
+    pub token: String,
+    // Traded once for new tokens
+    pub refresh_token: String,
+    pub expires_at: u64,
+}
+
+/// Signs the access tokens with the HMAC secret of the configuration, and
+/// keeps the refresh tokens it handed out.
+#[derive(Debug)]
+pub struct TokenIssuer {
+    hmac_secret: Secret<String>,
+    // Subject and expiry, by refresh token
+    refresh_tokens: RwLock<HashMap<String, (String, SystemTime)>>,
+}
+
+impl TokenIssuer {
+    pub fn new(hmac_secret: Secret<String>) -> Self {
+        Self {
+            hmac_secret,
+            refresh_tokens: RwLock::default(),
+        }
+    }
+
+    /// Opens a session of `subject`, authenticated by the caller.
+    pub fn open_session(&self, subject: &str) -> SessionToken {
+        let expires_at = SystemTime::now() + ACCESS_TOKEN_VALIDITY;
+        let claims = Claims {
+            sub: subject.to_string(),
+            exp: unix_seconds(expires_at),
+        };
+        let refresh_token = random_token();
+
+        let mut refresh_tokens = self.refresh_tokens.write().unwrap();
+        refresh_tokens.retain(|_, (_, expiry)| *expiry > SystemTime::now());
+        refresh_tokens.insert(
+            refresh_token.clone(),
+            (
+                subject.to_string(),
+                SystemTime::now() + REFRESH_TOKEN_VALIDITY,
+            ),
+        );
+
+        SessionToken {
+            identity: subject.to_string(),
+            token: self.sign(&claims),
+            refresh_token,
+            expires_at: claims.exp,
+        }
+    }
+
+    /// Uses up a refresh token, returns its subject to open a new session of.
+    pub fn redeem(&self, refresh_token: &str) -> Result<String, TokenError> {
+        let (subject, expires_at) = self
+            .refresh_tokens
+            .write()
+            .unwrap()
+            .remove(refresh_token)
+            .ok_or(TokenError::Unknown)?;
+        if expires_at <= SystemTime::now() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(subject)
+    }
+
+    /// The claims of an access token signed with our secret, not expired.
+    pub fn verify(&self, token: &str) -> Result<Claims, TokenError> {
+        let (payload, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| TokenError::Malformed)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::InvalidSignature)?;
+        let claims: Claims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|payload| serde_json::from_slice(&payload).ok())
+            .ok_or(TokenError::Malformed)?;
+        if claims.exp <= unix_seconds(SystemTime::now()) {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(claims)
+    }
+
+    fn sign(&self, claims: &Claims) -> String {
+        let payload = serde_json::to_vec(claims).expect("Claims always serialize");
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+        let signature = self.mac(&payload).finalize().into_bytes();
+
+        format!("{}.{}", payload, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC takes keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+fn random_token() -> String {
+    let mut token = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut token)
+        .expect("The system random generator is available");
+
+    URL_SAFE_NO_PAD.encode(token)
+}
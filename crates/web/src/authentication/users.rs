@@ -0,0 +1,74 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::storage::Storage;
+
+#[derive(thiserror::Error, Debug)]
+pub enum UserError {
+    #[error("User `{0}` is already registered")]
+    AlreadyRegistered(String),
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+    #[error(transparent)]
+    Hash(#[from] argon2::password_hash::Error),
+    #[error(transparent)]
+    Storage(#[from] sqlx::Error),
+}
+
+/// The users of the chat, by name, the argon2 hash of their password and
+/// their email.
+#[derive(Debug)]
+pub struct UserStore {
+    storage: Storage,
+}
+
+impl UserStore {
+    pub fn new(storage: Storage) -> Self {
+        Self { storage }
+    }
+
+    pub async fn register(
+        &self,
+        username: &str,
+        password: Secret<String>,
+        email: Option<&str>,
+    ) -> Result<(), UserError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.expose_secret().as_bytes(), &salt)?
+            .to_string();
+
+        if !self
+            .storage
+            .insert_user(username, &password_hash, email)
+            .await?
+        {
+            return Err(UserError::AlreadyRegistered(username.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the password of a user, an unknown user is told apart from a
+    /// wrong password by no one.
+    pub async fn verify(&self, username: &str, password: Secret<String>) -> Result<(), UserError> {
+        let password_hash = self
+            .storage
+            .password_hash(username)
+            .await?
+            .ok_or(UserError::InvalidCredentials)?;
+        let password_hash = PasswordHash::new(&password_hash)?;
+
+        Argon2::default()
+            .verify_password(password.expose_secret().as_bytes(), &password_hash)
+            .map_err(|_| UserError::InvalidCredentials)
+    }
+
+    /// Where the one-time codes of a user are sent.
+    pub async fn email(&self, username: &str) -> Result<Option<String>, UserError> {
+        Ok(self.storage.email(username).await?)
+    }
+}
@@ -1,11 +1,17 @@
 use std::path::Path;
 
-use secrecy::Secret;
-use serde_aux::field_attributes::deserialize_number_from_string;
+use secrecy::{ExposeSecret, Secret};
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
+
+use crate::email_client::parse_email;
 
 #[derive(serde::Deserialize, Clone)]
 pub struct Settings {
     pub application: ApplicationSettings,
+    pub database: DatabaseSettings,
+    pub email_client: EmailClientSettings,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -15,6 +21,104 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // Telnet clients of the delivery service, not accepted if not set
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub telnet_port: Option<u16>,
+    // Admin console of the delivery service, on localhost, not run if not set
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub admin_console_port: Option<u16>,
+    // Users allowed on the admin API
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+/// Where the AS keeps its users and credentials.
+#[derive(serde::Deserialize, Clone)]
+pub struct DatabaseSettings {
+    pub engine: DatabaseEngine,
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: String,
+    pub password: Secret<String>,
+    // The database file for SQLite
+    pub database_name: String,
+    pub require_ssl: bool,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseEngine {
+    // A file, for local runs and the tests
+    Sqlite,
+    // Any Postgres compatible server, for production
+    Postgres,
+}
+
+impl DatabaseSettings {
+    /// The server, without a database, e.g. to create one. SQLite has none.
+    pub fn without_db(&self) -> Secret<String> {
+        let ssl_mode = if self.require_ssl {
+            "require"
+        } else {
+            "prefer"
+        };
+        Secret::new(format!(
+            "postgres://{}:{}@{}:{}?sslmode={}",
+            self.username,
+            self.password.expose_secret(),
+            self.host,
+            self.port,
+            ssl_mode
+        ))
+    }
+
+    pub fn with_db(&self) -> Secret<String> {
+        match self.engine {
+            // Created if missing
+            DatabaseEngine::Sqlite => {
+                Secret::new(format!("sqlite://{}?mode=rwc", self.database_name))
+            }
+            DatabaseEngine::Postgres => {
+                let without_db = self.without_db();
+                let (server, ssl_mode) = without_db
+                    .expose_secret()
+                    .split_once('?')
+                    .expect("The server URL has the SSL mode");
+                Secret::new(format!("{}/{}?{}", server, self.database_name, ssl_mode))
+            }
+        }
+    }
+}
+
+/// How the AS sends its emails, the login OTPs and device-link codes.
+#[derive(serde::Deserialize, Clone)]
+pub struct EmailClientSettings {
+    pub kind: EmailClientKind,
+    pub base_url: String,
+    pub sender_email: String,
+    pub authorization_token: Secret<String>,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailClientKind {
+    // The HTTP API of an email provider, for production
+    Http,
+    // Kept in memory, for local runs and the tests
+    Capture,
+}
+
+impl EmailClientSettings {
+    pub fn sender(&self) -> Result<String, String> {
+        parse_email(&self.sender_email)
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_milliseconds)
+    }
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
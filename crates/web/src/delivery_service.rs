@@ -0,0 +1,145 @@
+// This module runs the Delivery Service (DS) actor that WebSocket and telnet
+// clients share. Its key package directory is also served over HTTP: clients
+// publish key packages to it, and claim the ones of the identities they add
+// to a group.
+
+use std::{net::SocketAddr, sync::Arc};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::auth_service::Registry;
+use openmls_group::{
+    accept::start_accept,
+    admin::start_admin_console,
+    client::{spawn_client, ClientInfo, TokenVerifier, Transport},
+    key_packages::{ClaimedKeyPackage, KeyPackageDirectory},
+    main_loop::{spawn_main_loop, ServerHandle},
+    metrics::Metrics,
+    rooms::RoomCatalog,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::auth_service::CredentialStore;
+
+/// A key package handed out by the directory.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, utoipa::ToSchema)]
+pub struct Claimed {
+    pub identity: String,
+    // Base64 encoded, TLS serialized
+    pub key_package: String,
+    // Stays in the directory, handed out again until replaced
+    pub last_resort: bool,
+}
+
+/// The key packages left for an identity, as exposed by the API.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, utoipa::ToSchema)]
+pub struct KeyPackageCount {
+    pub identity: String,
+    pub count: usize,
+    pub last_resort: bool,
+}
+
+impl From<ClaimedKeyPackage> for Claimed {
+    fn from(claimed: ClaimedKeyPackage) -> Self {
+        Self {
+            identity: claimed.identity,
+            key_package: STANDARD.encode(claimed.key_package),
+            last_resort: claimed.last_resort,
+        }
+    }
+}
+
+/// The DS actor, clients of every transport join the same groups through it.
+pub struct Delivery {
+    handle: ServerHandle,
+    // Where the sessions register their device credentials
+    registry: Arc<Registry>,
+    // The AS, the sessions check the leaves of the others against it
+    credentials: Arc<CredentialStore>,
+    // Managed on the admin API
+    rooms: Arc<RoomCatalog>,
+    // Published and claimed over HTTP too
+    key_packages: Arc<KeyPackageDirectory>,
+    // Shared with the web routes
+    metrics: Arc<Metrics>,
+}
+
+impl Delivery {
+    /// Spawns the main loop of the DS, it runs as long as the runtime. Its
+    /// sessions trust the credentials of `credentials`.
+    pub fn spawn(credentials: Arc<CredentialStore>) -> Self {
+        let rooms = Arc::new(RoomCatalog::default());
+        let key_packages = Arc::new(KeyPackageDirectory::new(credentials.clone()));
+        let metrics = Arc::new(Metrics::default());
+        let (handle, _join) = spawn_main_loop(rooms.clone(), key_packages.clone(), metrics.clone());
+        // Revoked by the AS or by a session, its leaves leave their groups.
+        credentials.subscribe(Arc::new(handle.clone()));
+        let registry = credentials.devices();
+        registry.subscribe(Arc::new(handle.clone()));
+
+        Self {
+            handle,
+            registry,
+            credentials,
+            rooms,
+            key_packages,
+            metrics,
+        }
+    }
+
+    /// The rooms whose membership the DS enforces.
+    pub fn rooms(&self) -> Arc<RoomCatalog> {
+        self.rooms.clone()
+    }
+
+    /// The key packages the DS sessions publish and claim.
+    pub fn key_packages(&self) -> Arc<KeyPackageDirectory> {
+        self.key_packages.clone()
+    }
+
+    /// The metrics registry of the DS, the web routes add theirs to it.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Accepts telnet clients on `bind` too, they log in with the access
+    /// tokens `verifier` accepts.
+    pub fn accept_telnet(&self, bind: SocketAddr, verifier: Arc<dyn TokenVerifier>) {
+        tokio::spawn(start_accept(
+            bind,
+            self.handle.clone(),
+            self.registry.clone(),
+            Some(self.credentials.clone()),
+            Some(verifier),
+        ));
+    }
+
+    /// Runs the admin console of the DS on `bind`, whoever connects to it is
+    /// an admin.
+    pub fn accept_admin_console(&self, bind: SocketAddr) {
+        tokio::spawn(start_admin_console(bind, self.handle.clone()));
+    }
+
+    /// Spawns the client actor of a device of `user`, authenticated by the
+    /// AS. Returns where to send its lines, commands or messages, and where
+    /// its lines come back.
+    pub fn connect(
+        &self,
+        ip: SocketAddr,
+        user: &str,
+    ) -> (UnboundedSender<String>, UnboundedReceiver<String>) {
+        let (to_client, incoming) = unbounded_channel();
+        let (outgoing, from_client) = unbounded_channel();
+        spawn_client(ClientInfo {
+            id: self.handle.next_id(),
+            ip,
+            handle: self.handle.clone(),
+            transport: Transport::Lines { incoming, outgoing },
+            registry: self.registry.clone(),
+            validator: Some(self.credentials.clone()),
+            user: Some(user.to_string()),
+            verifier: None,
+        });
+
+        (to_client, from_client)
+    }
+}
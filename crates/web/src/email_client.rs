@@ -0,0 +1,167 @@
+// This module sends the emails of the AS: the login OTPs and the device-link
+// codes. Production goes through the HTTP API of an email provider, local runs
+// and the tests keep the emails in memory to read them back.
+
+use std::sync::RwLock;
+
+use secrecy::{ExposeSecret, Secret};
+
+use crate::configuration::{EmailClientKind, EmailClientSettings};
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+/// An email, as sent by the AS.
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub recipient: String,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+pub fn parse_email(email: &str) -> Result<String, String> {
+    if !validator::validate_email(email) {
+        return Err(format!("{} is not a valid email.", email));
+    }
+
+    Ok(email.to_string())
+}
+
+#[derive(Debug)]
+pub enum EmailClient {
+    Http(HttpEmailClient),
+    Capture(EmailCapture),
+}
+
+impl EmailClient {
+    pub fn new(settings: &EmailClientSettings) -> Result<Self, String> {
+        Ok(match settings.kind {
+            EmailClientKind::Http => Self::Http(HttpEmailClient::new(
+                settings.base_url.clone(),
+                settings.sender()?,
+                settings.authorization_token.clone(),
+                settings.timeout(),
+            )),
+            EmailClientKind::Capture => Self::Capture(EmailCapture::default()),
+        })
+    }
+
+    pub async fn send_email(&self, email: Email) -> Result<(), EmailError> {
+        match self {
+            Self::Http(client) => client.send_email(&email).await,
+            Self::Capture(capture) => {
+                capture.send_email(email);
+                Ok(())
+            }
+        }
+    }
+
+    /// The emails kept in memory, `None` if they are really sent.
+    pub fn capture(&self) -> Option<&EmailCapture> {
+        match self {
+            Self::Http(_) => None,
+            Self::Capture(capture) => Some(capture),
+        }
+    }
+}
+
+/// Sends the emails with the Postmark compatible API at `base_url`.
+#[derive(Debug)]
+pub struct HttpEmailClient {
+    http_client: reqwest::Client,
+    base_url: String,
+    sender: String,
+    authorization_token: Secret<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+}
+
+impl HttpEmailClient {
+    pub fn new(
+        base_url: String,
+        sender: String,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("The HTTP client builds with a timeout");
+
+        Self {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+
+    pub async fn send_email(&self, email: &Email) -> Result<(), EmailError> {
+        let request_body = SendEmailRequest {
+            from: &self.sender,
+            to: &email.recipient,
+            subject: &email.subject,
+            html_body: &email.html_content,
+            text_body: &email.text_content,
+        };
+        self.http_client
+            .post(format!("{}/email", self.base_url))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Keeps the emails instead of sending them.
+#[derive(Debug, Default)]
+pub struct EmailCapture {
+    sent: RwLock<Vec<Email>>,
+}
+
+impl EmailCapture {
+    fn send_email(&self, email: Email) {
+        // Nothing else shows the codes to a local run, production sends them
+        tracing::info!(
+            recipient = %email.recipient,
+            subject = %email.subject,
+            text = %email.text_content,
+            "Captured email"
+        );
+        self.sent.write().unwrap().push(email);
+    }
+
+    /// All the emails, oldest first.
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.read().unwrap().clone()
+    }
+
+    /// The last email sent to `recipient`.
+    pub fn last_to(&self, recipient: &str) -> Option<Email> {
+        self.sent
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|email| email.recipient == recipient)
+            .cloned()
+    }
+}
@@ -1,5 +1,13 @@
+pub mod api_error;
+pub mod auth_service;
+pub mod authentication;
 pub mod configuration;
+pub mod delivery_service;
+pub mod email_client;
+pub mod metrics;
+pub mod openapi;
 mod routes;
 pub mod startup;
+pub mod storage;
 pub mod telemetry;
 pub mod utils;
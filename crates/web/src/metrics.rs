@@ -0,0 +1,51 @@
+// This module adds the metrics of the web routes to the registry of the DS,
+// both are served on `/metrics`.
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web,
+};
+use openmls_group::metrics::Metrics;
+use prometheus::{IntCounterVec, Opts};
+
+/// The requests the routes answered, by method and status.
+pub struct HttpMetrics {
+    requests: IntCounterVec,
+}
+
+impl HttpMetrics {
+    pub fn register(metrics: &Metrics) -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests answered by the AS"),
+            &["method", "status"],
+        )
+        .unwrap();
+        metrics.register(Box::new(requests.clone()));
+
+        Self { requests }
+    }
+}
+
+/// Counts every request with the status of its response, errors included.
+pub async fn count_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let http_metrics = req.app_data::<web::Data<HttpMetrics>>().cloned();
+    let res = next.call(req).await;
+    if let Some(http_metrics) = http_metrics {
+        let status = match &res {
+            Ok(res) => res.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+        http_metrics
+            .requests
+            .with_label_values(&[method.as_str(), status.as_str()])
+            .inc();
+    }
+
+    res
+}
@@ -0,0 +1,132 @@
+// This module generates the OpenAPI document of the web API from the route
+// handlers and their request and response types. The types of the other
+// crates are described by the schemas below, under their own names.
+
+use actix_web::{get, HttpResponse};
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi, ToSchema,
+};
+
+use crate::{
+    api_error::ErrorData,
+    auth_service::{CredentialRecord, IssuanceReceipt},
+    authentication::SessionToken,
+    delivery_service::{Claimed, KeyPackageCount},
+    routes,
+    utils::ResponseData,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "telnet-openmls-demo AS",
+        description = "The authentication service of the chat, and the key package directory \
+                       and admin API of its delivery service."
+    ),
+    paths(
+        routes::health_check,
+        routes::index,
+        openapi_json,
+        routes::get_metrics,
+        routes::post_identities,
+        routes::get_identity,
+        routes::rotate_identity,
+        routes::revoke_identity,
+        routes::get_ca_certificate,
+        routes::post_key_packages,
+        routes::claim_key_packages,
+        routes::count_key_packages,
+        routes::register_user,
+        routes::login,
+        routes::open_session,
+        routes::refresh_session,
+        routes::me,
+        routes::request_login_otp,
+        routes::login_with_otp,
+        routes::request_device_link,
+        routes::redeem_device_link,
+        routes::websocket,
+        routes::create_room,
+        routes::list_rooms,
+        routes::get_room,
+        routes::rename_room,
+        routes::set_room_members,
+        routes::archive_room,
+        routes::delete_room,
+    ),
+    components(schemas(
+        ResponseData<ErrorData>,
+        CredentialRecord,
+        IssuanceReceipt,
+        SessionToken,
+        Claimed,
+        KeyPackageCount,
+        SignatureScheme,
+        CredentialType,
+        Room,
+    )),
+    modifiers(&BearerToken),
+    tags(
+        (name = "meta", description = "The AS itself"),
+        (name = "identities", description = "The credentials of the devices"),
+        (name = "key_packages", description = "The key package directory"),
+        (name = "sessions", description = "Logins and the tokens of the users and devices"),
+        (name = "delivery", description = "The delivery service"),
+        (name = "rooms", description = "The rooms, for the admins"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Access tokens, sent as bearer tokens.
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+/// The signature scheme of a credential, from `chat_core`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub enum SignatureScheme {
+    Ed25519,
+    EcdsaSecp256r1,
+}
+
+/// The type of a credential, from `chat_core`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub enum CredentialType {
+    Basic,
+    X509,
+}
+
+/// A room of the delivery service, from `openmls_group::rooms`.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct Room {
+    // Also the MLS group id of the room's group
+    id: String,
+    name: String,
+    // Users, for all their devices, or single devices
+    allowed: Vec<String>,
+    // Takes no new members
+    archived: bool,
+}
+
+/// The OpenAPI document of this API.
+#[utoipa::path(
+    tag = "meta",
+    responses((status = 200, description = "The OpenAPI document", body = Object))
+)]
+#[get("/openapi.json")]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
@@ -1,15 +1,28 @@
-use actix_web::{get, post, HttpResponse};
+use actix_web::{get, HttpResponse};
 
+mod codes;
+mod identities;
+mod key_packages;
+mod metrics;
+mod rooms;
+mod sessions;
+mod websocket;
+
+pub use codes::*;
+pub use identities::*;
+pub use key_packages::*;
+pub use metrics::*;
+pub use rooms::*;
+pub use sessions::*;
+pub use websocket::*;
+
+#[utoipa::path(tag = "meta", responses((status = 200, description = "The AS is up")))]
 #[get("/health_check")]
 pub async fn health_check() -> Result<HttpResponse, actix_web::Error> {
     Ok(HttpResponse::Ok().finish())
 }
 
-#[post("/identities")]
-pub async fn post_identities() -> Result<HttpResponse, actix_web::Error> {
-    Ok(HttpResponse::Ok().finish())
-}
-
+#[utoipa::path(tag = "meta", responses((status = 200, description = "The AS is up")))]
 #[get("/")]
 pub async fn index() -> Result<HttpResponse, actix_web::Error> {
     Ok(HttpResponse::Ok().finish())
@@ -0,0 +1,178 @@
+use actix_web::{middleware::from_fn, post, web, HttpResponse};
+
+use crate::{
+    api_error::{ApiError, ErrorData},
+    authentication::{
+        reject_anonymous_users, Authenticated, OneTimeCodes, SessionToken, TokenIssuer, UserStore,
+        DEVICE_LINK_VALIDITY, OTP_VALIDITY,
+    },
+    email_client::{Email, EmailClient},
+    utils::ResponseData,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RequestLoginOtp {
+    username: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct LoginWithOtp {
+    username: String,
+    otp: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RedeemDeviceLink {
+    code: String,
+}
+
+/// Emails a login OTP to a user. The response is the same whether the user
+/// exists and has an email or not.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = RequestLoginOtp,
+    responses(
+        (status = 200, description = "The username, a code was sent if it has an email", body = ResponseData<String>)
+    )
+)]
+#[post("/login/otp")]
+pub async fn request_login_otp(
+    body: web::Json<RequestLoginOtp>,
+    users: web::Data<UserStore>,
+    codes: web::Data<OneTimeCodes>,
+    email_client: web::Data<EmailClient>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(recipient) = users
+        .email(&body.username)
+        .await
+        .map_err(ApiError::unexpected)?
+    {
+        let otp = codes.login_otp(&body.username);
+        email_client
+            .send_email(Email {
+                recipient,
+                subject: "Your login code".to_string(),
+                html_content: format!(
+                    "Your login code is <b>{}</b>.<br />It is valid for {} minutes.",
+                    otp,
+                    OTP_VALIDITY.as_secs() / 60
+                ),
+                text_content: format!(
+                    "Your login code is {}.\nIt is valid for {} minutes.",
+                    otp,
+                    OTP_VALIDITY.as_secs() / 60
+                ),
+            })
+            .await
+            .map_err(ApiError::unexpected)?;
+    }
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: body.into_inner().username,
+        message: "If the user has an email, a login code was sent to it.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Logs a user in with the OTP it was emailed, once.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = LoginWithOtp,
+    responses(
+        (status = 200, description = "The tokens of the session", body = ResponseData<SessionToken>),
+        (status = 401, description = "Invalid username or login code", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/login/otp/verify")]
+pub async fn login_with_otp(
+    body: web::Json<LoginWithOtp>,
+    codes: web::Data<OneTimeCodes>,
+    tokens: web::Data<TokenIssuer>,
+) -> Result<HttpResponse, ApiError> {
+    if !codes.check_login_otp(&body.username, body.otp.trim()) {
+        return Err(ApiError::unauthorized("Invalid username or login code."));
+    }
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: tokens.open_session(&body.username),
+        message: "Logged in.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Emails the user of the request a code that logs another device in as the
+/// user.
+#[utoipa::path(
+    tag = "sessions",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user, its code was emailed", body = ResponseData<String>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 409, description = "The user has no email", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/devices/link", wrap = "from_fn(reject_anonymous_users)")]
+pub async fn request_device_link(
+    authenticated: Authenticated,
+    users: web::Data<UserStore>,
+    codes: web::Data<OneTimeCodes>,
+    email_client: web::Data<EmailClient>,
+) -> Result<HttpResponse, ApiError> {
+    let user = authenticated.user();
+    let recipient = users
+        .email(&user)
+        .await
+        .map_err(ApiError::unexpected)?
+        .ok_or_else(|| ApiError::conflict(format!("{} has no email to send the code to.", user)))?;
+    let code = codes.device_link(&user);
+    email_client
+        .send_email(Email {
+            recipient,
+            subject: "Link a new device".to_string(),
+            html_content: format!(
+                "Enter <b>{}</b> on your new device to log it in.<br />\
+                 It is valid for {} minutes.",
+                code,
+                DEVICE_LINK_VALIDITY.as_secs() / 60
+            ),
+            text_content: format!(
+                "Enter {} on your new device to log it in.\nIt is valid for {} minutes.",
+                code,
+                DEVICE_LINK_VALIDITY.as_secs() / 60
+            ),
+        })
+        .await
+        .map_err(ApiError::unexpected)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: user,
+        message: "A device-link code was sent to your email.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Logs a new device in as the user a device-link code was emailed to, once.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = RedeemDeviceLink,
+    responses(
+        (status = 200, description = "The tokens of the session", body = ResponseData<SessionToken>),
+        (status = 401, description = "Wrong, expired or used code", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/devices/link/redeem")]
+pub async fn redeem_device_link(
+    body: web::Json<RedeemDeviceLink>,
+    codes: web::Data<OneTimeCodes>,
+    tokens: web::Data<TokenIssuer>,
+) -> Result<HttpResponse, ApiError> {
+    let user = codes.redeem_device_link(&body.code).ok_or_else(|| {
+        ApiError::unauthorized("The device-link code is wrong, expired or used up.")
+    })?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: tokens.open_session(&user),
+        message: "Device linked.".to_string(),
+        code: 200,
+    }))
+}
@@ -0,0 +1,304 @@
+use actix_web::{get, middleware::from_fn, post, web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::{
+    auth_service::{Credential, CredentialType, SignatureScheme, CREDENTIAL_VALIDITY},
+    ext_mls::{user_of, DEVICE_SEPARATOR},
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    api_error::{ApiError, ErrorData},
+    auth_service::{
+        registration_message, signed_by, CertificateAuthority, CredentialRecord, CredentialStore,
+        IssuanceReceipt, Issuer, StoreError,
+    },
+    authentication::{reject_anonymous_users, Admins, Authenticated},
+    utils::ResponseData,
+};
+
+const MAX_IDENTITY_LENGTH: usize = 256;
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RegisterIdentity {
+    identity: String,
+    // Base64 encoded
+    signature_key: String,
+    #[schema(value_type = crate::openapi::SignatureScheme)]
+    signature_scheme: SignatureScheme,
+    #[serde(default = "basic")]
+    #[schema(value_type = crate::openapi::CredentialType)]
+    credential_type: CredentialType,
+    // Base64 encoded signature of `registration_message`, made with the
+    // submitted signature key
+    proof: String,
+}
+
+fn basic() -> CredentialType {
+    CredentialType::Basic
+}
+
+impl TryFrom<RegisterIdentity> for Credential {
+    type Error = String;
+
+    fn try_from(value: RegisterIdentity) -> Result<Self, Self::Error> {
+        let identity = parse_identity(value.identity)?;
+        let public_key = parse_signature_key(&value.signature_key, value.signature_scheme)?;
+
+        Ok(Credential::basic(
+            identity,
+            public_key,
+            value.signature_scheme,
+            CREDENTIAL_VALIDITY,
+        ))
+    }
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RotateIdentity {
+    // Base64 encoded
+    signature_key: String,
+    #[schema(value_type = crate::openapi::SignatureScheme)]
+    signature_scheme: SignatureScheme,
+    // Base64 encoded signature of the new signature key, made with the
+    // registered one
+    proof: String,
+}
+
+fn parse_signature_key(
+    signature_key: &str,
+    signature_scheme: SignatureScheme,
+) -> Result<Vec<u8>, String> {
+    let public_key = STANDARD
+        .decode(signature_key)
+        .map_err(|_| "The signature key is not valid base64.".to_string())?;
+    if public_key.len() != signature_scheme.public_key_len() {
+        return Err(format!(
+            "A {:?} signature key is {} bytes long.",
+            signature_scheme,
+            signature_scheme.public_key_len()
+        ));
+    }
+
+    Ok(public_key)
+}
+
+fn parse_identity(identity: String) -> Result<String, String> {
+    let is_empty_or_whitespace = identity.trim().is_empty();
+    let is_too_long = identity.graphemes(true).count() > MAX_IDENTITY_LENGTH;
+    let has_control_characters = identity.chars().any(char::is_control);
+
+    let is_not_of_a_device = match identity.rsplit_once(DEVICE_SEPARATOR) {
+        Some((user, device)) => user.is_empty() || device.is_empty(),
+        None => true,
+    };
+
+    if is_empty_or_whitespace || is_too_long || has_control_characters || is_not_of_a_device {
+        return Err(format!("{} is not a valid identity.", identity));
+    }
+
+    Ok(identity)
+}
+
+/// Registers the signature key of a device of the signed in user, the
+/// request is signed with that key. The returned receipt is signed by the AS.
+/// For an X.509 credential the local CA certifies the key.
+#[utoipa::path(
+    tag = "identities",
+    security(("bearer" = [])),
+    request_body = RegisterIdentity,
+    responses(
+        (status = 200, description = "The receipt of the registration", body = ResponseData<IssuanceReceipt>),
+        (status = 400, description = "Invalid identity, key or proof", body = ResponseData<ErrorData>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "A device of another user", body = ResponseData<ErrorData>),
+        (status = 409, description = "Already registered", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/identities", wrap = "from_fn(reject_anonymous_users)")]
+pub async fn post_identities(
+    body: web::Json<RegisterIdentity>,
+    authenticated: Authenticated,
+    store: web::Data<CredentialStore>,
+    issuer: web::Data<Issuer>,
+    ca: web::Data<CertificateAuthority>,
+) -> Result<HttpResponse, ApiError> {
+    let credential_type = body.credential_type;
+    let proof = STANDARD
+        .decode(&body.proof)
+        .map_err(|_| ApiError::invalid_request("The proof is not valid base64."))?;
+    let mut credential =
+        Credential::try_from(body.into_inner()).map_err(ApiError::invalid_request)?;
+    if user_of(credential.identity.as_bytes()) != authenticated.user().as_bytes() {
+        return Err(ApiError::forbidden(format!(
+            "{} can not register {}.",
+            authenticated.0, credential.identity
+        )));
+    }
+    if !signed_by(
+        &credential,
+        &registration_message(&credential.identity),
+        &proof,
+    ) {
+        return Err(ApiError::invalid_request(
+            "The proof is not signed with the submitted key.",
+        ));
+    }
+    if credential_type == CredentialType::X509 {
+        credential = ca.certify(&credential).map_err(ApiError::unexpected)?;
+    }
+    let receipt = issuer.issue(&credential);
+    store.insert(credential).await.map_err(store_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: receipt,
+        message: "Identity registered.".to_string(),
+        code: 200,
+    }))
+}
+
+/// The credential registered for an identity, to check that a leaf carries
+/// the registered signature key.
+#[utoipa::path(
+    tag = "identities",
+    params(("identity" = String, Path, description = "The identity of a device")),
+    responses(
+        (status = 200, description = "The registered credential", body = ResponseData<CredentialRecord>),
+        (status = 404, description = "Not registered", body = ResponseData<ErrorData>)
+    )
+)]
+#[get("/identities/{identity}")]
+pub async fn get_identity(
+    identity: web::Path<String>,
+    store: web::Data<CredentialStore>,
+) -> Result<HttpResponse, ApiError> {
+    let credential = store
+        .get(&identity)
+        .ok_or_else(|| ApiError::not_found(format!("{} is not registered.", identity)))?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: CredentialRecord::from(&credential),
+        message: "Identity found.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Rotates the credential of an identity to a new signature key, before or
+/// after it expires. The request is signed with the registered key, and the
+/// new credential is of the same type, valid for a full period.
+#[utoipa::path(
+    tag = "identities",
+    params(("identity" = String, Path, description = "The identity of a device")),
+    request_body = RotateIdentity,
+    responses(
+        (status = 200, description = "The receipt of the new credential", body = ResponseData<IssuanceReceipt>),
+        (status = 400, description = "Invalid key or proof", body = ResponseData<ErrorData>),
+        (status = 403, description = "Revoked", body = ResponseData<ErrorData>),
+        (status = 404, description = "Not registered", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/identities/{identity}/rotate")]
+pub async fn rotate_identity(
+    identity: web::Path<String>,
+    body: web::Json<RotateIdentity>,
+    store: web::Data<CredentialStore>,
+    issuer: web::Data<Issuer>,
+    ca: web::Data<CertificateAuthority>,
+) -> Result<HttpResponse, ApiError> {
+    let previous = store
+        .get(&identity)
+        .ok_or_else(|| ApiError::not_found(format!("{} is not registered.", identity)))?;
+    let public_key = parse_signature_key(&body.signature_key, body.signature_scheme)
+        .map_err(ApiError::invalid_request)?;
+    let proof = STANDARD
+        .decode(&body.proof)
+        .map_err(|_| ApiError::invalid_request("The proof is not valid base64."))?;
+    if !signed_by(&previous, &public_key, &proof) {
+        return Err(ApiError::invalid_request(
+            "The proof is not signed with the registered key.",
+        ));
+    }
+
+    let mut credential = Credential::basic(
+        identity.into_inner(),
+        public_key,
+        body.signature_scheme,
+        CREDENTIAL_VALIDITY,
+    );
+    if previous.credential_type == CredentialType::X509 {
+        credential = ca.certify(&credential).map_err(ApiError::unexpected)?;
+    }
+    let receipt = issuer.issue(&credential);
+    store.rotate(credential).await.map_err(store_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: receipt,
+        message: "Identity rotated.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Revokes the credential of an identity. The delivery service is told, and
+/// has the leaves carrying it removed from their groups. Only its user, or an
+/// admin, can revoke it.
+#[utoipa::path(
+    tag = "identities",
+    security(("bearer" = [])),
+    params(("identity" = String, Path, description = "The identity of a device")),
+    responses(
+        (status = 200, description = "The revoked credential", body = ResponseData<CredentialRecord>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Neither its user nor an admin", body = ResponseData<ErrorData>),
+        (status = 404, description = "Not registered", body = ResponseData<ErrorData>)
+    )
+)]
+#[post(
+    "/identities/{identity}/revoke",
+    wrap = "from_fn(reject_anonymous_users)"
+)]
+pub async fn revoke_identity(
+    identity: web::Path<String>,
+    authenticated: Authenticated,
+    admins: web::Data<Admins>,
+    store: web::Data<CredentialStore>,
+) -> Result<HttpResponse, ApiError> {
+    let user = authenticated.user();
+    if user_of(identity.as_bytes()) != user.as_bytes() && !admins.0.contains(&user) {
+        return Err(ApiError::forbidden(format!(
+            "{} can not revoke {}.",
+            authenticated.0, identity
+        )));
+    }
+    let credential = store.revoke(&identity).await.map_err(store_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: CredentialRecord::from(&credential),
+        message: "Identity revoked.".to_string(),
+        code: 200,
+    }))
+}
+
+/// The certificate of the local CA, base64 encoded DER: the trust anchor of
+/// the X.509 credentials.
+#[utoipa::path(
+    tag = "identities",
+    responses(
+        (status = 200, description = "The base64 DER certificate of the local CA", body = ResponseData<String>)
+    )
+)]
+#[get("/ca/certificate")]
+pub async fn get_ca_certificate(ca: web::Data<CertificateAuthority>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseData {
+        data: STANDARD.encode(ca.trust_anchor().certificate()),
+        message: "CA certificate.".to_string(),
+        code: 200,
+    })
+}
+
+fn store_error(err: StoreError) -> ApiError {
+    match err {
+        StoreError::AlreadyRegistered(_) => ApiError::conflict(err),
+        StoreError::NotRegistered(_) => ApiError::not_found(err),
+        StoreError::Revoked(_) => ApiError::forbidden(err),
+        StoreError::Storage(_) => ApiError::unexpected(err),
+    }
+}
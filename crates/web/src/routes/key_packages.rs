@@ -0,0 +1,153 @@
+use actix_web::{get, middleware::from_fn, post, web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::ext_mls::{ciphersuite_by_name, user_of, CIPHERSUITE};
+use openmls::prelude::Ciphersuite;
+use openmls_group::key_packages::{DirectoryError, KeyPackageDirectory};
+
+use crate::{
+    api_error::{ApiError, ErrorData},
+    authentication::{reject_anonymous_users, Authenticated},
+    delivery_service::{Claimed, KeyPackageCount},
+    utils::ResponseData,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct PublishKeyPackages {
+    identity: String,
+    // Base64 encoded, TLS serialized
+    key_packages: Vec<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct ClaimKeyPackages {
+    identities: Vec<String>,
+    // x25519 or p256, x25519 if not given
+    ciphersuite: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::IntoParams)]
+pub struct CountQuery {
+    identity: String,
+    ciphersuite: Option<String>,
+}
+
+fn parse_ciphersuite(name: Option<&str>) -> Result<Ciphersuite, ApiError> {
+    match name {
+        None => Ok(CIPHERSUITE),
+        Some(name) => ciphersuite_by_name(name).ok_or_else(|| {
+            ApiError::invalid_request(format!(
+                "Unknown ciphersuite `{}`, pick x25519 or p256.",
+                name
+            ))
+        }),
+    }
+}
+
+/// Publishes a batch of key packages of a registered device of the signed in
+/// user, signed with its registered signature key. Nothing is stored if one
+/// is invalid.
+#[utoipa::path(
+    tag = "key_packages",
+    security(("bearer" = [])),
+    request_body = PublishKeyPackages,
+    responses(
+        (status = 200, description = "How many key packages were stored", body = ResponseData<usize>),
+        (status = 400, description = "Invalid key package", body = ResponseData<ErrorData>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "A device of another user, or not signed by the registered key", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/key_packages", wrap = "from_fn(reject_anonymous_users)")]
+pub async fn post_key_packages(
+    body: web::Json<PublishKeyPackages>,
+    authenticated: Authenticated,
+    directory: web::Data<KeyPackageDirectory>,
+) -> Result<HttpResponse, ApiError> {
+    if user_of(body.identity.as_bytes()) != authenticated.user().as_bytes() {
+        return Err(ApiError::forbidden(format!(
+            "{} can not publish for {}.",
+            authenticated.0, body.identity
+        )));
+    }
+    let key_packages = body
+        .key_packages
+        .iter()
+        .map(|key_package| STANDARD.decode(key_package))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ApiError::invalid_request("A key package is not valid base64."))?;
+    let stored = directory
+        .publish(&body.identity, &key_packages)
+        .map_err(|err| match err {
+            DirectoryError::InvalidKeyPackage(_) => ApiError::invalid_request(err),
+            DirectoryError::IdentityMismatch(..) | DirectoryError::Credential(_) => {
+                ApiError::forbidden(err)
+            }
+        })?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: stored,
+        message: "Key packages published.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Claims a key package of each requested identity, of each device for a
+/// user. Identities without key package are left out. Only signed in users
+/// claim, the pools are not drained by anyone who can reach the service.
+#[utoipa::path(
+    tag = "key_packages",
+    security(("bearer" = [])),
+    request_body = ClaimKeyPackages,
+    responses(
+        (status = 200, description = "A key package of each identity that has one", body = ResponseData<Vec<Claimed>>),
+        (status = 400, description = "Unknown ciphersuite", body = ResponseData<ErrorData>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/key_packages/claim", wrap = "from_fn(reject_anonymous_users)")]
+pub async fn claim_key_packages(
+    body: web::Json<ClaimKeyPackages>,
+    directory: web::Data<KeyPackageDirectory>,
+) -> Result<HttpResponse, ApiError> {
+    let ciphersuite = parse_ciphersuite(body.ciphersuite.as_deref())?;
+    let claimed: Vec<Claimed> = directory
+        .claim(&body.identities, ciphersuite)
+        .into_iter()
+        .map(Claimed::from)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: claimed,
+        message: "Key packages claimed.".to_string(),
+        code: 200,
+    }))
+}
+
+/// How many key packages an identity has left, so it publishes more before
+/// only its last resort one is handed out.
+#[utoipa::path(
+    tag = "key_packages",
+    params(CountQuery),
+    responses(
+        (status = 200, description = "The key packages left", body = ResponseData<KeyPackageCount>),
+        (status = 400, description = "Unknown ciphersuite", body = ResponseData<ErrorData>)
+    )
+)]
+#[get("/key_packages/count")]
+pub async fn count_key_packages(
+    query: web::Query<CountQuery>,
+    directory: web::Data<KeyPackageDirectory>,
+) -> Result<HttpResponse, ApiError> {
+    let ciphersuite = parse_ciphersuite(query.ciphersuite.as_deref())?;
+    let (count, last_resort) = directory.count(&query.identity, ciphersuite);
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: KeyPackageCount {
+            identity: query.identity.clone(),
+            count,
+            last_resort,
+        },
+        message: "Key packages counted.".to_string(),
+        code: 200,
+    }))
+}
@@ -0,0 +1,23 @@
+use actix_web::{get, web, HttpResponse};
+use openmls_group::{key_packages::KeyPackageDirectory, metrics::Metrics};
+
+/// The metrics of the AS and its DS, in the Prometheus text format.
+#[utoipa::path(
+    tag = "meta",
+    responses((status = 200, description = "The metrics", body = String, content_type = "text/plain"))
+)]
+#[get("/metrics")]
+pub async fn get_metrics(
+    metrics: web::Data<Metrics>,
+    directory: web::Data<KeyPackageDirectory>,
+) -> HttpResponse {
+    // Also published and claimed over HTTP, counted when scraped
+    metrics
+        .key_packages
+        .with_label_values(&["directory"])
+        .set(directory.total() as i64);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics.render())
+}
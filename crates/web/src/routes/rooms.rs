@@ -0,0 +1,222 @@
+use actix_web::{delete, get, middleware::from_fn, patch, post, put, web, HttpResponse};
+use openmls_group::rooms::{RoomCatalog, RoomError};
+
+use crate::{
+    api_error::{ApiError, ErrorData},
+    authentication::reject_non_admin_users,
+    utils::ResponseData,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct CreateRoom {
+    name: String,
+    // Users or devices, none if not given
+    #[serde(default)]
+    allowed: Vec<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RenameRoom {
+    name: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RoomMembers {
+    allowed: Vec<String>,
+}
+
+/// Creates a room, the delivery service lets only the allowed identities in
+/// its group.
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    request_body = CreateRoom,
+    responses(
+        (status = 200, description = "The created room", body = ResponseData<crate::openapi::Room>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>),
+        (status = 400, description = "Invalid name", body = ResponseData<ErrorData>),
+        (status = 409, description = "Name taken", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/admin/rooms", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn create_room(
+    body: web::Json<CreateRoom>,
+    rooms: web::Data<RoomCatalog>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let room = rooms.create(&body.name, body.allowed).map_err(room_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: room,
+        message: "Room created.".to_string(),
+        code: 200,
+    }))
+}
+
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The rooms, by name", body = ResponseData<Vec<crate::openapi::Room>>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>)
+    )
+)]
+#[get("/admin/rooms", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn list_rooms(rooms: web::Data<RoomCatalog>) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseData {
+        data: rooms.list(),
+        message: "Rooms listed.".to_string(),
+        code: 200,
+    })
+}
+
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "The id of the room")),
+    responses(
+        (status = 200, description = "The room", body = ResponseData<crate::openapi::Room>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>),
+        (status = 404, description = "No such room", body = ResponseData<ErrorData>)
+    )
+)]
+#[get("/admin/rooms/{id}", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn get_room(
+    id: web::Path<String>,
+    rooms: web::Data<RoomCatalog>,
+) -> Result<HttpResponse, ApiError> {
+    let room = rooms
+        .get(&id)
+        .ok_or_else(|| room_error(RoomError::NotFound(id.into_inner())))?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: room,
+        message: "Room found.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Renames a room, its group is not affected.
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "The id of the room")),
+    request_body = RenameRoom,
+    responses(
+        (status = 200, description = "The renamed room", body = ResponseData<crate::openapi::Room>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>),
+        (status = 400, description = "Invalid name", body = ResponseData<ErrorData>),
+        (status = 404, description = "No such room", body = ResponseData<ErrorData>),
+        (status = 409, description = "Name taken", body = ResponseData<ErrorData>)
+    )
+)]
+#[patch("/admin/rooms/{id}", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn rename_room(
+    id: web::Path<String>,
+    body: web::Json<RenameRoom>,
+    rooms: web::Data<RoomCatalog>,
+) -> Result<HttpResponse, ApiError> {
+    let room = rooms.rename(&id, &body.name).map_err(room_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: room,
+        message: "Room renamed.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Replaces the identities allowed in a room. The members no longer allowed
+/// stay in its group until removed.
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "The id of the room")),
+    request_body = RoomMembers,
+    responses(
+        (status = 200, description = "The room", body = ResponseData<crate::openapi::Room>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>),
+        (status = 404, description = "No such room", body = ResponseData<ErrorData>)
+    )
+)]
+#[put("/admin/rooms/{id}/members", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn set_room_members(
+    id: web::Path<String>,
+    body: web::Json<RoomMembers>,
+    rooms: web::Data<RoomCatalog>,
+) -> Result<HttpResponse, ApiError> {
+    let room = rooms
+        .set_allowed(&id, body.into_inner().allowed)
+        .map_err(room_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: room,
+        message: "Room members set.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Archives a room, its group keeps its members and takes no new ones.
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "The id of the room")),
+    responses(
+        (status = 200, description = "The archived room", body = ResponseData<crate::openapi::Room>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>),
+        (status = 404, description = "No such room", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/admin/rooms/{id}/archive", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn archive_room(
+    id: web::Path<String>,
+    rooms: web::Data<RoomCatalog>,
+) -> Result<HttpResponse, ApiError> {
+    let room = rooms.archive(&id).map_err(room_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: room,
+        message: "Room archived.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Deletes a room, its group takes no new members.
+#[utoipa::path(
+    tag = "rooms",
+    security(("bearer" = [])),
+    params(("id" = String, Path, description = "The id of the room")),
+    responses(
+        (status = 200, description = "The deleted room", body = ResponseData<crate::openapi::Room>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Not an admin", body = ResponseData<ErrorData>),
+        (status = 404, description = "No such room", body = ResponseData<ErrorData>)
+    )
+)]
+#[delete("/admin/rooms/{id}", wrap = "from_fn(reject_non_admin_users)")]
+pub async fn delete_room(
+    id: web::Path<String>,
+    rooms: web::Data<RoomCatalog>,
+) -> Result<HttpResponse, ApiError> {
+    let room = rooms.delete(&id).map_err(room_error)?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: room,
+        message: "Room deleted.".to_string(),
+        code: 200,
+    }))
+}
+
+fn room_error(err: RoomError) -> ApiError {
+    match err {
+        RoomError::InvalidName(_) => ApiError::invalid_request(err),
+        RoomError::NameTaken(_) | RoomError::HasGroup(_) => ApiError::conflict(err),
+        RoomError::NotFound(_) => ApiError::not_found(err),
+        RoomError::Archived(_) | RoomError::NotAllowed(_) => ApiError::forbidden(err),
+    }
+}
@@ -0,0 +1,256 @@
+use std::time::SystemTime;
+
+use actix_web::{get, middleware::from_fn, post, web, HttpResponse};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::ext_mls::DEVICE_SEPARATOR;
+use secrecy::{ExposeSecret, Secret};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    api_error::{ApiError, ErrorData},
+    auth_service::{session_message, signed_by, unix_seconds, CredentialStore, MAX_CLOCK_SKEW},
+    authentication::{
+        reject_anonymous_users, Authenticated, SessionToken, TokenIssuer, UserError, UserStore,
+    },
+    email_client::parse_email,
+    utils::ResponseData,
+};
+
+const MAX_USERNAME_LENGTH: usize = 256;
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct OpenSession {
+    identity: String,
+    // Seconds since the Unix epoch
+    signed_at: u64,
+    // Base64 encoded signature of `session_message`, made with the
+    // registered key
+    proof: String,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct UserPassword {
+    username: String,
+    #[schema(value_type = String, format = Password)]
+    password: Secret<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct NewUser {
+    username: String,
+    #[schema(value_type = String, format = Password)]
+    password: Secret<String>,
+    // Where its one-time codes are sent, none if not given
+    #[serde(default)]
+    email: Option<String>,
+}
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct RefreshSession {
+    refresh_token: String,
+}
+
+fn parse_username(username: &str) -> Result<&str, String> {
+    let is_empty_or_whitespace = username.trim().is_empty();
+    let is_too_long = username.graphemes(true).count() > MAX_USERNAME_LENGTH;
+    let has_forbidden_characters = username
+        .chars()
+        .any(|c| c.is_control() || c.is_whitespace() || c == DEVICE_SEPARATOR);
+
+    if is_empty_or_whitespace || is_too_long || has_forbidden_characters {
+        return Err(format!("{} is not a valid username.", username));
+    }
+
+    Ok(username)
+}
+
+fn parse_password(password: &Secret<String>) -> Result<(), String> {
+    let length = password.expose_secret().graphemes(true).count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(format!(
+            "A password is {} to {} characters long.",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+
+    Ok(())
+}
+
+/// Registers a user with a password, and an email to log in with one-time
+/// codes. Its devices log in as the user.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = NewUser,
+    responses(
+        (status = 200, description = "The registered username", body = ResponseData<String>),
+        (status = 400, description = "Invalid username, password or email", body = ResponseData<ErrorData>),
+        (status = 409, description = "Already registered", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/users")]
+pub async fn register_user(
+    body: web::Json<NewUser>,
+    users: web::Data<UserStore>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let username = parse_username(&body.username).map_err(ApiError::invalid_request)?;
+    parse_password(&body.password).map_err(ApiError::invalid_request)?;
+    let email = body
+        .email
+        .as_deref()
+        .map(parse_email)
+        .transpose()
+        .map_err(ApiError::invalid_request)?;
+    users
+        .register(username, body.password, email.as_deref())
+        .await
+        .map_err(|err| match err {
+            UserError::AlreadyRegistered(_) => ApiError::conflict(err),
+            err => ApiError::unexpected(err),
+        })?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: body.username,
+        message: "User registered.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Logs a user in with its password.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = UserPassword,
+    responses(
+        (status = 200, description = "The tokens of the session", body = ResponseData<SessionToken>),
+        (status = 401, description = "Invalid username or password", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/login")]
+pub async fn login(
+    body: web::Json<UserPassword>,
+    users: web::Data<UserStore>,
+    tokens: web::Data<TokenIssuer>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    users
+        .verify(&body.username, body.password)
+        .await
+        .map_err(|err| match err {
+            UserError::InvalidCredentials => ApiError::unauthorized(err),
+            err => ApiError::unexpected(err),
+        })?;
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: tokens.open_session(&body.username),
+        message: "Logged in.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Opens a session of a registered identity, the request is signed with its
+/// registered key. The token authenticates the identity to the delivery
+/// service.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = OpenSession,
+    responses(
+        (status = 200, description = "The tokens of the session", body = ResponseData<SessionToken>),
+        (status = 400, description = "Invalid proof", body = ResponseData<ErrorData>),
+        (status = 401, description = "Not registered, or not signed with the registered key", body = ResponseData<ErrorData>),
+        (status = 403, description = "Revoked", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/sessions")]
+pub async fn open_session(
+    body: web::Json<OpenSession>,
+    store: web::Data<CredentialStore>,
+    tokens: web::Data<TokenIssuer>,
+) -> Result<HttpResponse, ApiError> {
+    let credential = store
+        .get(&body.identity)
+        .ok_or_else(|| ApiError::unauthorized(format!("{} is not registered.", body.identity)))?;
+    if credential.revoked {
+        return Err(ApiError::forbidden(format!(
+            "The credential of {} is revoked.",
+            body.identity
+        )));
+    }
+    let now = unix_seconds(SystemTime::now());
+    if now.abs_diff(body.signed_at) > MAX_CLOCK_SKEW.as_secs() {
+        return Err(ApiError::unauthorized(
+            "The request was not signed just now.",
+        ));
+    }
+    let proof = STANDARD
+        .decode(&body.proof)
+        .map_err(|_| ApiError::invalid_request("The proof is not valid base64."))?;
+    let message = session_message(&body.identity, body.signed_at);
+    if !signed_by(&credential, &message, &proof) {
+        return Err(ApiError::unauthorized(
+            "The proof is not signed with the registered key.",
+        ));
+    }
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: tokens.open_session(&body.identity),
+        message: "Session opened.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Trades a refresh token for new tokens, once. A revoked identity can't
+/// refresh its session.
+#[utoipa::path(
+    tag = "sessions",
+    request_body = RefreshSession,
+    responses(
+        (status = 200, description = "The new tokens", body = ResponseData<SessionToken>),
+        (status = 401, description = "Unknown, used or expired refresh token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Revoked", body = ResponseData<ErrorData>)
+    )
+)]
+#[post("/sessions/refresh")]
+pub async fn refresh_session(
+    body: web::Json<RefreshSession>,
+    store: web::Data<CredentialStore>,
+    tokens: web::Data<TokenIssuer>,
+) -> Result<HttpResponse, ApiError> {
+    let subject = tokens
+        .redeem(&body.refresh_token)
+        .map_err(ApiError::unauthorized)?;
+    if store
+        .get(&subject)
+        .is_some_and(|credential| credential.revoked)
+    {
+        return Err(ApiError::forbidden(format!(
+            "The credential of {} is revoked.",
+            subject
+        )));
+    }
+
+    Ok(HttpResponse::Ok().json(ResponseData {
+        data: tokens.open_session(&subject),
+        message: "Session refreshed.".to_string(),
+        code: 200,
+    }))
+}
+
+/// Who the access token of the request was issued to.
+#[utoipa::path(
+    tag = "sessions",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user or device of the token", body = ResponseData<String>),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>)
+    )
+)]
+#[get("/me", wrap = "from_fn(reject_anonymous_users)")]
+pub async fn me(authenticated: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().json(ResponseData {
+        data: authenticated.0,
+        message: "Authenticated.".to_string(),
+        code: 200,
+    })
+}
@@ -0,0 +1,71 @@
+use actix_web::{get, middleware::from_fn, rt, web, HttpRequest, HttpResponse};
+use actix_ws::AggregatedMessage;
+use tokio::select;
+
+use crate::{
+    api_error::{ApiError, ErrorData},
+    authentication::{reject_anonymous_users, Authenticated},
+    delivery_service::Delivery,
+    utils::ResponseData,
+};
+
+/// Connects a client to the delivery service over a WebSocket, as a device of
+/// the user the access token was issued to. Each text message is a line, a
+/// command or a chat message as telnet clients type them, and each line of
+/// the delivery service comes back as a text message.
+#[utoipa::path(
+    tag = "delivery",
+    security(("bearer" = [])),
+    params(("token" = Option<String>, Query, description = "The access token, for browsers")),
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol"),
+        (status = 401, description = "No valid access token", body = ResponseData<ErrorData>),
+        (status = 403, description = "Revoked", body = ResponseData<ErrorData>)
+    )
+)]
+#[get("/ws", wrap = "from_fn(reject_anonymous_users)")]
+pub async fn websocket(
+    req: HttpRequest,
+    body: web::Payload,
+    authenticated: Authenticated,
+    delivery: web::Data<Delivery>,
+) -> Result<HttpResponse, ApiError> {
+    let (response, mut ws, stream) =
+        actix_ws::handle(&req, body).map_err(ApiError::invalid_request)?;
+    let mut stream = stream.aggregate_continuations();
+    let ip = req.peer_addr().unwrap_or_else(|| ([0, 0, 0, 0], 0).into());
+    let (to_client, mut from_client) = delivery.connect(ip, &authenticated.user());
+
+    // The WebSocket halves are not `Send`, they stay on this worker.
+    rt::spawn(async move {
+        loop {
+            select! {
+                msg = stream.recv() => match msg {
+                    Some(Ok(AggregatedMessage::Text(line))) => {
+                        if to_client.send(line.to_string()).is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(AggregatedMessage::Ping(bytes))) => {
+                        if ws.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(AggregatedMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+                line = from_client.recv() => match line {
+                    Some(line) => {
+                        if ws.text(line).await.is_err() {
+                            return;
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+        let _ = ws.close(None).await;
+    });
+
+    Ok(response)
+}
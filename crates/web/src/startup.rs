@@ -1,17 +1,48 @@
 use actix_cors::Cors;
-use actix_web::{dev::Server, web::Data, App, HttpServer};
-use std::{io::Error, net::TcpListener};
+use actix_web::{
+    dev::Server,
+    middleware::from_fn,
+    web::{Data, JsonConfig, PathConfig, QueryConfig},
+    App, HttpServer,
+};
+use std::{io::Error, net::TcpListener, sync::Arc};
 use tracing_actix_web::TracingLogger;
 
 use crate::{
+    api_error::ApiError,
+    auth_service::{CertificateAuthority, CredentialStore, Issuer},
+    authentication::{Admins, Authenticator, OneTimeCodes, TokenIssuer, UserStore},
     configuration::Settings,
-    routes::{health_check, index, post_identities},
+    delivery_service::Delivery,
+    email_client::EmailClient,
+    metrics::{count_requests, HttpMetrics},
+    openapi::openapi_json,
+    routes::{
+        archive_room, claim_key_packages, count_key_packages, create_room, delete_room,
+        get_ca_certificate, get_identity, get_metrics, get_room, health_check, index, list_rooms,
+        login, login_with_otp, me, open_session, post_identities, post_key_packages,
+        redeem_device_link, refresh_session, register_user, rename_room, request_device_link,
+        request_login_otp, revoke_identity, rotate_identity, set_room_members, websocket,
+    },
+    storage::Storage,
 };
 
 pub struct ApplicationBaseUrl(pub String);
+
+// Shared by the HTTP workers and the telnet listener
+struct Stores {
+    ca: CertificateAuthority,
+    issuer: Issuer,
+    credential_store: Data<CredentialStore>,
+    users: Data<UserStore>,
+    tokens: Data<TokenIssuer>,
+    admins: Data<Admins>,
+    email_client: Data<EmailClient>,
+}
 pub struct Application {
     port: u16,
     server: Server,
+    email_client: Data<EmailClient>,
 }
 
 impl Application {
@@ -20,28 +51,96 @@ impl Application {
             "{}:{}",
             configuration.application.host, configuration.application.port
         );
-        let listener = TcpListener::bind(address).expect(&format!(
-            "Failed to bind port {}",
-            configuration.application.port
-        ));
+        let listener = TcpListener::bind(address)
+            .unwrap_or_else(|_| panic!("Failed to bind port {}", configuration.application.port));
         let port = listener.local_addr().unwrap().port();
 
-        let server = run(listener, configuration.application.base_url).await?;
+        let storage = Storage::connect(&configuration.database).await?;
+        let ca = CertificateAuthority::load(&storage).await?;
+        let issuer = Issuer::load(&storage).await?;
+        let stores = Stores {
+            credential_store: Data::new(
+                CredentialStore::load(storage.clone())
+                    .await?
+                    .with_trust_anchor(ca.trust_anchor()),
+            ),
+            users: Data::new(UserStore::new(storage)),
+            tokens: Data::new(TokenIssuer::new(configuration.application.hmac_secret)),
+            admins: Data::new(Admins(
+                configuration.application.admins.into_iter().collect(),
+            )),
+            email_client: Data::new(
+                EmailClient::new(&configuration.email_client).map_err(anyhow::Error::msg)?,
+            ),
+            ca,
+            issuer,
+        };
+        let email_client = stores.email_client.clone();
+
+        let delivery = Delivery::spawn(stores.credential_store.clone().into_inner());
+        if let Some(telnet_port) = configuration.application.telnet_port {
+            let verifier =
+                Authenticator::new(stores.tokens.clone(), stores.credential_store.clone());
+            delivery.accept_telnet(([0, 0, 0, 0], telnet_port).into(), Arc::new(verifier));
+        }
+        if let Some(console_port) = configuration.application.admin_console_port {
+            delivery.accept_admin_console(([127, 0, 0, 1], console_port).into());
+        }
 
-        Ok(Self { port, server })
+        let server = run(
+            listener,
+            configuration.application.base_url,
+            stores,
+            delivery,
+        )
+        .await?;
+
+        Ok(Self {
+            port,
+            server,
+            email_client,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// Where the app sends its emails, e.g. to read back the captured ones.
+    pub fn email_client(&self) -> Data<EmailClient> {
+        self.email_client.clone()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), Error> {
         self.server.await
     }
 }
 
-async fn run(listener: TcpListener, base_url: String) -> Result<Server, anyhow::Error> {
+async fn run(
+    listener: TcpListener,
+    base_url: String,
+    stores: Stores,
+    delivery: Delivery,
+) -> Result<Server, anyhow::Error> {
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let Stores {
+        ca,
+        issuer,
+        credential_store,
+        users,
+        tokens,
+        admins,
+        email_client,
+    } = stores;
+    let codes = Data::new(OneTimeCodes::default());
+    let authenticator = Data::new(Authenticator::new(tokens.clone(), credential_store.clone()));
+    let ca = Data::new(ca);
+    let issuer = Data::new(issuer);
+    let directory = Data::from(delivery.key_packages());
+    let rooms = Data::from(delivery.rooms());
+    let metrics = Data::from(delivery.metrics());
+    let http_metrics = Data::new(HttpMetrics::register(&metrics));
+    let delivery = Data::new(delivery);
     let server = HttpServer::new(move || {
         let cors = Cors::default()
             .allow_any_origin()
@@ -51,11 +150,63 @@ async fn run(listener: TcpListener, base_url: String) -> Result<Server, anyhow::
             // Logger middleware
             // Sent active-web log to log subscriber
             .wrap(TracingLogger::default())
+            .wrap(from_fn(count_requests))
             .wrap(cors)
             .service(index)
             .service(health_check)
+            .service(openapi_json)
+            .service(get_metrics)
             .service(post_identities)
+            .service(get_identity)
+            .service(rotate_identity)
+            .service(revoke_identity)
+            .service(get_ca_certificate)
+            .service(post_key_packages)
+            .service(claim_key_packages)
+            .service(count_key_packages)
+            .service(register_user)
+            .service(login)
+            .service(open_session)
+            .service(refresh_session)
+            .service(me)
+            .service(request_login_otp)
+            .service(login_with_otp)
+            .service(request_device_link)
+            .service(redeem_device_link)
+            .service(websocket)
+            .service(create_room)
+            .service(list_rooms)
+            .service(get_room)
+            .service(rename_room)
+            .service(set_room_members)
+            .service(archive_room)
+            .service(delete_room)
+            // Malformed requests are answered like the other errors
+            .app_data(
+                JsonConfig::default().error_handler(|err, _| ApiError::invalid_request(err).into()),
+            )
+            .app_data(
+                QueryConfig::default()
+                    .error_handler(|err, _| ApiError::invalid_request(err).into()),
+            )
+            .app_data(
+                PathConfig::default().error_handler(|err, _| ApiError::invalid_request(err).into()),
+            )
             .app_data(base_url.clone())
+            .app_data(credential_store.clone())
+            .app_data(issuer.clone())
+            .app_data(ca.clone())
+            .app_data(directory.clone())
+            .app_data(users.clone())
+            .app_data(tokens.clone())
+            .app_data(codes.clone())
+            .app_data(email_client.clone())
+            .app_data(authenticator.clone())
+            .app_data(admins.clone())
+            .app_data(rooms.clone())
+            .app_data(metrics.clone())
+            .app_data(http_metrics.clone())
+            .app_data(delivery.clone())
     })
     .listen(listener)?
     .run();
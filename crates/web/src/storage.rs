@@ -0,0 +1,178 @@
+// This module keeps the state of the AS in a database: a SQLite file locally
+// and in the tests, a Postgres compatible server in production. Both are used
+// through the same pool, the queries and the migrations stick to the SQL they
+// both understand.
+
+use std::time::{Duration, SystemTime};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::auth_service::Credential;
+use secrecy::ExposeSecret;
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions},
+    AnyPool,
+};
+
+use crate::{auth_service::unix_seconds, configuration::DatabaseSettings};
+
+/// A signing key of the AS, PKCS#8 encoded, with its DER certificate for the
+/// key of a CA.
+#[derive(Clone, Debug)]
+pub struct SigningKey {
+    pub private_key: Vec<u8>,
+    pub certificate: Option<Vec<u8>>,
+}
+
+/// The database of the AS, cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    pool: AnyPool,
+}
+
+impl Storage {
+    /// Connects to the configured database and runs the migrations it
+    /// misses.
+    pub async fn connect(settings: &DatabaseSettings) -> Result<Self, sqlx::Error> {
+        install_default_drivers();
+        let pool = AnyPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(2))
+            .connect(settings.with_db().expose_secret())
+            .await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Adds a user, `false` if the name is taken.
+    pub async fn insert_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+        email: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO users (username, password_hash, email, created_at) \
+             VALUES ($1, $2, $3, $4) ON CONFLICT (username) DO NOTHING",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .bind(email)
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn password_hash(&self, username: &str) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar("SELECT password_hash FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// The email of a user, `None` if unknown or without one.
+    pub async fn email(&self, username: &str) -> Result<Option<String>, sqlx::Error> {
+        let email: Option<Option<String>> =
+            sqlx::query_scalar("SELECT email FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(email.flatten())
+    }
+
+    /// Adds the credential of an identity, `false` if it is registered.
+    pub async fn insert_credential(&self, credential: &Credential) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO credentials (identity, credential, updated_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (identity) DO NOTHING",
+        )
+        .bind(&credential.identity)
+        .bind(encode(credential))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Replaces the credential of a registered identity, e.g. rotated or
+    /// revoked.
+    pub async fn update_credential(&self, credential: &Credential) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE credentials SET credential = $1, updated_at = $2 WHERE identity = $3")
+            .bind(encode(credential))
+            .bind(now())
+            .bind(&credential.identity)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn credentials(&self) -> Result<Vec<Credential>, sqlx::Error> {
+        let credentials: Vec<String> = sqlx::query_scalar("SELECT credential FROM credentials")
+            .fetch_all(&self.pool)
+            .await?;
+
+        credentials
+            .iter()
+            .map(|credential| {
+                serde_json::from_str(credential).map_err(|err| sqlx::Error::Decode(Box::new(err)))
+            })
+            .collect()
+    }
+
+    /// Adds the signing key `name`, `false` if it is stored.
+    pub async fn insert_signing_key(
+        &self,
+        name: &str,
+        key: &SigningKey,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO signing_keys (name, private_key, certificate, created_at) \
+             VALUES ($1, $2, $3, $4) ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(name)
+        .bind(STANDARD.encode(&key.private_key))
+        .bind(
+            key.certificate
+                .as_ref()
+                .map(|certificate| STANDARD.encode(certificate)),
+        )
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn signing_key(&self, name: &str) -> Result<Option<SigningKey>, sqlx::Error> {
+        let row: Option<(String, Option<String>)> =
+            sqlx::query_as("SELECT private_key, certificate FROM signing_keys WHERE name = $1")
+                .bind(name)
+                .fetch_optional(&self.pool)
+                .await?;
+        let Some((private_key, certificate)) = row else {
+            return Ok(None);
+        };
+        let decode = |encoded: String| {
+            STANDARD
+                .decode(encoded)
+                .map_err(|err| sqlx::Error::Decode(Box::new(err)))
+        };
+
+        Ok(Some(SigningKey {
+            private_key: decode(private_key)?,
+            certificate: certificate.map(decode).transpose()?,
+        }))
+    }
+}
+
+fn encode(credential: &Credential) -> String {
+    serde_json::to_string(credential).expect("Credentials always serialize")
+}
+
+fn now() -> i64 {
+    unix_seconds(SystemTime::now()) as i64
+}
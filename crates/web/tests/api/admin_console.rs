@@ -0,0 +1,123 @@
+use futures::{SinkExt, StreamExt};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::helpers::{expect_line, spawn_app_with_admin_console};
+
+#[tokio::test]
+async fn admin_console_lists_the_sessions_and_groups() {
+    let app = spawn_app_with_admin_console().await;
+    let (_alice, [_bob]) = app.group_of("alice", ["bob"]).await;
+    let mut console = app.connect_admin_console().await;
+
+    let sessions = console.run("a#ses", "sessions.").await;
+    let groups = console.run("a#grp", "groups.").await;
+
+    assert_eq!(sessions.last().unwrap(), "2 sessions.");
+    assert!(sessions.iter().any(|line| line.contains(" bob:")));
+    assert_eq!(groups.len(), 2);
+    assert!(groups[0].contains("epoch 1, 2 members, 0 pending proposals"));
+}
+
+#[tokio::test]
+async fn admin_console_counts_the_key_packages() {
+    let app = spawn_app_with_admin_console().await;
+    let mut bob = app.connected("bob").await;
+    bob.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut bob, "Published key packages").await;
+    let mut console = app.connect_admin_console().await;
+
+    let stats = console.run("a#kps", "key packages.").await;
+
+    assert!(stats[0].starts_with("bob:"));
+    assert_eq!(stats.last().unwrap(), "2 key packages.");
+}
+
+#[tokio::test]
+async fn admin_console_kicks_a_client() {
+    let app = spawn_app_with_admin_console().await;
+    let (_alice, [mut bob]) = app.group_of("alice", ["bob"]).await;
+    let mut console = app.connect_admin_console().await;
+    let sessions = console.run("a#ses", "sessions.").await;
+    let bob_id = sessions
+        .iter()
+        .find(|line| line.contains(" bob:"))
+        .and_then(|line| line.split(' ').next())
+        .unwrap()
+        .to_string();
+
+    let kicked = console.run(&format!("a#kck {}", bob_id), "client").await;
+
+    assert_eq!(kicked, vec![format!("Kicked client {}.", bob_id)]);
+    let closed = timeout(Duration::from_secs(10), async {
+        while let Some(Ok(msg)) = bob.next().await {
+            if msg.is_close() {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok(), "The WebSocket of bob stays open.");
+    let sessions = console.run("a#ses", "sessions.").await;
+    assert_eq!(sessions.last().unwrap(), "1 sessions.");
+    let groups = console.run("a#grp", "groups.").await;
+    assert!(groups[0].contains("1 members"));
+}
+
+#[tokio::test]
+async fn admin_console_broadcasts_a_notice() {
+    let app = spawn_app_with_admin_console().await;
+    let mut alice = app.connected("alice").await;
+    let mut bob = app.connected("bob").await;
+    let mut console = app.connect_admin_console().await;
+
+    let sent = console
+        .run("a#bct Restarting at noon", "Sent the notice")
+        .await;
+
+    assert_eq!(sent, vec!["Sent the notice to 2 clients."]);
+    for ws in [&mut alice, &mut bob] {
+        assert_eq!(
+            expect_line(ws, "Server notice").await,
+            "Server notice: Restarting at noon"
+        );
+    }
+}
+
+#[tokio::test]
+async fn admin_console_rejects_unknown_commands() {
+    let app = spawn_app_with_admin_console().await;
+    let mut console = app.connect_admin_console().await;
+
+    let kick_without_id = console.run("a#kck", "Unknown command").await;
+    let unknown = console.run("c#pkp", "Unknown command").await;
+
+    assert_eq!(kick_without_id.len(), 1);
+    assert_eq!(unknown.len(), 1);
+}
+
+#[tokio::test]
+async fn the_leaves_of_a_kicked_client_are_removed_once_revoked() {
+    let app = spawn_app_with_admin_console().await;
+    let mut alice = app.connected("alice").await;
+    let (mut bob, device) = app.connected_device("bob").await;
+    bob.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut bob, "Published key packages").await;
+    alice.send(Message::text("c#cgw bob")).await.unwrap();
+    expect_line(&mut bob, "Joined group").await;
+    let mut console = app.connect_admin_console().await;
+    let sessions = console.run("a#ses", "sessions.").await;
+    let bob_id = sessions
+        .iter()
+        .find(|line| line.contains(&device))
+        .and_then(|line| line.split(' ').next())
+        .unwrap()
+        .to_string();
+    console.run(&format!("a#kck {}", bob_id), "client").await;
+
+    let token = app.access_token("bob").await;
+    let response = app.revoke_identity(&token, &device).await;
+
+    assert_eq!(200, response.status().as_u16());
+    expect_line(&mut alice, &format!("Removed {}.", device)).await;
+}
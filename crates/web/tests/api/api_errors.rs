@@ -0,0 +1,89 @@
+use actix_web::{body::to_bytes, ResponseError};
+use anyhow::Context;
+use web::{
+    api_error::{ApiError, ErrorData},
+    utils::ResponseData,
+};
+
+use crate::helpers::spawn_app;
+
+fn unexpected_error() -> ApiError {
+    let cause = std::io::Error::other("disk on fire");
+
+    ApiError::unexpected(
+        Err::<(), _>(cause)
+            .context("Failed to store the user")
+            .unwrap_err(),
+    )
+}
+
+#[tokio::test]
+async fn errors_are_response_data_with_a_stable_code() {
+    let app = spawn_app().await;
+
+    let response = app.get_identity("nobody:0").await;
+
+    assert_eq!(404, response.status().as_u16());
+    let body: ResponseData<ErrorData> = response.json().await.unwrap();
+    assert_eq!(body.data.error, "not_found");
+    assert_eq!(body.message, "nobody:0 is not registered.");
+    assert_eq!(body.code, 404);
+}
+
+#[tokio::test]
+async fn middleware_errors_are_response_data() {
+    let app = spawn_app().await;
+
+    let response = app.get_me("not-a-token").await;
+
+    assert_eq!(401, response.status().as_u16());
+    let body: ResponseData<ErrorData> = response.json().await.unwrap();
+    assert_eq!(body.data.error, "unauthorized");
+}
+
+#[tokio::test]
+async fn malformed_bodies_and_queries_are_invalid_requests() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        app.register_user(&serde_json::json!({ "username": "alice" }))
+            .await,
+        app.api_client
+            .post(format!("{}/login", &app.address))
+            .header("Content-Type", "application/json")
+            .body("{not json")
+            .send()
+            .await
+            .unwrap(),
+        app.api_client
+            .get(format!("{}/key_packages/count", &app.address))
+            .send()
+            .await
+            .unwrap(),
+    ];
+
+    for response in test_cases {
+        assert_eq!(400, response.status().as_u16());
+        let body: ResponseData<ErrorData> = response.json().await.unwrap();
+        assert_eq!(body.data.error, "invalid_request");
+        assert_eq!(body.code, 400);
+    }
+}
+
+#[tokio::test]
+async fn unexpected_errors_hide_their_cause_from_the_client() {
+    let response = unexpected_error().error_response();
+
+    assert_eq!(500, response.status().as_u16());
+    let body = to_bytes(response.into_body()).await.unwrap();
+    let body: ResponseData<ErrorData> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body.data.error, "internal_error");
+    assert!(!body.message.contains("disk on fire"));
+}
+
+#[test]
+fn the_debug_output_has_the_whole_cause_chain() {
+    let debug = format!("{:?}", unexpected_error());
+
+    assert!(debug.contains("Failed to store the user"));
+    assert!(debug.contains("disk on fire"));
+}
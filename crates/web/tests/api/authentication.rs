@@ -0,0 +1,163 @@
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+use web::{authentication::SessionToken, utils::ResponseData};
+
+use crate::helpers::{expect_line, spawn_app, TestApp};
+
+fn user(username: &str, password: &str) -> serde_json::Value {
+    serde_json::json!({
+        "username": username,
+        "password": password,
+    })
+}
+
+/// Registers `username` and logs it in, returns its tokens.
+async fn logged_in(app: &TestApp, username: &str) -> SessionToken {
+    app.register_user(&user(username, "correct horse")).await;
+    let response = app.login(&user(username, "correct horse")).await;
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+
+    body.data
+}
+
+#[tokio::test]
+async fn register_user_returns_200_for_valid_user() {
+    let app = spawn_app().await;
+
+    let response = app.register_user(&user("alice", "correct horse")).await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn register_user_returns_400_when_data_is_invalid() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (user("", "correct horse"), "empty username"),
+        (
+            user("alice:0", "correct horse"),
+            "username with a device separator",
+        ),
+        (user("al ice", "correct horse"), "username with whitespace"),
+        (user("alice", "short"), "password too short"),
+        (user("alice", &"a".repeat(129)), "password too long"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.register_user(&body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload had an {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn register_user_returns_409_for_registered_username() {
+    let app = spawn_app().await;
+    app.register_user(&user("alice", "correct horse")).await;
+
+    let response = app.register_user(&user("alice", "battery staple")).await;
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn login_returns_tokens_for_the_right_password() {
+    let app = spawn_app().await;
+    app.register_user(&user("alice", "correct horse")).await;
+
+    let response = app.login(&user("alice", "correct horse")).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+    assert_eq!(body.data.identity, "alice");
+    assert!(!body.data.token.is_empty());
+    assert!(!body.data.refresh_token.is_empty());
+}
+
+#[tokio::test]
+async fn login_returns_401_for_invalid_credentials() {
+    let app = spawn_app().await;
+    app.register_user(&user("alice", "correct horse")).await;
+    let test_cases = vec![
+        (user("alice", "battery staple"), "a wrong password"),
+        (user("bob", "correct horse"), "an unknown user"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.login(&body).await;
+
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not fail with 401 Unauthorized for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn me_returns_the_subject_of_the_access_token() {
+    let app = spawn_app().await;
+    let session = logged_in(&app, "alice").await;
+
+    let response = app.get_me(&session.token).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<String> = response.json().await.unwrap();
+    assert_eq!(body.data, "alice");
+}
+
+#[tokio::test]
+async fn me_returns_401_without_a_valid_access_token() {
+    let app = spawn_app().await;
+    let session = logged_in(&app, "alice").await;
+    let (payload, signature) = session.token.split_once('.').unwrap();
+    let tampered = format!("{}x.{}", payload, signature);
+    let test_cases = vec![
+        ("", "no token"),
+        ("not a token", "a malformed token"),
+        (tampered.as_str(), "a tampered token"),
+    ];
+
+    for (token, description) in test_cases {
+        let response = app.get_me(token).await;
+
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not fail with 401 Unauthorized for {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn refresh_token_is_traded_for_new_tokens_once() {
+    let app = spawn_app().await;
+    let session = logged_in(&app, "alice").await;
+
+    let response = app.refresh_session(&session.refresh_token).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+    assert_eq!(200, app.get_me(&body.data.token).await.status().as_u16());
+    assert_ne!(body.data.refresh_token, session.refresh_token);
+
+    let response = app.refresh_session(&session.refresh_token).await;
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn logged_in_user_connects_to_the_delivery_service() {
+    let app = spawn_app().await;
+    let session = logged_in(&app, "alice").await;
+    let mut ws = app.connect_websocket(&session.token).await.unwrap();
+
+    ws.send(Message::text("c#usr mallory")).await.unwrap();
+
+    expect_line(&mut ws, "You signed in as alice").await;
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use claims::{assert_err, assert_ok};
+use secrecy::Secret;
+use web::email_client::{Email, HttpEmailClient};
+use wiremock::{
+    matchers::{any, header, header_exists, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+fn email() -> Email {
+    Email {
+        recipient: "alice@example.com".to_string(),
+        subject: "Your login code".to_string(),
+        html_content: "Your login code is <b>123456</b>.".to_string(),
+        text_content: "Your login code is 123456.".to_string(),
+    }
+}
+
+fn email_client(base_url: String) -> HttpEmailClient {
+    HttpEmailClient::new(
+        base_url,
+        "chat@example.com".to_string(),
+        Secret::new("my-secret-token".to_string()),
+        Duration::from_millis(200),
+    )
+}
+
+#[tokio::test]
+async fn send_email_posts_the_email_to_the_api() {
+    let mock_server = MockServer::start().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header_exists("X-Postmark-Server-Token"))
+        .and(header("Content-Type", "application/json"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcome = email_client(mock_server.uri()).send_email(&email()).await;
+
+    assert_ok!(outcome);
+    let requests = mock_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["From"], "chat@example.com");
+    assert_eq!(body["To"], "alice@example.com");
+    assert_eq!(body["TextBody"], "Your login code is 123456.");
+}
+
+#[tokio::test]
+async fn send_email_fails_if_the_api_returns_500() {
+    let mock_server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcome = email_client(mock_server.uri()).send_email(&email()).await;
+
+    assert_err!(outcome);
+}
+
+#[tokio::test]
+async fn send_email_times_out_if_the_api_takes_too_long() {
+    let mock_server = MockServer::start().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(180)))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let outcome = email_client(mock_server.uri()).send_email(&email()).await;
+
+    assert_err!(outcome);
+}
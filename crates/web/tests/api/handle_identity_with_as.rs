@@ -1,11 +1,363 @@
-use crate::helpers::spawn_app;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::auth_service::{
+    Credential, CredentialError, CredentialType, CredentialValidator, SignatureScheme, TrustAnchor,
+};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use web::{
+    auth_service::{CredentialRecord, CredentialStore, IssuanceReceipt},
+    utils::ResponseData,
+};
+
+use crate::helpers::{key_pair, registration, spawn_app, ADMIN};
+
+/// A rotation to `new`, signed with the registered `old` key.
+fn rotation(old: &Ed25519KeyPair, new: &Ed25519KeyPair) -> serde_json::Value {
+    serde_json::json!({
+        "signature_key": STANDARD.encode(new.public_key()),
+        "signature_scheme": "Ed25519",
+        "proof": STANDARD.encode(old.sign(new.public_key().as_ref())),
+    })
+}
+
+#[tokio::test]
+async fn post_identities_returns_a_signed_receipt() {
+    let app = spawn_app().await;
+    let key_pair = key_pair();
+    let token = app.access_token("alice").await;
+
+    let response = app
+        .post_identities(&token, &registration("alice:0", &key_pair))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<IssuanceReceipt> = response.json().await.unwrap();
+    let receipt = body.data;
+    assert_eq!(receipt.credential.identity, "alice:0");
+    assert_eq!(
+        receipt.credential.signature_key,
+        STANDARD.encode(key_pair.public_key())
+    );
+    assert!(!receipt.credential.revoked);
+    assert!(receipt.credential.valid_until > Some(receipt.credential.valid_from));
+
+    let issuer_key = STANDARD.decode(&receipt.issuer_key).unwrap();
+    assert!(receipt.verify(&issuer_key));
+    let mut forged = receipt;
+    forged.credential.identity = "mallory:0".to_string();
+    assert!(!forged.verify(&issuer_key));
+}
+
+#[tokio::test]
+async fn post_identities_issues_a_certificate_for_x509_credentials() {
+    let app = spawn_app().await;
+    let key_pair = key_pair();
+    let mut body = registration("alice:0", &key_pair);
+    body["credential_type"] = "X509".into();
+    let token = app.access_token("alice").await;
+
+    let response = app.post_identities(&token, &body).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<IssuanceReceipt> = response.json().await.unwrap();
+    let record = body.data.credential;
+    assert_eq!(record.credential_type, CredentialType::X509);
+    assert_eq!(record.certificate_chain.len(), 1);
+
+    // The chain is checked offline against the certificate of the local CA.
+    let body: ResponseData<String> = app.get_ca_certificate().await.json().await.unwrap();
+    let anchor = TrustAnchor::from_der(STANDARD.decode(body.data).unwrap()).unwrap();
+    let chain = record
+        .certificate_chain
+        .iter()
+        .map(|certificate| STANDARD.decode(certificate).unwrap())
+        .collect();
+    let credential = Credential::x509(chain, &anchor).unwrap();
+    assert_eq!(credential.identity, "alice:0");
+    assert_eq!(credential.public_key, key_pair.public_key().as_ref());
+}
+
+#[tokio::test]
+async fn post_identities_returns_400_for_invalid_data() {
+    let app = spawn_app().await;
+    let token = app.access_token("alice").await;
+    let mut signed_with_another_key = registration("alice:0", &key_pair());
+    signed_with_another_key["proof"] = registration("alice:0", &key_pair())["proof"].clone();
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "identity": " ",
+                "signature_key": STANDARD.encode([7u8; 32]),
+                "signature_scheme": "Ed25519",
+                "proof": STANDARD.encode([0u8; 64]),
+            }),
+            "empty identity",
+        ),
+        (
+            serde_json::json!({
+                "identity": "alice:0",
+                "signature_key": "not base64!",
+                "signature_scheme": "Ed25519",
+                "proof": STANDARD.encode([0u8; 64]),
+            }),
+            "invalid base64 key",
+        ),
+        (
+            serde_json::json!({
+                "identity": "alice:0",
+                "signature_key": STANDARD.encode([7u8; 31]),
+                "signature_scheme": "Ed25519",
+                "proof": STANDARD.encode([0u8; 64]),
+            }),
+            "key of the wrong length",
+        ),
+        (
+            serde_json::json!({
+                "identity": "alice:0",
+                "signature_key": STANDARD.encode([7u8; 32]),
+                "signature_scheme": "Rsa",
+                "proof": STANDARD.encode([0u8; 64]),
+            }),
+            "unknown signature scheme",
+        ),
+        (
+            serde_json::json!({ "identity": "alice:0" }),
+            "missing signature key",
+        ),
+        (
+            registration("alice", &key_pair()),
+            "identity without device",
+        ),
+        (registration("alice:", &key_pair()), "empty device"),
+        (signed_with_another_key, "proof of another key"),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_identities(&token, &body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn post_identities_returns_401_without_a_valid_access_token() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_identities("not a token", &registration("alice:0", &key_pair()))
+        .await;
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(404, app.get_identity("alice:0").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_identities_returns_403_for_a_device_of_another_user() {
+    let app = spawn_app().await;
+    let token = app.access_token("mallory").await;
+
+    let response = app
+        .post_identities(&token, &registration("alice:0", &key_pair()))
+        .await;
+
+    assert_eq!(403, response.status().as_u16());
+    assert_eq!(404, app.get_identity("alice:0").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_identities_returns_409_for_a_registered_identity() {
+    let app = spawn_app().await;
+    app.registered("alice:0").await;
+    let token = app.access_token("alice").await;
+
+    let response = app
+        .post_identities(&token, &registration("alice:0", &key_pair()))
+        .await;
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn get_identity_returns_the_registered_credential() {
+    let app = spawn_app().await;
+    let key_pair = app.registered("alice:0").await;
+
+    let response = app.get_identity("alice:0").await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<CredentialRecord> = response.json().await.unwrap();
+    assert_eq!(body.data.identity, "alice:0");
+    assert_eq!(
+        body.data.signature_key,
+        STANDARD.encode(key_pair.public_key())
+    );
+}
+
+#[tokio::test]
+async fn get_identity_returns_404_for_an_unknown_identity() {
+    let app = spawn_app().await;
+
+    let response = app.get_identity("bob:0").await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn the_store_validates_registered_identities_and_devices_of_sessions() {
+    let store = CredentialStore::default();
+    let validity = std::time::Duration::from_secs(60);
+    store
+        .insert(Credential::basic(
+            "alice:0".to_string(),
+            vec![7; 32],
+            SignatureScheme::Ed25519,
+            validity,
+        ))
+        .await
+        .unwrap();
+    store.devices().register(Credential::basic(
+        "bob:1".to_string(),
+        vec![8; 32],
+        SignatureScheme::Ed25519,
+        validity,
+    ));
+
+    assert!(store.validate(b"alice:0", &[7; 32]).is_ok());
+    assert!(store.validate(b"bob:1", &[8; 32]).is_ok());
+    assert!(matches!(
+        store.validate(b"alice:0", &[8; 32]),
+        Err(CredentialError::KeyMismatch(_))
+    ));
+    // A session can't pass for an identity it did not register
+    assert!(matches!(
+        store.validate(b"mallory:2", &[9; 32]),
+        Err(CredentialError::Unknown(_))
+    ));
+}
+
+#[tokio::test]
+async fn revoke_identity_marks_the_credential_revoked() {
+    let app = spawn_app().await;
+    app.registered("alice:0").await;
+    let token = app.access_token("alice").await;
+
+    let response = app.revoke_identity(&token, "alice:0").await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<CredentialRecord> = response.json().await.unwrap();
+    assert!(body.data.revoked);
+    let body: ResponseData<CredentialRecord> =
+        app.get_identity("alice:0").await.json().await.unwrap();
+    assert!(body.data.revoked);
+}
+
+#[tokio::test]
+async fn revoke_identity_returns_404_for_unknown_identity() {
+    let app = spawn_app().await;
+    let token = app.access_token("nobody").await;
+
+    let response = app.revoke_identity(&token, "nobody:0").await;
+
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn revoke_identity_returns_401_without_a_valid_access_token() {
+    let app = spawn_app().await;
+    app.registered("alice:0").await;
+
+    let response = app.revoke_identity("not a token", "alice:0").await;
+
+    assert_eq!(401, response.status().as_u16());
+    let body: ResponseData<CredentialRecord> =
+        app.get_identity("alice:0").await.json().await.unwrap();
+    assert!(!body.data.revoked);
+}
+
+#[tokio::test]
+async fn revoke_identity_returns_403_for_the_identity_of_another_user() {
+    let app = spawn_app().await;
+    app.registered("alice:0").await;
+    let token = app.access_token("bob").await;
+
+    let response = app.revoke_identity(&token, "alice:0").await;
+
+    assert_eq!(403, response.status().as_u16());
+    let body: ResponseData<CredentialRecord> =
+        app.get_identity("alice:0").await.json().await.unwrap();
+    assert!(!body.data.revoked);
+}
+
+#[tokio::test]
+async fn revoke_identity_lets_admins_revoke_any_identity() {
+    let app = spawn_app().await;
+    app.registered("alice:0").await;
+    let token = app.access_token(ADMIN).await;
+
+    let response = app.revoke_identity(&token, "alice:0").await;
+
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn rotate_identity_replaces_the_signature_key() {
+    let app = spawn_app().await;
+    let (old, new) = (key_pair(), key_pair());
+    let mut body = registration("alice:0", &old);
+    body["credential_type"] = "X509".into();
+    let token = app.access_token("alice").await;
+    app.post_identities(&token, &body).await;
+
+    let response = app.rotate_identity("alice:0", &rotation(&old, &new)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<IssuanceReceipt> = response.json().await.unwrap();
+    let record = body.data.credential;
+    assert_eq!(record.signature_key, STANDARD.encode(new.public_key()));
+    assert_eq!(record.credential_type, CredentialType::X509);
+    assert_eq!(record.certificate_chain.len(), 1);
+    let body: ResponseData<CredentialRecord> =
+        app.get_identity("alice:0").await.json().await.unwrap();
+    assert_eq!(body.data.signature_key, STANDARD.encode(new.public_key()));
+}
+
+#[tokio::test]
+async fn rotate_identity_returns_400_without_a_proof_of_the_registered_key() {
+    let app = spawn_app().await;
+    let (old, new) = (app.registered("alice:0").await, key_pair());
+
+    // Signed with the new key instead of the registered one
+    let response = app.rotate_identity("alice:0", &rotation(&new, &new)).await;
+
+    assert_eq!(400, response.status().as_u16());
+    let body: ResponseData<CredentialRecord> =
+        app.get_identity("alice:0").await.json().await.unwrap();
+    assert_eq!(body.data.signature_key, STANDARD.encode(old.public_key()));
+}
+
+#[tokio::test]
+async fn rotate_identity_returns_403_for_revoked_identity() {
+    let app = spawn_app().await;
+    let (old, new) = (app.registered("alice:0").await, key_pair());
+    let token = app.access_token("alice").await;
+    app.revoke_identity(&token, "alice:0").await;
+
+    let response = app.rotate_identity("alice:0", &rotation(&old, &new)).await;
+
+    assert_eq!(403, response.status().as_u16());
+}
 
 #[tokio::test]
-async fn post_identities_works() {
+async fn rotate_identity_returns_404_for_unknown_identity() {
     let app = spawn_app().await;
 
-    let response = app.post_identities().await;
+    let response = app
+        .rotate_identity("nobody:0", &rotation(&key_pair(), &key_pair()))
+        .await;
 
-    assert!(response.status().is_success());
-    assert_eq!(Some(0), response.content_length());
+    assert_eq!(404, response.status().as_u16());
 }
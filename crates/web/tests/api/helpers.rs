@@ -1,33 +1,75 @@
 use fake::faker::internet::en::SafeEmail;
 use fake::Fake;
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{SinkExt, StreamExt};
 use once_cell::sync::Lazy;
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+use secrecy::ExposeSecret;
+use sqlx::{any::install_default_drivers, AnyConnection, Connection, Executor};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    time::timeout,
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream, WebSocketStream,
+};
 use uuid::Uuid;
 use web::{
-    configuration::get_configuration,
+    auth_service::registration_message,
+    authentication::SessionToken,
+    configuration::{get_configuration, DatabaseEngine, DatabaseSettings, Settings},
+    email_client::{Email, EmailClient},
     startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, TestSink},
+    utils::ResponseData,
 };
 
-static TRACING: Lazy<()> = Lazy::new(|| {
-    let default_filter_level = "info".to_string();
-    let subscriber_name = "test".to_string();
+pub type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+/// The user the test app lets on the admin API.
+pub const ADMIN: &str = "admin";
+
+/// The records of every test app, for the tests to assert on. Printed too
+/// with `TEST_LOG` set.
+pub static TEST_SINK: Lazy<TestSink> = Lazy::new(|| {
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::stdout);
-        init_subscriber(subscriber);
+        TestSink::echoing()
     } else {
-        let subscriber = get_subscriber(subscriber_name, default_filter_level, std::io::sink);
-        init_subscriber(subscriber);
+        TestSink::default()
     }
 });
 
+static TRACING: Lazy<()> = Lazy::new(|| {
+    let default_filter_level = "info".to_string();
+    let subscriber_name = "test".to_string();
+
+    let subscriber = get_subscriber(subscriber_name, default_filter_level, TEST_SINK.clone());
+    init_subscriber(subscriber);
+});
+
+// Not every field is read by the tests yet
+#[allow(dead_code)]
 pub struct TestApp {
     pub address: String,
     pub port: u16,
     pub api_client: reqwest::Client,
     pub test_user: TestUser,
+    pub configuration: Settings,
+    pub email_client: actix_web::web::Data<EmailClient>,
 }
 
+#[allow(dead_code)]
 pub struct TestUser {
     pub user_id: Uuid,
     pub username: String,
@@ -47,49 +89,561 @@ impl TestUser {
 impl TestApp {
     pub async fn get_healthcheck(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/health_check", &self.address))
+            .get(format!("{}/health_check", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_metrics(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/metrics", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_identities(
+        &self,
+        token: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/identities", &self.address))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_identity(&self, identity: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/identities/{}", &self.address, identity))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_ca_certificate(&self) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/ca/certificate", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn rotate_identity(
+        &self,
+        identity: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/identities/{}/rotate", &self.address, identity))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_key_packages(
+        &self,
+        token: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/key_packages", &self.address))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn claim_key_packages(
+        &self,
+        token: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/key_packages/claim", &self.address))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn count_key_packages(&self, identity: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/key_packages/count", &self.address))
+            .query(&[("identity", identity)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn open_session(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/sessions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn register_user(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/users", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn login(&self, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn refresh_session(&self, refresh_token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/sessions/refresh", &self.address))
+            .json(&serde_json::json!({ "refresh_token": refresh_token }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_me(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/me", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Connects to the delivery service with an access token as bearer.
+    pub async fn connect_websocket(
+        &self,
+        token: &str,
+    ) -> Result<WebSocket, tokio_tungstenite::tungstenite::Error> {
+        let mut request = format!("ws://127.0.0.1:{}/ws", self.port)
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "Authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+
+        connect_async(request).await.map(|(stream, _)| stream)
+    }
+
+    /// Registers `username` with a password and logs it in, returns its
+    /// access token.
+    pub async fn access_token(&self, username: &str) -> String {
+        let user = serde_json::json!({
+            "username": username,
+            "password": "correct horse",
+        });
+        self.register_user(&user).await;
+        let response = self.login(&user).await;
+        let body: ResponseData<SessionToken> = response.json().await.unwrap();
+
+        body.data.token
+    }
+
+    /// Registers the device `identity` as its user, under a new key pair.
+    pub async fn registered(&self, identity: &str) -> Ed25519KeyPair {
+        let key_pair = key_pair();
+        let user = identity.split(':').next().unwrap();
+        self.post_identities(
+            &self.access_token(user).await,
+            &registration(identity, &key_pair),
+        )
+        .await;
+
+        key_pair
+    }
+
+    /// Connects a new device of `user` to the delivery service, returns its
+    /// WebSocket and its identity.
+    pub async fn connected_device(&self, user: &str) -> (WebSocket, String) {
+        let mut ws = self
+            .connect_websocket(&self.access_token(user).await)
+            .await
+            .unwrap();
+        let line = expect_line(&mut ws, "You are device").await;
+        let identity = line
+            .trim_start_matches("You are device ")
+            .trim_end_matches('.')
+            .to_string();
+
+        (ws, identity)
+    }
+
+    /// Connects a new device of `user` to the delivery service.
+    pub async fn connected(&self, user: &str) -> WebSocket {
+        self.connected_device(user).await.0
+    }
+
+    /// `creator` creates a group with `members`, who published their key
+    /// packages. Returns the WebSocket of each, once they all joined.
+    pub async fn group_of<const N: usize>(
+        &self,
+        creator: &str,
+        members: [&str; N],
+    ) -> (WebSocket, [WebSocket; N]) {
+        let mut first = self.connected(creator).await;
+        let mut others = Vec::new();
+        for member in members {
+            let mut ws = self.connected(member).await;
+            ws.send(Message::text("c#pkp")).await.unwrap();
+            expect_line(&mut ws, "Published key packages").await;
+            others.push(ws);
+        }
+        first
+            .send(Message::text(format!("c#cgw {}", members.join(" "))))
+            .await
+            .unwrap();
+        for ws in &mut others {
+            expect_line(ws, "Joined group").await;
+        }
+        let Ok(others) = others.try_into() else {
+            unreachable!("One WebSocket of each member");
+        };
+
+        (first, others)
+    }
+
+    pub async fn create_room(&self, token: &str, body: &serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/rooms", &self.address))
+            .bearer_auth(token)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn list_rooms(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/rooms", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_room(&self, token: &str, id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/admin/rooms/{}", &self.address, id))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn rename_room(&self, token: &str, id: &str, name: &str) -> reqwest::Response {
+        self.api_client
+            .patch(format!("{}/admin/rooms/{}", &self.address, id))
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "name": name }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn set_room_members(
+        &self,
+        token: &str,
+        id: &str,
+        allowed: &[&str],
+    ) -> reqwest::Response {
+        self.api_client
+            .put(format!("{}/admin/rooms/{}/members", &self.address, id))
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "allowed": allowed }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn archive_room(&self, token: &str, id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/rooms/{}/archive", &self.address, id))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_room(&self, token: &str, id: &str) -> reqwest::Response {
+        self.api_client
+            .delete(format!("{}/admin/rooms/{}", &self.address, id))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn request_login_otp(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/otp", &self.address))
+            .json(&serde_json::json!({ "username": username }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn login_with_otp(&self, username: &str, otp: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/login/otp/verify", &self.address))
+            .json(&serde_json::json!({ "username": username, "otp": otp }))
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
-    pub async fn post_identities(&self) -> reqwest::Response {
+    pub async fn request_device_link(&self, token: &str) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/identities", &self.address))
+            .post(format!("{}/devices/link", &self.address))
+            .bearer_auth(token)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn redeem_device_link(&self, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/devices/link/redeem", &self.address))
+            .json(&serde_json::json!({ "code": code }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Connects to the admin console, of an app spawned with one.
+    pub async fn connect_admin_console(&self) -> AdminConsole {
+        let port = self
+            .configuration
+            .application
+            .admin_console_port
+            .expect("The app runs an admin console");
+        // The console is spawned with the app, it may not listen yet
+        let connect = async {
+            loop {
+                match TcpStream::connect(("127.0.0.1", port)).await {
+                    Ok(tcp) => return tcp,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let tcp = timeout(Duration::from_secs(10), connect)
+            .await
+            .expect("The admin console does not listen");
+        let (read, write) = tcp.into_split();
+        let mut console = AdminConsole {
+            lines: BufReader::new(read).lines(),
+            write,
+        };
+        console.lines.next_line().await.unwrap();
+
+        console
+    }
+
+    /// The last email the app captured for `recipient`.
+    pub fn last_email_to(&self, recipient: &str) -> Email {
+        self.email_client
+            .capture()
+            .expect("The test app captures its emails")
+            .last_to(recipient)
+            .unwrap_or_else(|| panic!("No email was sent to {}.", recipient))
+    }
+
+    pub async fn revoke_identity(&self, token: &str, identity: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/identities/{}/revoke", &self.address, identity))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+}
+
+/// The one-time code in the text of an email, its only word of digits and
+/// capitals.
+pub fn code_in(email: &Email) -> String {
+    email
+        .text_content
+        .split_whitespace()
+        .map(|word| word.trim_end_matches('.'))
+        .find(|word| {
+            word.len() >= 6
+                && word
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+        })
+        .expect("The email has a code")
+        .to_string()
+}
+
+/// A telnet connection to the admin console of the DS.
+pub struct AdminConsole {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+}
+
+impl AdminConsole {
+    /// Sends `command`, returns the lines of the answer up to the one that
+    /// contains `last`.
+    pub async fn run(&mut self, command: &str, last: &str) -> Vec<String> {
+        self.write
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .unwrap();
+        let read = async {
+            let mut answer = Vec::new();
+            while let Some(line) = self.lines.next_line().await.unwrap() {
+                let done = line.contains(last);
+                answer.push(line);
+                if done {
+                    return answer;
+                }
+            }
+            panic!("The admin console closed before `{}`.", last);
+        };
+
+        timeout(Duration::from_secs(10), read)
+            .await
+            .unwrap_or_else(|_| panic!("No line with `{}`.", last))
+    }
+}
+
+/// Reads the lines of a WebSocket until one contains `expected`, and returns
+/// it.
+pub fn key_pair() -> Ed25519KeyPair {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+}
+
+/// A registration of `identity` under the key of `key_pair`, signed with it.
+pub fn registration(identity: &str, key_pair: &Ed25519KeyPair) -> serde_json::Value {
+    serde_json::json!({
+        "identity": identity,
+        "signature_key": STANDARD.encode(key_pair.public_key()),
+        "signature_scheme": "Ed25519",
+        "proof": STANDARD.encode(key_pair.sign(&registration_message(identity))),
+    })
+}
+
+pub async fn expect_line(ws: &mut WebSocket, expected: &str) -> String {
+    let read = async {
+        while let Some(msg) = ws.next().await {
+            if let Message::Text(line) = msg.unwrap() {
+                if line.contains(expected) {
+                    return line;
+                }
+            }
+        }
+        panic!("The WebSocket closed before `{}`.", expected);
+    };
+
+    timeout(Duration::from_secs(10), read)
+        .await
+        .unwrap_or_else(|_| panic!("No line with `{}`.", expected))
+}
+
+/// A line of the chat without the time it was sent, e.g. `alice: hello`.
+pub fn untimed(line: String) -> String {
+    match line.split_once("] ") {
+        Some((_, text)) if line.starts_with('[') => text.to_string(),
+        _ => line,
+    }
+}
+
+/// Points the app at a database of its own, created empty.
+async fn configure_database(config: &mut DatabaseSettings) {
+    match config.engine {
+        DatabaseEngine::Sqlite => {
+            config.database_name = std::env::temp_dir()
+                .join(format!("{}.db", Uuid::new_v4()))
+                .to_string_lossy()
+                .into_owned();
+        }
+        DatabaseEngine::Postgres => {
+            config.database_name = Uuid::new_v4().to_string();
+            install_default_drivers();
+            let mut connection = AnyConnection::connect(config.without_db().expose_secret())
+                .await
+                .expect("Failed to connect to Postgres");
+            connection
+                .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
+                .await
+                .expect("Failed to create database.");
+        }
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(test_configuration().await).await
+}
+
+/// Runs the app with the admin console of its DS, on a free port.
+pub async fn spawn_app_with_admin_console() -> TestApp {
+    let mut configuration = test_configuration().await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port")
+        .port();
+    configuration.application.admin_console_port = Some(port);
+
+    spawn_app_with(configuration).await
+}
+
+/// The configuration of a test app, with a database of its own.
+async fn test_configuration() -> Settings {
     // Singleton Pattern
     Lazy::force(&TRACING);
 
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    // Wildcard port, the system will find available port
+    configuration.application.port = 0;
+    configuration.application.admins = vec![ADMIN.to_string()];
+    configure_database(&mut configuration.database).await;
+
+    configuration
+}
+
+/// Runs the app with `configuration`, e.g. a second one on the database of
+/// another.
+pub async fn spawn_app_with(configuration: Settings) -> TestApp {
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
         .cookie_store(true)
         .build()
         .unwrap();
-    let configuration = {
-        let mut c = get_configuration().expect("Failed to read configuration");
-        // Wildcard port, the system will find available port
-        c.application.port = 0;
-        c
-    };
     let app = Application::build(configuration.clone())
         .await
         .expect("Failed to build application");
     let port = app.port();
+    let email_client = app.email_client();
     let address = format!("http://127.0.0.1:{}", port);
 
     // Run the application
-    let _ = tokio::spawn(app.run_until_stopped());
+    tokio::spawn(app.run_until_stopped());
     let test_app = TestApp {
         address,
         port,
         api_client,
         test_user: TestUser::generate(),
+        configuration,
+        email_client,
     };
     // Add test user
     test_app
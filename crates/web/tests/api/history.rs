@@ -0,0 +1,73 @@
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::helpers::{expect_line, spawn_app, untimed, TestApp, WebSocket};
+
+/// Alice creates a group with bob, and says hello twice.
+async fn alice_and_bob_talked(app: &TestApp) -> (WebSocket, WebSocket) {
+    let (mut alice, [mut bob]) = app.group_of("alice", ["bob"]).await;
+    for line in ["hello bob", "how are you"] {
+        alice.send(Message::text(line)).await.unwrap();
+        expect_line(&mut bob, line).await;
+    }
+
+    (alice, bob)
+}
+
+#[tokio::test]
+async fn history_returns_the_last_lines_of_the_group() {
+    let app = spawn_app().await;
+    let (mut alice, mut bob) = alice_and_bob_talked(&app).await;
+
+    bob.send(Message::text("/history 10")).await.unwrap();
+    alice.send(Message::text("/history 1")).await.unwrap();
+
+    assert_eq!(
+        untimed(expect_line(&mut bob, "alice:").await),
+        "alice: hello bob"
+    );
+    assert_eq!(
+        untimed(expect_line(&mut bob, "alice:").await),
+        "alice: how are you"
+    );
+    assert_eq!(
+        untimed(expect_line(&mut alice, "alice:").await),
+        "alice: how are you"
+    );
+}
+
+#[tokio::test]
+async fn history_from_before_joining_can_not_be_decrypted() {
+    let app = spawn_app().await;
+    let (mut alice, mut bob) = alice_and_bob_talked(&app).await;
+    let mut carol = app.connected("carol").await;
+    carol.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut carol, "Published key packages").await;
+    alice.send(Message::text("c#pad carol")).await.unwrap();
+    alice.send(Message::text("c#cmt")).await.unwrap();
+    expect_line(&mut carol, "Joined group").await;
+    bob.send(Message::text("welcome carol")).await.unwrap();
+    expect_line(&mut carol, "welcome carol").await;
+
+    carol.send(Message::text("/history 10")).await.unwrap();
+
+    assert_eq!(
+        untimed(expect_line(&mut carol, "bob:").await),
+        "bob: welcome carol"
+    );
+    // The lines of alice, the read receipts of bob are not archived
+    assert_eq!(
+        expect_line(&mut carol, "decrypted").await,
+        "2 messages can not be decrypted."
+    );
+}
+
+#[tokio::test]
+async fn history_is_only_fetched_in_a_group() {
+    let app = spawn_app().await;
+    let mut alice = app.connected("alice").await;
+
+    alice.send(Message::text("/history 10")).await.unwrap();
+
+    expect_line(&mut alice, "You are not in a group.").await;
+}
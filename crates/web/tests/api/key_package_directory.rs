@@ -0,0 +1,310 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::ext_mls::{identity_of, read_key_package, Member, MemoryProvider};
+use futures::SinkExt;
+use openmls::{
+    group::GroupId,
+    prelude::{tls_codec::Serialize as _, CredentialType},
+};
+use openmls_traits::signatures::Signer;
+use tokio_tungstenite::tungstenite::Message;
+use web::{
+    auth_service::{registration_message, IssuanceReceipt},
+    delivery_service::{Claimed, KeyPackageCount},
+    utils::ResponseData,
+};
+
+use crate::helpers::{expect_line, spawn_app, TestApp};
+
+/// A device registered with the AS under its signature key.
+async fn registered_member(app: &TestApp, user: &str, device: &str) -> Member {
+    let member = Member::new(user, device.to_string(), GroupId::from_slice(&[]));
+    app.post_identities(
+        &app.access_token(user).await,
+        &member_registration(&member, "Basic"),
+    )
+    .await;
+
+    member
+}
+
+/// A device whose leaves carry the certificate chain the AS issued for its
+/// signature key.
+async fn certified_member(app: &TestApp, user: &str, device: &str) -> Member {
+    let member = Member::new(user, device.to_string(), GroupId::from_slice(&[]));
+    let body: ResponseData<IssuanceReceipt> = app
+        .post_identities(
+            &app.access_token(user).await,
+            &member_registration(&member, "X509"),
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    let chain: Vec<Vec<u8>> = body
+        .data
+        .credential
+        .certificate_chain
+        .iter()
+        .map(|certificate| STANDARD.decode(certificate).unwrap())
+        .collect();
+
+    member.with_certificate_chain(&chain).unwrap()
+}
+
+fn member_registration(member: &Member, credential_type: &str) -> serde_json::Value {
+    let identity = String::from_utf8_lossy(member.identity()).into_owned();
+    let proof = member
+        .signer
+        .sign(&registration_message(&identity))
+        .unwrap();
+
+    serde_json::json!({
+        "identity": identity,
+        "signature_key": STANDARD.encode(member.credential_with_key.signature_key.as_slice()),
+        "signature_scheme": "Ed25519",
+        "credential_type": credential_type,
+        "proof": STANDARD.encode(proof),
+    })
+}
+
+fn publication(member: &Member, key_packages: usize, last_resort: bool) -> serde_json::Value {
+    let mut encoded: Vec<String> = (0..key_packages)
+        .map(|_| member.key_package().unwrap())
+        .map(|key_package| STANDARD.encode(key_package.tls_serialize_detached().unwrap()))
+        .collect();
+    if last_resort {
+        let key_package = member.last_resort_key_package().unwrap();
+        encoded.push(STANDARD.encode(key_package.tls_serialize_detached().unwrap()));
+    }
+
+    serde_json::json!({
+        "identity": String::from_utf8_lossy(member.identity()),
+        "key_packages": encoded,
+    })
+}
+
+async fn count(app: &TestApp, identity: &str) -> KeyPackageCount {
+    let body: ResponseData<KeyPackageCount> =
+        app.count_key_packages(identity).await.json().await.unwrap();
+    body.data
+}
+
+/// Claims as `token`, the key packages of `identities`.
+async fn claim(app: &TestApp, token: &str, identities: &[&str]) -> Vec<Claimed> {
+    let body: ResponseData<Vec<Claimed>> = app
+        .claim_key_packages(token, &serde_json::json!({ "identities": identities }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    body.data
+}
+
+#[tokio::test]
+async fn published_key_packages_are_claimed_once() {
+    let app = spawn_app().await;
+    let member = registered_member(&app, "alice", "0").await;
+    let alice = app.access_token("alice").await;
+    let token = app.access_token("bob").await;
+
+    let response = app
+        .post_key_packages(&alice, &publication(&member, 2, false))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(count(&app, "alice:0").await.count, 2);
+    for left in [1, 0] {
+        let claimed = claim(&app, &token, &["alice:0"]).await;
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].identity, "alice:0");
+        assert!(!claimed[0].last_resort);
+        let bytes = STANDARD.decode(&claimed[0].key_package).unwrap();
+        read_key_package(&MemoryProvider::default(), &bytes).unwrap();
+        assert_eq!(count(&app, "alice:0").await.count, left);
+    }
+    assert!(claim(&app, &token, &["alice:0"]).await.is_empty());
+}
+
+#[tokio::test]
+async fn key_packages_carry_the_certificate_chain_issued_by_the_ca() {
+    let app = spawn_app().await;
+    let member = certified_member(&app, "alice", "0").await;
+    let alice = app.access_token("alice").await;
+
+    let response = app
+        .post_key_packages(&alice, &publication(&member, 1, false))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let claimed = claim(&app, &app.access_token("bob").await, &["alice:0"]).await;
+    let bytes = STANDARD.decode(&claimed[0].key_package).unwrap();
+    let key_package = read_key_package(&MemoryProvider::default(), &bytes).unwrap();
+    let credential = key_package.leaf_node().credential();
+    assert_eq!(credential.credential_type(), CredentialType::X509);
+    assert_eq!(identity_of(credential), b"alice:0");
+}
+
+#[tokio::test]
+async fn a_user_gets_a_key_package_of_each_device() {
+    let app = spawn_app().await;
+    let bob = app.access_token("bob").await;
+    for device in ["0", "1"] {
+        let member = registered_member(&app, "bob", device).await;
+        app.post_key_packages(&bob, &publication(&member, 1, false))
+            .await;
+    }
+    let token = app.access_token("alice").await;
+
+    let mut claimed: Vec<String> = claim(&app, &token, &["bob"])
+        .await
+        .into_iter()
+        .map(|claimed| claimed.identity)
+        .collect();
+
+    claimed.sort();
+    assert_eq!(claimed, ["bob:0", "bob:1"]);
+}
+
+#[tokio::test]
+async fn last_resort_key_package_is_handed_out_once_the_pool_is_empty() {
+    let app = spawn_app().await;
+    let member = registered_member(&app, "alice", "0").await;
+    let alice = app.access_token("alice").await;
+    app.post_key_packages(&alice, &publication(&member, 1, true))
+        .await;
+    let token = app.access_token("bob").await;
+    assert_eq!(
+        count(&app, "alice:0").await,
+        KeyPackageCount {
+            identity: "alice:0".to_string(),
+            count: 1,
+            last_resort: true,
+        }
+    );
+
+    assert!(!claim(&app, &token, &["alice:0"]).await[0].last_resort);
+    for _ in 0..2 {
+        let claimed = claim(&app, &token, &["alice:0"]).await;
+        assert!(claimed[0].last_resort);
+    }
+    assert!(count(&app, "alice:0").await.last_resort);
+}
+
+#[tokio::test]
+async fn key_packages_published_on_the_delivery_service_are_claimed_over_http() {
+    let app = spawn_app().await;
+    let (mut ws, identity) = app.connected_device("alice").await;
+    ws.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut ws, "Published key packages").await;
+    let token = app.access_token("bob").await;
+
+    let claimed = claim(&app, &token, &[&identity]).await;
+
+    assert_eq!(claimed.len(), 1);
+    assert_eq!(claimed[0].identity, identity);
+    let bytes = STANDARD.decode(&claimed[0].key_package).unwrap();
+    read_key_package(&MemoryProvider::default(), &bytes).unwrap();
+}
+
+#[tokio::test]
+async fn claim_key_packages_returns_401_without_a_valid_access_token() {
+    let app = spawn_app().await;
+    let member = registered_member(&app, "alice", "0").await;
+    let alice = app.access_token("alice").await;
+    app.post_key_packages(&alice, &publication(&member, 1, false))
+        .await;
+
+    let response = app
+        .claim_key_packages(
+            "not a token",
+            &serde_json::json!({ "identities": ["alice:0"] }),
+        )
+        .await;
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(count(&app, "alice:0").await.count, 1);
+}
+
+#[tokio::test]
+async fn post_key_packages_returns_400_for_invalid_key_packages() {
+    let app = spawn_app().await;
+    let member = registered_member(&app, "alice", "0").await;
+    let alice = app.access_token("alice").await;
+    let mut tampered = member
+        .key_package()
+        .unwrap()
+        .tls_serialize_detached()
+        .unwrap();
+    let last = tampered.len() - 1;
+    tampered[last] ^= 1;
+    let test_cases = vec![
+        (vec!["not base64".to_string()], "invalid base64"),
+        (vec![STANDARD.encode([1u8, 2, 3])], "not a key package"),
+        (vec![STANDARD.encode(tampered)], "invalid signature"),
+    ];
+
+    for (key_packages, description) in test_cases {
+        let response = app
+            .post_key_packages(
+                &alice,
+                &serde_json::json!({
+                    "identity": "alice:0",
+                    "key_packages": key_packages,
+                }),
+            )
+            .await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+    }
+    assert_eq!(count(&app, "alice:0").await.count, 0);
+}
+
+#[tokio::test]
+async fn post_key_packages_returns_401_without_a_valid_access_token() {
+    let app = spawn_app().await;
+    let member = registered_member(&app, "alice", "0").await;
+
+    let response = app
+        .post_key_packages("not a token", &publication(&member, 1, false))
+        .await;
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(count(&app, "alice:0").await.count, 0);
+}
+
+#[tokio::test]
+async fn post_key_packages_returns_403_for_unregistered_or_other_identity() {
+    let app = spawn_app().await;
+    let member = registered_member(&app, "alice", "0").await;
+    let alice = app.access_token("alice").await;
+    let mallory = app.access_token("mallory").await;
+    let unregistered = Member::new("mallory", "0".to_string(), GroupId::from_slice(&[]));
+
+    let mut other = publication(&member, 1, false);
+    other["identity"] = "mallory:0".into();
+    let response = app.post_key_packages(&alice, &other).await;
+    assert_eq!(403, response.status().as_u16());
+
+    // A device of another user, even with its key packages
+    let response = app
+        .post_key_packages(&mallory, &publication(&member, 1, false))
+        .await;
+    assert_eq!(403, response.status().as_u16());
+
+    let response = app
+        .post_key_packages(&mallory, &publication(&unregistered, 1, false))
+        .await;
+    assert_eq!(403, response.status().as_u16());
+
+    app.revoke_identity(&alice, "alice:0").await;
+    let response = app
+        .post_key_packages(&alice, &publication(&member, 1, false))
+        .await;
+    assert_eq!(403, response.status().as_u16());
+    assert_eq!(count(&app, "alice:0").await.count, 0);
+}
@@ -1,3 +1,18 @@
+mod admin_console;
+mod api_errors;
+mod authentication;
+mod email_client;
 mod handle_identity_with_as;
 mod health_check;
 mod helpers;
+mod history;
+mod key_package_directory;
+mod metrics;
+mod one_time_codes;
+mod openapi;
+mod presence;
+mod receipts;
+mod rooms;
+mod storage;
+mod telemetry;
+mod websocket_transport;
@@ -0,0 +1,101 @@
+use chat_core::ext_mls::SUPPORTED_CIPHERSUITES;
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::helpers::{expect_line, spawn_app, TestApp};
+
+async fn scrape(app: &TestApp) -> String {
+    app.get_metrics().await.text().await.unwrap()
+}
+
+/// The value of the sample `sample`, e.g. `ds_groups` or
+/// `ds_messages_total{kind="commit"}`, among the scraped `metrics`.
+fn sample(metrics: &str, sample: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| {
+        let (name, value) = line.rsplit_once(' ')?;
+        (name == sample).then(|| value.parse().unwrap())
+    })
+}
+
+#[tokio::test]
+async fn metrics_are_served_in_the_prometheus_text_format() {
+    let app = spawn_app().await;
+
+    let response = app.get_metrics().await;
+
+    assert_eq!(200, response.status().as_u16());
+    let content_type = response.headers()["content-type"].to_str().unwrap();
+    assert!(content_type.starts_with("text/plain"));
+    let body = response.text().await.unwrap();
+    assert!(body.contains("# TYPE ds_sessions gauge"));
+    assert!(body.contains("# TYPE ds_groups gauge"));
+}
+
+#[tokio::test]
+async fn requests_are_counted_by_status() {
+    let app = spawn_app().await;
+
+    app.get_healthcheck().await;
+    app.get_me("not a token").await;
+
+    let metrics = scrape(&app).await;
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",status="200"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",status="401"}"#
+        ),
+        Some(1.0)
+    );
+}
+
+#[tokio::test]
+async fn sessions_groups_and_messages_are_counted() {
+    let app = spawn_app().await;
+    let mut alice = app
+        .connect_websocket(&app.access_token("alice").await)
+        .await
+        .unwrap();
+    let mut bob = app
+        .connect_websocket(&app.access_token("bob").await)
+        .await
+        .unwrap();
+    expect_line(&mut alice, "You are device").await;
+    expect_line(&mut bob, "You are device").await;
+
+    bob.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut bob, "Published key packages").await;
+    alice.send(Message::text("c#cgw bob")).await.unwrap();
+    expect_line(&mut bob, "Joined group").await;
+    alice.send(Message::text("hello bob")).await.unwrap();
+    expect_line(&mut bob, "hello bob").await;
+    expect_line(&mut alice, "bob read").await;
+
+    let metrics = scrape(&app).await;
+    assert_eq!(sample(&metrics, "ds_sessions"), Some(2.0));
+    assert_eq!(sample(&metrics, "ds_groups"), Some(1.0));
+    // One of each ciphersuite was published, adding bob claimed one
+    assert_eq!(
+        sample(&metrics, r#"key_packages{pool="directory"}"#),
+        Some((SUPPORTED_CIPHERSUITES.len() - 1) as f64)
+    );
+    // The line, and the read receipt of bob to alice only
+    assert_eq!(
+        sample(&metrics, r#"ds_messages_total{kind="group_message"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, r#"ds_messages_total{kind="signal"}"#),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(&metrics, r#"ds_messages_total{kind="welcome"}"#),
+        Some(1.0)
+    );
+}
@@ -0,0 +1,125 @@
+use web::{
+    authentication::{SessionToken, MAX_OTP_ATTEMPTS},
+    utils::ResponseData,
+};
+
+use crate::helpers::{code_in, spawn_app, TestApp};
+
+const EMAIL: &str = "alice@example.com";
+
+async fn register_alice(app: &TestApp) {
+    let response = app
+        .register_user(&serde_json::json!({
+            "username": "alice",
+            "password": "correct horse",
+            "email": EMAIL,
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn register_rejects_an_invalid_email() {
+    let app = spawn_app().await;
+
+    let response = app
+        .register_user(&serde_json::json!({
+            "username": "alice",
+            "password": "correct horse",
+            "email": "not-an-email",
+        }))
+        .await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn an_emailed_otp_logs_the_user_in_once() {
+    let app = spawn_app().await;
+    register_alice(&app).await;
+
+    assert_eq!(200, app.request_login_otp("alice").await.status().as_u16());
+    let email = app.last_email_to(EMAIL);
+    let otp = code_in(&email);
+    assert_eq!(6, otp.len());
+
+    let response = app.login_with_otp("alice", &otp).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+    assert_eq!(body.data.identity, "alice");
+    assert_eq!(200, app.get_me(&body.data.token).await.status().as_u16());
+
+    assert_eq!(
+        401,
+        app.login_with_otp("alice", &otp).await.status().as_u16()
+    );
+}
+
+#[tokio::test]
+async fn users_without_an_email_get_the_same_response_and_no_email() {
+    let app = spawn_app().await;
+    app.access_token("bob").await;
+
+    let without_email = app.request_login_otp("bob").await;
+    let unknown = app.request_login_otp("mallory").await;
+
+    assert_eq!(200, without_email.status().as_u16());
+    assert_eq!(200, unknown.status().as_u16());
+    let without_email: ResponseData<String> = without_email.json().await.unwrap();
+    let unknown: ResponseData<String> = unknown.json().await.unwrap();
+    assert_eq!(without_email.message, unknown.message);
+    assert!(app.email_client.capture().unwrap().sent().is_empty());
+}
+
+#[tokio::test]
+async fn too_many_wrong_otps_use_it_up() {
+    let app = spawn_app().await;
+    register_alice(&app).await;
+    app.request_login_otp("alice").await;
+    let otp = code_in(&app.last_email_to(EMAIL));
+    let wrong = if otp == "000000" { "111111" } else { "000000" };
+
+    for _ in 0..MAX_OTP_ATTEMPTS {
+        assert_eq!(
+            401,
+            app.login_with_otp("alice", wrong).await.status().as_u16()
+        );
+    }
+
+    assert_eq!(
+        401,
+        app.login_with_otp("alice", &otp).await.status().as_u16()
+    );
+}
+
+#[tokio::test]
+async fn a_device_link_code_logs_a_new_device_in_as_the_user_once() {
+    let app = spawn_app().await;
+    register_alice(&app).await;
+    let response = app
+        .login(&serde_json::json!({ "username": "alice", "password": "correct horse" }))
+        .await;
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+
+    let response = app.request_device_link(&body.data.token).await;
+    assert_eq!(200, response.status().as_u16());
+    let email = app.last_email_to(EMAIL);
+    assert_eq!(email.subject, "Link a new device");
+    let code = code_in(&email);
+
+    let response = app.redeem_device_link(&code.to_lowercase()).await;
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+    assert_eq!(body.data.identity, "alice");
+
+    assert_eq!(401, app.redeem_device_link(&code).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn a_device_link_needs_a_session_and_an_email() {
+    let app = spawn_app().await;
+    let token = app.access_token("bob").await;
+
+    assert_eq!(401, app.request_device_link("nope").await.status().as_u16());
+    assert_eq!(409, app.request_device_link(&token).await.status().as_u16());
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeSet;
+
+use utoipa::OpenApi;
+use web::openapi::ApiDoc;
+
+use crate::helpers::spawn_app;
+
+const ROUTE_METHODS: [&str; 5] = ["get", "post", "put", "patch", "delete"];
+
+/// The method and path of each operation of the generated document.
+fn documented_routes() -> BTreeSet<(String, String)> {
+    let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+    spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, operations)| {
+            operations
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(|method| (method.clone(), path.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The method and path of each route macro of the handlers, e.g.
+/// `#[post("/users")]`, also when rustfmt puts the path on a line of its own.
+fn implemented_routes() -> BTreeSet<(String, String)> {
+    let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+    let mut files = vec![src.join("routes.rs"), src.join("openapi.rs")];
+    for entry in std::fs::read_dir(src.join("routes")).unwrap() {
+        files.push(entry.unwrap().path());
+    }
+
+    let mut routes = BTreeSet::new();
+    for file in files {
+        let source = std::fs::read_to_string(file).unwrap();
+        let mut lines = source.lines().map(str::trim).peekable();
+        while let Some(line) = lines.next() {
+            for method in ROUTE_METHODS {
+                let attribute = match lines.peek() {
+                    Some(next) if line == format!("#[{}(", method) => format!("{}{}", line, next),
+                    _ => line.to_string(),
+                };
+                let Some(rest) = attribute.strip_prefix(&format!("#[{}(\"", method)) else {
+                    continue;
+                };
+                let path = rest.split('"').next().unwrap();
+                routes.insert((method.to_string(), path.to_string()));
+            }
+        }
+    }
+
+    routes
+}
+
+#[tokio::test]
+async fn openapi_json_serves_the_generated_document() {
+    let app = spawn_app().await;
+
+    let response = app
+        .api_client
+        .get(format!("{}/openapi.json", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(200, response.status().as_u16());
+    let spec: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(spec, serde_json::to_value(ApiDoc::openapi()).unwrap());
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+}
+
+#[test]
+fn every_route_is_documented_and_every_documented_route_exists() {
+    let documented = documented_routes();
+    let implemented = implemented_routes();
+
+    let undocumented: Vec<_> = implemented.difference(&documented).collect();
+    let unimplemented: Vec<_> = documented.difference(&implemented).collect();
+    assert!(
+        undocumented.is_empty(),
+        "Routes missing from the OpenAPI document: {:?}",
+        undocumented
+    );
+    assert!(
+        unimplemented.is_empty(),
+        "Documented routes without a handler: {:?}",
+        unimplemented
+    );
+}
+
+#[tokio::test]
+async fn every_documented_route_is_served() {
+    let app = spawn_app().await;
+
+    for (method, path) in documented_routes() {
+        let url = format!("{}{}", &app.address, path.replace(['{', '}'], ""));
+        let response = app
+            .api_client
+            .request(method.to_uppercase().parse().unwrap(), url)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // The app answers the routes it doesn't serve with an empty 404, its
+        // handlers with a JSON body
+        let status = response.status().as_u16();
+        let body = response.text().await.unwrap();
+        assert!(
+            status != 404 || !body.is_empty(),
+            "{} {} is documented but not served.",
+            method,
+            path
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::helpers::{expect_line, spawn_app};
+
+#[tokio::test]
+async fn who_lists_the_members_online() {
+    let app = spawn_app().await;
+    let (mut alice, [_bob]) = app.group_of("alice", ["bob"]).await;
+
+    alice.send(Message::text("/who")).await.unwrap();
+
+    expect_line(&mut alice, "Members of the group").await;
+    assert!(expect_line(&mut alice, "alice:")
+        .await
+        .ends_with(", online"));
+    assert!(expect_line(&mut alice, "bob:").await.ends_with(", online"));
+}
+
+#[tokio::test]
+async fn members_see_a_member_step_away_and_come_back() {
+    let app = spawn_app().await;
+    let (mut alice, [mut bob]) = app.group_of("alice", ["bob"]).await;
+
+    bob.send(Message::text("/away")).await.unwrap();
+
+    assert_eq!(expect_line(&mut alice, "is away").await, "bob is away.");
+    alice.send(Message::text("/who")).await.unwrap();
+    assert!(expect_line(&mut alice, "bob:").await.ends_with(", away"));
+    bob.send(Message::text("/back")).await.unwrap();
+    assert_eq!(expect_line(&mut alice, "is online").await, "bob is online.");
+}
+
+#[tokio::test]
+async fn members_see_a_member_leave() {
+    let app = spawn_app().await;
+    let (mut alice, [mut bob]) = app.group_of("alice", ["bob"]).await;
+
+    bob.close(None).await.unwrap();
+
+    assert_eq!(
+        expect_line(&mut alice, "is offline").await,
+        "bob is offline."
+    );
+    alice.send(Message::text("/who")).await.unwrap();
+    assert!(expect_line(&mut alice, "bob:").await.ends_with(", offline"));
+}
+
+#[tokio::test]
+async fn typing_is_sent_to_the_group() {
+    let app = spawn_app().await;
+    let (mut alice, [mut bob]) = app.group_of("alice", ["bob"]).await;
+
+    alice.send(Message::text("/typing")).await.unwrap();
+
+    assert_eq!(expect_line(&mut bob, "typing").await, "alice is typing...");
+}
@@ -0,0 +1,64 @@
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::helpers::{expect_line, spawn_app, untimed};
+
+#[tokio::test]
+async fn the_delivery_service_acknowledges_a_line() {
+    let app = spawn_app().await;
+    let (mut alice, [_bob, _carol]) = app.group_of("alice", ["bob", "carol"]).await;
+
+    alice.send(Message::text("hello")).await.unwrap();
+
+    assert_eq!(
+        expect_line(&mut alice, "Delivered").await,
+        r#"Delivered "hello" to 2 members."#
+    );
+}
+
+#[tokio::test]
+async fn members_who_left_are_not_counted_as_delivered() {
+    let app = spawn_app().await;
+    let (mut alice, [_bob, mut carol]) = app.group_of("alice", ["bob", "carol"]).await;
+    carol.close(None).await.unwrap();
+    expect_line(&mut alice, "carol is offline.").await;
+
+    alice.send(Message::text("hello")).await.unwrap();
+
+    assert_eq!(
+        expect_line(&mut alice, "Delivered").await,
+        r#"Delivered "hello" to 1 members."#
+    );
+}
+
+#[tokio::test]
+async fn recipients_tell_the_sender_they_read_a_line() {
+    let app = spawn_app().await;
+    let (mut alice, [mut bob, mut carol]) = app.group_of("alice", ["bob", "carol"]).await;
+
+    alice.send(Message::text("hello")).await.unwrap();
+    expect_line(&mut bob, "alice: hello").await;
+    expect_line(&mut carol, "alice: hello").await;
+
+    let mut read = vec![
+        expect_line(&mut alice, " read ").await,
+        expect_line(&mut alice, " read ").await,
+    ];
+    read.sort();
+    assert_eq!(read, vec![r#"bob read "hello"."#, r#"carol read "hello"."#]);
+}
+
+#[tokio::test]
+async fn only_the_sender_is_told_of_the_receipts() {
+    let app = spawn_app().await;
+    let (mut alice, [mut bob, mut carol]) = app.group_of("alice", ["bob", "carol"]).await;
+
+    alice.send(Message::text("hello")).await.unwrap();
+    expect_line(&mut carol, "alice: hello").await;
+    expect_line(&mut alice, "carol read").await;
+    carol.send(Message::text("hi")).await.unwrap();
+
+    // Bob sees the line of carol, not the receipt of carol before it
+    assert_eq!(untimed(expect_line(&mut bob, ":").await), "alice: hello");
+    assert_eq!(untimed(expect_line(&mut bob, ":").await), "carol: hi");
+}
@@ -0,0 +1,190 @@
+use futures::SinkExt;
+use openmls_group::rooms::Room;
+use tokio_tungstenite::tungstenite::Message;
+use web::utils::ResponseData;
+
+use crate::helpers::{expect_line, spawn_app, untimed, TestApp, WebSocket, ADMIN};
+
+/// Creates room `name` allowing `allowed`, as the admin.
+async fn create_room(app: &TestApp, admin: &str, name: &str, allowed: &[&str]) -> Room {
+    let response = app
+        .create_room(
+            admin,
+            &serde_json::json!({ "name": name, "allowed": allowed }),
+        )
+        .await;
+    let body: ResponseData<Room> = response.json().await.unwrap();
+
+    body.data
+}
+
+/// Connects `user` to the delivery service, with its key packages published.
+async fn connect(app: &TestApp, user: &str) -> WebSocket {
+    let mut ws = app.connected(user).await;
+    ws.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut ws, "Published key packages").await;
+
+    ws
+}
+
+#[tokio::test]
+async fn create_room_returns_the_room_for_admins() {
+    let app = spawn_app().await;
+    let admin = app.access_token(ADMIN).await;
+
+    let response = app
+        .create_room(
+            &admin,
+            &serde_json::json!({ "name": "lobby", "allowed": ["alice", "bob:0"] }),
+        )
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<Room> = response.json().await.unwrap();
+    assert_eq!(body.data.name, "lobby");
+    assert!(body.data.allows("alice:3"));
+    assert!(body.data.allows("bob:0"));
+    assert!(!body.data.allows("bob:1"));
+    assert!(!body.data.archived);
+}
+
+#[tokio::test]
+async fn admin_api_rejects_anonymous_and_non_admin_users() {
+    let app = spawn_app().await;
+    let alice = app.access_token("alice").await;
+    let test_cases = vec![("", 401, "no token"), (alice.as_str(), 403, "a user token")];
+
+    for (token, status, description) in test_cases {
+        let response = app
+            .create_room(token, &serde_json::json!({ "name": "lobby" }))
+            .await;
+
+        assert_eq!(
+            status,
+            response.status().as_u16(),
+            "The API did not fail with {} for {}.",
+            status,
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn create_room_returns_400_for_invalid_name_and_409_for_taken_name() {
+    let app = spawn_app().await;
+    let admin = app.access_token(ADMIN).await;
+    create_room(&app, &admin, "lobby", &[]).await;
+    let test_cases = vec![
+        ("", 400, "an empty name"),
+        ("the lobby", 400, "a name with whitespace"),
+        ("lobby", 409, "a taken name"),
+    ];
+
+    for (name, status, description) in test_cases {
+        let response = app
+            .create_room(&admin, &serde_json::json!({ "name": name }))
+            .await;
+
+        assert_eq!(
+            status,
+            response.status().as_u16(),
+            "The API did not fail with {} for {}.",
+            status,
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn rooms_are_listed_renamed_archived_and_deleted() {
+    let app = spawn_app().await;
+    let admin = app.access_token(ADMIN).await;
+    let lobby = create_room(&app, &admin, "lobby", &[]).await;
+    create_room(&app, &admin, "attic", &[]).await;
+
+    let response = app.list_rooms(&admin).await;
+    let body: ResponseData<Vec<Room>> = response.json().await.unwrap();
+    let names: Vec<&str> = body.data.iter().map(|room| room.name.as_str()).collect();
+    assert_eq!(names, ["attic", "lobby"]);
+
+    let response = app.rename_room(&admin, &lobby.id, "hall").await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(
+        409,
+        app.rename_room(&admin, &lobby.id, "attic").await.status()
+    );
+
+    let response = app.set_room_members(&admin, &lobby.id, &["carol"]).await;
+    let body: ResponseData<Room> = response.json().await.unwrap();
+    assert!(body.data.allows("carol:0"));
+
+    let response = app.archive_room(&admin, &lobby.id).await;
+    let body: ResponseData<Room> = response.json().await.unwrap();
+    assert_eq!(body.data.name, "hall");
+    assert!(body.data.archived);
+
+    assert_eq!(200, app.delete_room(&admin, &lobby.id).await.status());
+    assert_eq!(404, app.get_room(&admin, &lobby.id).await.status());
+    assert_eq!(404, app.delete_room(&admin, &lobby.id).await.status());
+}
+
+#[tokio::test]
+async fn room_group_takes_only_the_allowed_identities() {
+    let app = spawn_app().await;
+    let admin = app.access_token(ADMIN).await;
+    create_room(&app, &admin, "lobby", &["alice", "bob"]).await;
+    let mut alice = connect(&app, "alice").await;
+    let mut bob = connect(&app, "bob").await;
+    connect(&app, "mallory").await;
+
+    alice
+        .send(Message::text("c#cgr lobby mallory"))
+        .await
+        .unwrap();
+    expect_line(&mut alice, "Not allowed in the room: mallory.").await;
+    alice.send(Message::text("c#pad bob")).await.unwrap();
+    alice.send(Message::text("c#cmt")).await.unwrap();
+    expect_line(&mut bob, "Joined group").await;
+    alice.send(Message::text("hello bob")).await.unwrap();
+    assert_eq!(
+        untimed(expect_line(&mut bob, "hello").await),
+        "alice: hello bob"
+    );
+}
+
+#[tokio::test]
+async fn room_group_is_refused_to_users_not_allowed() {
+    let app = spawn_app().await;
+    let admin = app.access_token(ADMIN).await;
+    create_room(&app, &admin, "lobby", &["alice"]).await;
+    let mut mallory = connect(&app, "mallory").await;
+
+    mallory.send(Message::text("c#cgr lobby")).await.unwrap();
+    expect_line(&mut mallory, "You are not allowed in room lobby.").await;
+    mallory.send(Message::text("c#cgr attic")).await.unwrap();
+    expect_line(&mut mallory, "There is no room attic.").await;
+}
+
+#[tokio::test]
+async fn allowed_users_join_the_room_group_by_its_name_until_archived() {
+    let app = spawn_app().await;
+    let admin = app.access_token(ADMIN).await;
+    let lobby = create_room(&app, &admin, "lobby", &["alice", "bob"]).await;
+    let mut alice = connect(&app, "alice").await;
+    let mut bob = connect(&app, "bob").await;
+    let mut mallory = connect(&app, "mallory").await;
+    alice.send(Message::text("c#cgr lobby")).await.unwrap();
+    expect_line(&mut alice, "Created the group of room lobby.").await;
+
+    mallory.send(Message::text("c#jxc lobby")).await.unwrap();
+    expect_line(&mut mallory, "You are not allowed to join group lobby.").await;
+    bob.send(Message::text("c#jxc lobby")).await.unwrap();
+    expect_line(&mut alice, "bob joined the group.").await;
+
+    app.archive_room(&admin, &lobby.id).await;
+    let mut carol = connect(&app, "carol").await;
+    app.set_room_members(&admin, &lobby.id, &["alice", "bob", "carol"])
+        .await;
+    carol.send(Message::text("c#jxc lobby")).await.unwrap();
+    expect_line(&mut carol, "You are not allowed to join group lobby.").await;
+}
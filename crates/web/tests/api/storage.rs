@@ -0,0 +1,112 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chat_core::auth_service::{Credential, TrustAnchor};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use web::{
+    auth_service::{session_message, CredentialRecord, IssuanceReceipt},
+    utils::ResponseData,
+};
+
+use crate::helpers::{key_pair, registration, spawn_app, spawn_app_with, TestApp};
+
+fn user() -> serde_json::Value {
+    serde_json::json!({
+        "username": "alice",
+        "password": "correct horse",
+    })
+}
+
+#[tokio::test]
+async fn users_and_credentials_outlive_the_app() {
+    let app = spawn_app().await;
+    app.register_user(&user()).await;
+    let key_pair = app.registered("alice:0").await;
+    let token = app.access_token("alice").await;
+    app.revoke_identity(&token, "alice:0").await;
+
+    let restarted = spawn_app_with(app.configuration.clone()).await;
+
+    assert_eq!(200, restarted.login(&user()).await.status().as_u16());
+    assert_eq!(
+        409,
+        restarted.register_user(&user()).await.status().as_u16()
+    );
+    let response = restarted.get_identity("alice:0").await;
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<CredentialRecord> = response.json().await.unwrap();
+    assert_eq!(
+        body.data.signature_key,
+        STANDARD.encode(key_pair.public_key())
+    );
+    assert!(body.data.revoked);
+}
+
+async fn ca_certificate(app: &TestApp) -> String {
+    let body: ResponseData<String> = app.get_ca_certificate().await.json().await.unwrap();
+    body.data
+}
+
+/// Registers `identity` with an X.509 credential, returns the receipt.
+async fn registered_x509(
+    app: &TestApp,
+    identity: &str,
+    key_pair: &Ed25519KeyPair,
+) -> IssuanceReceipt {
+    let mut body = registration(identity, key_pair);
+    body["credential_type"] = "X509".into();
+    let token = app.access_token("alice").await;
+    let response = app.post_identities(&token, &body).await;
+    let body: ResponseData<IssuanceReceipt> = response.json().await.unwrap();
+    body.data
+}
+
+#[tokio::test]
+async fn the_ca_and_issuer_keys_outlive_the_app() {
+    let app = spawn_app().await;
+    let key_pair = key_pair();
+    let receipt = registered_x509(&app, "alice:0", &key_pair).await;
+    let certificate = ca_certificate(&app).await;
+
+    let restarted = spawn_app_with(app.configuration.clone()).await;
+
+    assert_eq!(ca_certificate(&restarted).await, certificate);
+    let anchor = TrustAnchor::from_der(STANDARD.decode(&certificate).unwrap()).unwrap();
+    let chain = receipt
+        .credential
+        .certificate_chain
+        .iter()
+        .map(|certificate| STANDARD.decode(certificate).unwrap())
+        .collect();
+    assert!(Credential::x509(chain, &anchor).is_ok());
+    // The chain still validates, the device opens a session
+    let signed_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let response = restarted
+        .open_session(&serde_json::json!({
+            "identity": "alice:0",
+            "signed_at": signed_at,
+            "proof": STANDARD.encode(key_pair.sign(&session_message("alice:0", signed_at))),
+        }))
+        .await;
+    assert_eq!(200, response.status().as_u16());
+    // The receipts are signed with the same key
+    let other = registered_x509(&restarted, "alice:1", &self::key_pair()).await;
+    assert_eq!(other.issuer_key, receipt.issuer_key);
+}
+
+#[tokio::test]
+async fn each_test_app_has_a_database_of_its_own() {
+    let app = spawn_app().await;
+    app.register_user(&user()).await;
+
+    let other = spawn_app().await;
+
+    assert_ne!(
+        app.configuration.database.database_name,
+        other.configuration.database.database_name
+    );
+    assert_eq!(401, other.login(&user()).await.status().as_u16());
+}
@@ -0,0 +1,68 @@
+use futures::SinkExt;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+use crate::helpers::{expect_line, spawn_app, TEST_SINK};
+
+/// A user no other test uses, its records are its own.
+fn unique_user() -> String {
+    format!("user{}", Uuid::new_v4().simple())
+}
+
+/// The records of the events `msg`, among `records`. Bunyan prefixes the
+/// message of an event with the name of its span.
+fn with_msg<'a>(records: &'a [serde_json::Value], msg: &str) -> Vec<&'a serde_json::Value> {
+    records
+        .iter()
+        .filter(|record| {
+            record["msg"]
+                .as_str()
+                .is_some_and(|logged| logged.ends_with(msg))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn sessions_are_traced_with_their_client_and_device() {
+    let app = spawn_app().await;
+    let alice = unique_user();
+
+    let (_ws, identity) = app.connected_device(&alice).await;
+
+    let records = TEST_SINK.records_with("identity", &identity);
+    let signed_in = with_msg(&records, "Signed in");
+    assert_eq!(signed_in.len(), 1);
+    assert!(signed_in[0]["client_id"].is_string());
+    assert!(signed_in[0]["ip"]
+        .as_str()
+        .unwrap()
+        .starts_with("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn group_messages_are_traced_in_their_group_and_delivery() {
+    let app = spawn_app().await;
+    let (alice, bob) = (unique_user(), unique_user());
+    let (mut alice_ws, identity) = app.connected_device(&alice).await;
+    let mut bob_ws = app.connected(&bob).await;
+
+    bob_ws.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut bob_ws, "Published key packages").await;
+    alice_ws
+        .send(Message::text(format!("c#cgw {}", bob)))
+        .await
+        .unwrap();
+    expect_line(&mut bob_ws, "Joined group").await;
+    alice_ws.send(Message::text("hello bob")).await.unwrap();
+    expect_line(&mut bob_ws, "hello bob").await;
+
+    let records = TEST_SINK.records_with("identity", &identity);
+    let created = with_msg(&records, "Created the group");
+    assert_eq!(created.len(), 1);
+    let group_id = created[0]["group_id"].as_str().unwrap();
+    let records = TEST_SINK.records_with("group_id", group_id);
+    let delivered = with_msg(&records, "Delivered the message");
+    assert!(delivered
+        .iter()
+        .any(|record| record["kind"] == "group_message" && record["recipients"] == 1));
+}
@@ -0,0 +1,195 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::SinkExt;
+use ring::signature::Ed25519KeyPair;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use web::{auth_service::session_message, authentication::SessionToken, utils::ResponseData};
+
+use crate::helpers::{expect_line, spawn_app, untimed, TestApp};
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// A session request of `identity` signed at `signed_at` with `key_pair`.
+fn session_request(identity: &str, signed_at: u64, key_pair: &Ed25519KeyPair) -> serde_json::Value {
+    serde_json::json!({
+        "identity": identity,
+        "signed_at": signed_at,
+        "proof": STANDARD.encode(key_pair.sign(&session_message(identity, signed_at))),
+    })
+}
+
+/// Registers `identity` and opens a session of it, returns the token.
+async fn signed_in(app: &TestApp, identity: &str) -> String {
+    let key_pair = app.registered(identity).await;
+    let response = app
+        .open_session(&session_request(identity, now(), &key_pair))
+        .await;
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+
+    body.data.token
+}
+
+#[tokio::test]
+async fn open_session_returns_a_token_for_a_signed_request() {
+    let app = spawn_app().await;
+    let key_pair = app.registered("alice:0").await;
+
+    let response = app
+        .open_session(&session_request("alice:0", now(), &key_pair))
+        .await;
+
+    assert_eq!(200, response.status().as_u16());
+    let body: ResponseData<SessionToken> = response.json().await.unwrap();
+    assert_eq!(body.data.identity, "alice:0");
+    assert!(!body.data.token.is_empty());
+    assert!(body.data.expires_at > now());
+}
+
+#[tokio::test]
+async fn open_session_returns_401_for_an_unauthenticated_request() {
+    let app = spawn_app().await;
+    let key_pair = app.registered("alice:0").await;
+    let test_cases = vec![
+        (
+            session_request("alice:0", now(), &crate::helpers::key_pair()),
+            "signed with another key",
+        ),
+        (
+            session_request("alice:0", now() - 60 * 60, &key_pair),
+            "signed an hour ago",
+        ),
+        (
+            session_request("bob:0", now(), &key_pair),
+            "of an unregistered identity",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.open_session(&body).await;
+
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not fail with 401 Unauthorized when the request was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn open_session_returns_403_for_revoked_identity() {
+    let app = spawn_app().await;
+    let key_pair = app.registered("alice:0").await;
+    let token = app.access_token("alice").await;
+    app.revoke_identity(&token, "alice:0").await;
+
+    let response = app
+        .open_session(&session_request("alice:0", now(), &key_pair))
+        .await;
+
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn websocket_is_refused_without_a_valid_session_token() {
+    let app = spawn_app().await;
+
+    let err = app.connect_websocket("not a token").await.unwrap_err();
+
+    let Error::Http(response) = err else {
+        panic!("The handshake did not fail with an HTTP error: {:?}", err);
+    };
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn websocket_is_refused_once_the_identity_is_revoked() {
+    let app = spawn_app().await;
+    let token = signed_in(&app, "alice:0").await;
+    app.revoke_identity(&token, "alice:0").await;
+
+    let err = app.connect_websocket(&token).await.unwrap_err();
+
+    let Error::Http(response) = err else {
+        panic!("The handshake did not fail with an HTTP error: {:?}", err);
+    };
+    assert_eq!(403, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn websocket_clients_chat_in_a_group() {
+    let app = spawn_app().await;
+    let mut alice = app
+        .connect_websocket(&signed_in(&app, "alice:0").await)
+        .await
+        .unwrap();
+    let mut bob = app
+        .connect_websocket(&signed_in(&app, "bob:0").await)
+        .await
+        .unwrap();
+    expect_line(&mut alice, "You are device alice:").await;
+    expect_line(&mut bob, "You are device bob:").await;
+
+    bob.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut bob, "Published key packages").await;
+    alice.send(Message::text("c#cgw bob")).await.unwrap();
+    expect_line(&mut alice, "Added bob:").await;
+    expect_line(&mut bob, "Joined group alice:").await;
+    alice.send(Message::text("hello bob")).await.unwrap();
+
+    assert_eq!(
+        untimed(expect_line(&mut bob, "hello").await),
+        "alice: hello bob"
+    );
+}
+
+#[tokio::test]
+async fn a_device_revoked_over_http_is_removed_from_its_group() {
+    let app = spawn_app().await;
+    let mut alice = app
+        .connect_websocket(&signed_in(&app, "alice:0").await)
+        .await
+        .unwrap();
+    let token = signed_in(&app, "bob:0").await;
+    let mut bob = app.connect_websocket(&token).await.unwrap();
+    expect_line(&mut alice, "You are device alice:").await;
+    let line = expect_line(&mut bob, "You are device bob:").await;
+    let device = line
+        .trim_start_matches("You are device ")
+        .trim_end_matches('.')
+        .to_string();
+    bob.send(Message::text("c#pkp")).await.unwrap();
+    expect_line(&mut bob, "Published key packages").await;
+    alice.send(Message::text("c#cgw bob")).await.unwrap();
+    expect_line(&mut alice, "Added bob:").await;
+    expect_line(&mut bob, "Joined group alice:").await;
+
+    let response = app.revoke_identity(&token, &device).await;
+
+    assert_eq!(200, response.status().as_u16());
+    expect_line(
+        &mut alice,
+        &format!("The credential of {} was revoked.", device),
+    )
+    .await;
+    expect_line(&mut alice, &format!("Removed {}.", device)).await;
+}
+
+#[tokio::test]
+async fn websocket_client_can_not_pick_another_user() {
+    let app = spawn_app().await;
+    let mut alice = app
+        .connect_websocket(&signed_in(&app, "alice:0").await)
+        .await
+        .unwrap();
+
+    alice.send(Message::text("c#usr mallory")).await.unwrap();
+
+    expect_line(&mut alice, "You signed in as alice").await;
+}